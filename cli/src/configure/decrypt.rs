@@ -5,21 +5,31 @@ pub(crate) fn decrypt(disks: &mut Disks, decrypt: Option<Values>) -> Result<(),
     eprintln!("distinst: decrypting luks partitions");
     if let Some(decrypt) = decrypt {
         for device in decrypt {
-            let values: Vec<&str> = device.split(':').collect();
-            if values.len() != 3 {
-                return Err(DistinstError::DecryptArgs);
-            }
-
-            let (device, pv) = (Path::new(values[0]), values[1].into());
-
-            let (mut pass, mut keydata) = (None, None);
-            parse_key(&values[2], &mut pass, &mut keydata)?;
-
+            let (device, enc) = parse_decrypt(device)?;
             disks
-                .decrypt_partition(device, &LvmEncryption::new(pv, pass, keydata))
+                .decrypt_partition(device, &enc)
                 .map_err(|why| DistinstError::DecryptFailed { why })?;
         }
     }
 
     Ok(())
 }
+
+/// The keys to reopen the LUKS partitions of an installation that is resumed with.
+pub(crate) fn resume_keys(decrypt: Option<Values>) -> Result<Vec<LvmEncryption>, DistinstError> {
+    decrypt.into_iter().flatten().map(|device| parse_decrypt(device).map(|(_, enc)| enc)).collect()
+}
+
+fn parse_decrypt(device: &str) -> Result<(&Path, LvmEncryption), DistinstError> {
+    let values: Vec<&str> = device.split(':').collect();
+    if values.len() != 3 {
+        return Err(DistinstError::DecryptArgs);
+    }
+
+    let (device, pv) = (Path::new(values[0]), values[1].into());
+
+    let (mut pass, mut keydata) = (None, None);
+    parse_key(&values[2], &mut pass, &mut keydata)?;
+
+    Ok((device, LvmEncryption::new(pv, pass, keydata)))
+}
@@ -97,6 +97,8 @@ pub(crate) fn lvm(
                     builder = builder.flags(flags.clone());
                 }
 
+                builder = builder.format_options(args.options.clone());
+
                 lvm_device
                     .add_partition(builder)
                     .map_err(|why| DistinstError::LvmPartitionAdd { why })
@@ -111,17 +113,19 @@ pub(crate) fn lvm(
 // Defines a new partition to assign to a volume group
 struct LogicalArgs {
     // The group to create a partition on
-    group: String,
+    group:   String,
     // The name of the partition
-    name:  String,
+    name:    String,
     // The length of the partition
-    size:  Sector,
+    size:    Sector,
     // The filesystem to assign to this partition
-    fs:    Option<FileSystem>,
+    fs:      Option<FileSystem>,
     // Where to mount this partition
-    mount: Option<PathBuf>,
+    mount:   Option<PathBuf>,
     // The partition flags to assign
-    flags: Option<Vec<PartitionFlag>>,
+    flags:   Option<Vec<PartitionFlag>>,
+    // The options to create the filesystem with
+    options: FormatOptions,
 }
 
 fn parse_logical<F: FnMut(LogicalArgs) -> Result<(), DistinstError>>(
@@ -130,11 +134,12 @@ fn parse_logical<F: FnMut(LogicalArgs) -> Result<(), DistinstError>>(
 ) -> Result<(), DistinstError> {
     for value in values {
         let values: Vec<&str> = value.split(':').collect();
-        if values.len() < 4 || values.len() > 6 {
+        if values.len() < 4 {
             return Err(DistinstError::LogicalArgs);
         }
 
         let (mut mount, mut flags) = (None, None);
+        let mut options = FormatOptions::default();
 
         for arg in values.iter().skip(4) {
             if arg.starts_with("mount=") {
@@ -151,7 +156,7 @@ fn parse_logical<F: FnMut(LogicalArgs) -> Result<(), DistinstError>>(
                 }
 
                 flags = Some(parse_flags(flagval));
-            } else {
+            } else if !parse_format_option(&mut options, arg)? {
                 return Err(DistinstError::InvalidField { field: (*arg).into() });
             }
         }
@@ -168,6 +173,7 @@ fn parse_logical<F: FnMut(LogicalArgs) -> Result<(), DistinstError>>(
             },
             mount,
             flags,
+            options,
         })?;
     }
 
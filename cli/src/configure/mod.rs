@@ -8,6 +8,8 @@ mod table;
 
 use self::{decrypt::*, lvm::*, moved::*, new::*, removed::*, reuse::*, table::*};
 
+pub(crate) use self::decrypt::resume_keys;
+
 use super::*;
 use errors::DistinstError;
 
@@ -19,7 +21,11 @@ pub(crate) fn configure_disks(matches: &ArgMatches) -> Result<Disks, DistinstErr
 
         for block in matches.values_of("disk").unwrap() {
             eprintln!("distinst: adding {} to disks configuration", block);
-            disks.add(Disk::from_name(block)?);
+            if Path::new(block).is_file() {
+                disks.add_image(block)?;
+            } else {
+                disks.add(Disk::from_name(block)?);
+            }
         }
 
         tables(disks, matches.values_of("table"))
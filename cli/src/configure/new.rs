@@ -7,7 +7,7 @@ pub(crate) fn new(disks: &mut Disks, parts: Option<Values>) -> Result<(), Distin
     if let Some(parts) = parts {
         for part in parts {
             let values: Vec<&str> = part.split(':').collect();
-            if values.len() < 5 || values.len() > 7 {
+            if values.len() < 5 {
                 return Err(DistinstError::NewArgs);
             }
 
@@ -20,6 +20,7 @@ pub(crate) fn new(disks: &mut Disks, parts: Option<Values>) -> Result<(), Distin
             );
 
             let (mut key, mut mount, mut flags) = (None, None, None);
+            let mut options = FormatOptions::default();
 
             for value in values.iter().skip(5) {
                 if value.starts_with("mount=") {
@@ -28,7 +29,7 @@ pub(crate) fn new(disks: &mut Disks, parts: Option<Values>) -> Result<(), Distin
                     flags = Some(parse_flags(&value[6..]));
                 } else if value.starts_with("keyid=") {
                     key = Some(String::from(&value[6..]));
-                } else {
+                } else if !parse_format_option(&mut options, value)? {
                     return Err(DistinstError::InvalidField { field: (*value).into() });
                 }
             }
@@ -46,6 +47,8 @@ pub(crate) fn new(disks: &mut Disks, parts: Option<Values>) -> Result<(), Distin
                 PartType::Fs(fs) => PartitionBuilder::new(start, end, fs).partition_type(kind),
             };
 
+            builder = builder.format_options(options);
+
             if let Some(flags) = flags {
                 builder = builder.flags(flags);
             }
@@ -6,7 +6,7 @@ pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), Dis
     if let Some(parts) = parts {
         for part in parts {
             let values: Vec<&str> = part.split(':').collect();
-            if values.len() < 3 || values.len() > 5 {
+            if values.len() < 3 {
                 return Err(DistinstError::ReusedArgs);
             }
 
@@ -23,6 +23,7 @@ pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), Dis
             );
 
             let (mut key, mut mount, mut flags) = (None, None, None);
+            let mut options = FormatOptions::default();
 
             for value in values.iter().skip(3) {
                 if value.starts_with("mount=") {
@@ -31,7 +32,7 @@ pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), Dis
                     flags = Some(parse_flags(&value[6..]));
                 } else if value.starts_with("keyid=") {
                     key = Some(String::from(&value[6..]));
-                } else {
+                } else if !parse_format_option(&mut options, value)? {
                     return Err(DistinstError::InvalidField { field: (*value).into() });
                 }
             }
@@ -53,18 +54,25 @@ pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), Dis
                 partition.set_mount(Path::new(mount).to_path_buf());
             }
 
-            if let Some(fs) = fs {
-                let fs = match fs {
-                    PartType::Fs(fs) => fs,
-                    PartType::Lvm(volume_group, encryption) => {
-                        partition.set_volume_group(volume_group, encryption);
-                        Some(FileSystem::Lvm)
-                    }
-                };
+            let fs = fs.and_then(|fs| match fs {
+                PartType::Fs(fs) => fs,
+                PartType::Lvm(volume_group, encryption) => {
+                    partition.set_volume_group(volume_group, encryption);
+                    Some(FileSystem::Lvm)
+                }
+            });
 
-                if let Some(fs) = fs {
-                    partition.format_with(fs);
+            match fs {
+                Some(fs) => {
+                    let path = partition.device_path.clone();
+                    partition
+                        .format_with_options(fs, options)
+                        .map_err(|why| DiskError::new_partition_error(path, why))?;
+                }
+                None if options != FormatOptions::default() => {
+                    return Err(DistinstError::UnformattedOptions);
                 }
+                None => (),
             }
 
             if let Some(flags) = flags {
@@ -1,4 +1,5 @@
 use super::*;
+use distinst::plan::PlanError;
 
 #[derive(Debug, Fail)]
 pub(crate) enum DistinstError {
@@ -56,12 +57,20 @@ pub(crate) enum DistinstError {
     NoMountPath,
     #[fail(display = "mount value is empty")]
     EmptyMount,
+    #[fail(display = "format options may only be given to partitions which are formatted")]
+    UnformattedOptions,
     #[fail(display = "unable to add partition to lvm device: {}", why)]
     LvmPartitionAdd { why: DiskError },
     #[fail(display = "unable to initialize volume groups: {}", why)]
     InitializeVolumes { why: DiskError },
+    #[fail(display = "install plan error: {}", why)]
+    Plan { why: PlanError },
 }
 
 impl From<DiskError> for DistinstError {
     fn from(why: DiskError) -> DistinstError { DistinstError::Disk { why } }
 }
+
+impl From<PlanError> for DistinstError {
+    fn from(why: PlanError) -> DistinstError { DistinstError::Plan { why } }
+}
@@ -11,9 +11,12 @@ mod errors;
 
 use clap::{App, Arg, ArgMatches, Values};
 use configure::*;
-use distinst::{timezones::Timezones, *};
+use distinst::{
+    plan::{ChangeReport, DisksPlan, InstallPlan},
+    timezones::Timezones,
+    *,
+};
 use errors::DistinstError;
-use os_release::OsRelease;
 
 use pbr::ProgressBar;
 
@@ -27,8 +30,8 @@ use std::{
 };
 
 fn main() {
-    let release = OsRelease::new_from(Path::new("/etc/os-release")).expect("error loading os_release");
-    let isNixOS = release.id == "nixos";
+    let distribution = Distribution::detect();
+    let isNixOS = distribution == Distribution::Nixos;
 
     let matches = App::new("distinst")
         .arg(
@@ -51,6 +54,43 @@ fn main() {
                 .requires("username")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("groups")
+                .long("groups")
+                .help("supplementary groups for the user, instead of the administrator groups")
+                .requires("username")
+                .value_delimiter(",")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shell")
+                .long("shell")
+                .help("the login shell of the user to create")
+                .requires("username")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("uid")
+                .long("uid")
+                .help("a fixed user ID for the user to create")
+                .requires("username")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gid")
+                .long("gid")
+                .help("a fixed group ID for the primary group of the user to create")
+                .requires("username")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ssh-key")
+                .long("ssh-key")
+                .help("an SSH public key which may log in as the user to create")
+                .requires("username")
+                .takes_value(true)
+                .multiple(true),
+        )
         .arg(
             Arg::with_name("timezone")
                 .long("tz")
@@ -59,21 +99,25 @@ fn main() {
                 .min_values(2)
                 .max_values(2),
         )
-        .arg(
-            Arg::with_name("squashfs")
+        .arg({
+            let arg = Arg::with_name("squashfs")
                 .short("s")
                 .long("squashfs")
                 .help("define the squashfs image which will be installed")
-                .takes_value(true)
-                .required(!isNixOS),
-        )
+                .takes_value(true);
+            if isNixOS {
+                arg
+            } else {
+                arg.required_unless("plan")
+            }
+        })
         .arg(
             Arg::with_name("hostname")
                 .short("h")
                 .long("hostname")
                 .help("define the hostname that the new system will have")
                 .takes_value(true)
-                .required(true),
+                .required_unless("plan"),
         )
         .arg(
             Arg::with_name("keyboard")
@@ -93,22 +137,29 @@ fn main() {
                 .takes_value(true)
                 .default_value("en_US.UTF-8"),
         )
-        .arg(
-            Arg::with_name("remove")
+        .arg({
+            let arg = Arg::with_name("remove")
                 .short("r")
                 .long("remove")
                 .help("defines the manifest file that contains the packages to remove post-install")
-                .takes_value(true)
-                .required(!isNixOS),
-        )
+                .takes_value(true);
+            if isNixOS {
+                arg
+            } else {
+                arg.required_unless("plan")
+            }
+        })
         .arg(
             Arg::with_name("disk")
                 .short("b")
                 .long("block")
-                .help("defines a disk that will be manipulated in the installation process")
+                .help(
+                    "defines a disk that will be manipulated in the installation process, which \
+                     may be a disk image file to attach to a loop device",
+                )
                 .takes_value(true)
                 .multiple(true)
-                .required(true),
+                .required_unless_one(&["plan", "resume"]),
         )
         .arg(
             Arg::with_name("table")
@@ -140,7 +191,7 @@ fn main() {
         .arg(
             Arg::with_name("test")
                 .long("test")
-                .help("simply test whether the provided arguments pass the partitioning stage"),
+                .help("print the changes that would be made to the disks, without applying them"),
         )
         .arg(
             Arg::with_name("hardware-support")
@@ -213,22 +264,127 @@ fn main() {
         .arg(
             Arg::with_name("decrypt")
                 .long("decrypt")
-                .help("decrypts an existing LUKS partition")
+                .help(
+                    "decrypts an existing LUKS partition, or reopens one when resuming an \
+                     installation",
+                )
                 .takes_value(true)
                 .multiple(true),
         )
+        .arg(
+            Arg::with_name("plan")
+                .long("plan")
+                .help("installs the disks, config, and user accounts described by a plan file")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "disk",
+                    "table",
+                    "new",
+                    "use",
+                    "delete",
+                    "move",
+                    "logical",
+                    "logical-modify",
+                    "logical-remove",
+                    "logical-remove-all",
+                    "decrypt",
+                    "hostname",
+                    "username",
+                ]),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("resumes a failed installation from its checkpoint, after partitioning")
+                .conflicts_with_all(&[
+                    "disk",
+                    "table",
+                    "new",
+                    "use",
+                    "delete",
+                    "move",
+                    "logical",
+                    "logical-modify",
+                    "logical-remove",
+                    "logical-remove-all",
+                    "export-plan",
+                    "test",
+                ]),
+        )
+        .arg(
+            Arg::with_name("hook")
+                .long("hook")
+                .help(
+                    "a script, or directory of scripts, to run within the target after it has \
+                     been installed",
+                )
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("boot-manager")
+                .long("boot-manager")
+                .help("the boot loader to install on EFI systems")
+                .takes_value(true)
+                .possible_values(&["grub", "systemd-boot"]),
+        )
+        .arg(
+            Arg::with_name("nixos-template")
+                .long("nixos-template")
+                .help("the conf-tool template that NixOS configurations are generated from")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nixos-seed")
+                .long("nixos-seed")
+                .help("a JSON file which the generated conf-tool configuration is based on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nixos-installer")
+                .long("nixos-installer")
+                .help("the command which builds NixOS into the target")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nixos-json")
+                .long("nixos-json")
+                .help("a JSON object to merge into the generated conf-tool configuration")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nixos-closure")
+                .long("nixos-closure")
+                .help("a prebuilt NixOS system in the live image's store to install")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nixos-flake")
+                .long("nixos-flake")
+                .help("generate a flake for the NixOS configuration, and install from it"),
+        )
+        .arg(
+            Arg::with_name("nixos-wipe-on-failure")
+                .long("nixos-wipe-on-failure")
+                .help("remove everything from the target if the NixOS install fails"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .help("install without the network, from what is available on the live image"),
+        )
+        .arg(
+            Arg::with_name("export-plan")
+                .long("export-plan")
+                .help("writes the configuration to a plan file, instead of installing")
+                .takes_value(true),
+        )
         .get_matches();
 
     if let Err(err) = distinst::log(|_level, _message| {}) {
         eprintln!("Failed to initialize logging: {}", err);
     }
 
-    let squashfs = if isNixOS { "" } else { matches.value_of("squashfs").expect("should have --squashfs") };
-    let remove = if isNixOS { "" } else { matches.value_of("remove").expect("should have --remove") };
-    let hostname = matches.value_of("hostname").unwrap();
-    let mut keyboard = matches.values_of("keyboard").unwrap();
-    let lang = matches.value_of("lang").unwrap();
-
     let tzs_;
     let timezone = match matches.values_of("timezone") {
         Some(mut tz) => {
@@ -249,22 +405,20 @@ fn main() {
         None => None,
     };
 
-    let user_account = matches.value_of("username").map(|username| {
-        let username = username.to_owned();
-        let realname = matches.value_of("realname").map(String::from);
-        let password = matches.value_of("password").map(String::from).or_else(|| {
-            if unsafe { libc::isatty(0) } == 0 {
-                let mut pass = String::new();
-                io::stdin().read_line(&mut pass).unwrap();
-                pass.pop();
-                Some(pass)
-            } else {
-                None
+    let (disks_plan, config, user_accounts) = match matches.value_of("plan") {
+        Some(path) => match InstallPlan::from_file(path) {
+            Ok(InstallPlan { disks, config, users }) => (Some(disks), config, users),
+            Err(why) => {
+                eprintln!("distinst: {}", why);
+                exit(1);
             }
-        });
-
-        UserAccountCreate { realname, username, password }
-    });
+        },
+        None => (
+            None,
+            config_from_args(&matches, distribution),
+            user_from_args(&matches).into_iter().collect(),
+        ),
+    };
 
     let pb_opt: Rc<RefCell<Option<ProgressBar<io::Stdout>>>> = Rc::new(RefCell::new(None));
 
@@ -303,6 +457,7 @@ fn main() {
                         Step::Extract => "Extracting filesystem ",
                         Step::Configure => "Configuring installation",
                         Step::Bootloader => "Installing bootloader ",
+                        Step::Hooks => "Running hooks ",
                     });
                     *pb_opt.borrow_mut() = Some(pb);
                 }
@@ -313,28 +468,82 @@ fn main() {
             });
         }
 
+        {
+            let pb_opt = pb_opt.clone();
+            installer.on_event(move |event| {
+                if let Progress::SubStep(ref task) = event.progress {
+                    if let Some(ref mut pb) = *pb_opt.borrow_mut() {
+                        pb.message(&[task, " "].concat());
+                    }
+                }
+            });
+        }
+
         if let Some(timezone) = timezone {
             installer.set_timezone_callback(move || timezone.clone());
         }
 
-        if let Some(user_account) = user_account {
-            installer.set_user_callback(move || user_account.clone());
-        }
+        let disks = if matches.is_present("resume") {
+            None
+        } else {
+            let disks = match disks_plan {
+                Some(plan) => plan.build().map_err(DistinstError::from),
+                None => configure_disks(&matches),
+            };
 
-        let disks = match configure_disks(&matches) {
-            Ok(disks) => disks,
-            Err(why) => {
-                eprintln!("distinst: {}", why);
-                exit(1);
+            let disks = match disks {
+                Ok(disks) => disks,
+                Err(why) => {
+                    eprintln!("distinst: {}", why);
+                    exit(1);
+                }
+            };
+
+            if let Some(path) = matches.value_of("export-plan") {
+                let plan = InstallPlan {
+                    disks: DisksPlan::from_disks(&disks),
+                    config,
+                    users: user_accounts,
+                };
+
+                match plan.write(path) {
+                    Ok(()) => {
+                        eprintln!("distinst: wrote install plan to {}", path);
+                        exit(0);
+                    }
+                    Err(why) => {
+                        eprintln!("distinst: {}", why);
+                        exit(1);
+                    }
+                }
             }
-        };
 
-        configure_signal_handling();
+            if matches.is_present("test") {
+                match ChangeReport::new(&disks) {
+                    Ok(ref report) if report.is_empty() => {
+                        println!("no changes will be made to the disks");
+                        exit(0);
+                    }
+                    Ok(report) => {
+                        print!("{}", report);
+                        exit(0);
+                    }
+                    Err(why) => {
+                        eprintln!("distinst: {}", why);
+                        exit(1);
+                    }
+                }
+            }
+
+            Some(disks)
+        };
 
-        if matches.is_present("test") {
-            PARTITIONING_TEST.store(true, Ordering::Relaxed);
+        if !user_accounts.is_empty() {
+            installer.set_users_callback(move || user_accounts.clone());
         }
 
+        configure_signal_handling();
+
         if matches.is_present("force-bios") {
             FORCE_BOOTLOADER.store(1, Ordering::Relaxed);
         } else if matches.is_present("force-efi") {
@@ -345,10 +554,6 @@ fn main() {
             NO_EFI_VARIABLES.store(true, Ordering::Relaxed);
         }
 
-        fn take_optional_string(argument: Option<&str>) -> Option<String> {
-            argument.map(String::from).and_then(|x| if x.is_empty() { None } else { Some(x) })
-        }
-
         // The lock is an `OwnedFd`, which on drop will close / unlock the inhibitor.
         let _inhibit_suspend = match distinst::dbus_interfaces::LoginManager::new() {
             Ok(manager) => match manager.connect().inhibit_suspend(
@@ -367,20 +572,16 @@ fn main() {
             }
         };
 
-        installer.install(
-            disks,
-            &Config {
-                flags:            install_flags(&matches),
-                hostname:         hostname.into(),
-                keyboard_layout:  keyboard.next().map(String::from).unwrap(),
-                keyboard_model:   take_optional_string(keyboard.next()),
-                keyboard_variant: take_optional_string(keyboard.next()),
-                old_root:         None,
-                lang:             lang.into(),
-                remove:           remove.into(),
-                squashfs:         squashfs.into(),
+        match disks {
+            Some(disks) => installer.install(disks, &config),
+            None => match resume_keys(matches.values_of("decrypt")) {
+                Ok(keys) => installer.resume(&config, &keys),
+                Err(why) => {
+                    eprintln!("distinst: {}", why);
+                    exit(1);
+                }
             },
-        )
+        }
     };
 
     if let Some(mut pb) = pb_opt.borrow_mut().take() {
@@ -401,6 +602,94 @@ fn main() {
     exit(status);
 }
 
+fn config_from_args(matches: &ArgMatches, distribution: Distribution) -> Config {
+    fn take_optional_string(argument: Option<&str>) -> Option<String> {
+        argument.map(String::from).and_then(|x| if x.is_empty() { None } else { Some(x) })
+    }
+
+    let is_nixos = distribution == Distribution::Nixos;
+    let squashfs =
+        if is_nixos { "" } else { matches.value_of("squashfs").expect("should have --squashfs") };
+    let remove =
+        if is_nixos { "" } else { matches.value_of("remove").expect("should have --remove") };
+    let mut keyboard = matches.values_of("keyboard").unwrap();
+
+    Config {
+        flags:            install_flags(matches),
+        hostname:         matches.value_of("hostname").unwrap().into(),
+        keyboard_layout:  keyboard.next().map(String::from).unwrap(),
+        keyboard_model:   take_optional_string(keyboard.next()),
+        keyboard_variant: take_optional_string(keyboard.next()),
+        old_root:         None,
+        lang:             matches.value_of("lang").unwrap().into(),
+        remove:           remove.into(),
+        squashfs:         squashfs.into(),
+        distribution:     Some(distribution),
+        hooks:            matches
+            .values_of("hook")
+            .map_or_else(Vec::new, |hooks| hooks.map(PathBuf::from).collect()),
+        boot_manager:     matches
+            .value_of("boot-manager")
+            .and_then(|manager| manager.parse().ok())
+            .unwrap_or_default(),
+        nixos:            nixos_from_args(matches),
+    }
+}
+
+fn nixos_from_args(matches: &ArgMatches) -> NixosConfig {
+    let defaults = NixosConfig::default();
+    NixosConfig {
+        template:   matches.value_of("nixos-template").map_or(defaults.template, String::from),
+        seed:       matches.value_of("nixos-seed").map_or(defaults.seed, PathBuf::from),
+        installer:  matches.value_of("nixos-installer").map_or(defaults.installer, String::from),
+        extra_json: matches.value_of("nixos-json").map(String::from),
+        closure:    matches.value_of("nixos-closure").map(PathBuf::from),
+        flake:      matches.is_present("nixos-flake"),
+        wipe:       matches.is_present("nixos-wipe-on-failure"),
+    }
+}
+
+fn user_from_args(matches: &ArgMatches) -> Option<UserAccountCreate> {
+    matches.value_of("username").map(|username| {
+        let username = username.to_owned();
+        let realname = matches.value_of("realname").map(String::from);
+        let password = matches.value_of("password").map(String::from).or_else(|| {
+            if unsafe { libc::isatty(0) } == 0 {
+                let mut pass = String::new();
+                io::stdin().read_line(&mut pass).unwrap();
+                pass.pop();
+                Some(pass)
+            } else {
+                None
+            }
+        });
+
+        let id = |name: &str| {
+            matches.value_of(name).map(|id| {
+                id.parse::<u32>().unwrap_or_else(|_| {
+                    eprintln!("distinst: --{} must be a numeric ID", name);
+                    exit(1);
+                })
+            })
+        };
+
+        UserAccountCreate {
+            realname,
+            username,
+            password,
+            groups: matches
+                .values_of("groups")
+                .map_or_else(Vec::new, |groups| groups.map(String::from).collect()),
+            shell: matches.value_of("shell").map(String::from),
+            uid: id("uid"),
+            gid: id("gid"),
+            ssh_keys: matches
+                .values_of("ssh-key")
+                .map_or_else(Vec::new, |keys| keys.map(String::from).collect()),
+        }
+    })
+}
+
 fn install_flags(matches: &ArgMatches) -> u8 {
     let mut flags = 0;
 
@@ -413,6 +702,8 @@ fn install_flags(matches: &ArgMatches) -> u8 {
         0
     };
 
+    flags += if matches.occurrences_of("offline") != 0 { distinst::OFFLINE } else { 0 };
+
     flags
 }
 
@@ -496,6 +787,32 @@ fn parse_fs(fs: &str) -> Result<PartType, DistinstError> {
     }
 }
 
+/// Applies a `label=`, `uuid=`, `features=`, `btrfs-data=`, `btrfs-metadata=`, or
+/// `reflink=` field to the options that a file system is created with. Returns false if
+/// the field is not a format option.
+fn parse_format_option(options: &mut FormatOptions, field: &str) -> Result<bool, DistinstError> {
+    let invalid = || DistinstError::InvalidField { field: field.into() };
+    let profile = |value: &str| value.parse::<BtrfsProfile>().map_err(|_| invalid());
+
+    if field.starts_with("label=") {
+        options.label = Some(field[6..].into());
+    } else if field.starts_with("uuid=") {
+        options.uuid = Some(field[5..].into());
+    } else if field.starts_with("features=") {
+        options.ext_features.extend(field[9..].split(',').map(String::from));
+    } else if field.starts_with("btrfs-data=") {
+        options.btrfs_data = Some(profile(&field[11..])?);
+    } else if field.starts_with("btrfs-metadata=") {
+        options.btrfs_metadata = Some(profile(&field[15..])?);
+    } else if field.starts_with("reflink=") {
+        options.xfs_reflink = Some(field[8..].parse::<bool>().map_err(|_| invalid())?);
+    } else {
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
 fn parse_sector(sector: &str) -> Result<Sector, DistinstError> {
     let result = if sector.ends_with("MiB") {
         sector[..sector.len() - 3].parse::<i64>().ok().and_then(|mebibytes| {
@@ -10,6 +10,9 @@ use std::{
 };
 
 /// Convenient wrapper around `process::Command` to make it easier to work with.
+///
+/// Commands which do not run within a chroot should use `distinst-external-commands`
+/// instead, so that they are executed by its replaceable runner.
 pub struct Command<'a> {
     cmd:   process::Command,
     stdin: Option<&'a str>,
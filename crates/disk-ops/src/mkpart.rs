@@ -1,4 +1,5 @@
 use disk_types::{BlockDeviceExt, FileSystem, PartitionExt, PartitionType};
+use external::FormatOptions;
 use libparted::{
     Device, FileSystemType as PedFileSystem, Geometry, Partition as PedPartition, PartitionFlag,
     PartitionType as PedPartitionType,
@@ -29,6 +30,8 @@ pub struct PartitionCreate {
     pub flags:        Vec<PartitionFlag>,
     /// Defines the label to apply
     pub label:        Option<String>,
+    /// The options that the file system is created with, if it is formatted.
+    pub fs_options:   FormatOptions,
 }
 
 impl BlockDeviceExt for PartitionCreate {
@@ -2,7 +2,7 @@
 
 use super::*;
 use disk_types::{FileSystem, PartitionTable, PartitionType};
-use external::{blockdev, mkfs};
+use external::{blockdev, mkfs_with, FormatOptions};
 use libparted::{Device, Disk as PedDisk, Partition as PedPartition};
 use mkpart::PartitionCreate;
 use parted::*;
@@ -186,6 +186,7 @@ impl<'a> ChangePartitions<'a> {
                             kind,
                             flags,
                             label,
+                            fs_options: FormatOptions::default(),
                         },
                     )?;
 
@@ -209,7 +210,7 @@ impl<'a> ChangePartitions<'a> {
 pub struct CreatePartitions<'a> {
     device_path:       &'a Path,
     create_partitions: Vec<PartitionCreate>,
-    format_partitions: Vec<(PathBuf, FileSystem)>,
+    format_partitions: Vec<(PathBuf, FileSystem, FormatOptions)>,
 }
 
 impl<'a> CreatePartitions<'a> {
@@ -234,6 +235,7 @@ impl<'a> CreatePartitions<'a> {
                     partition
                         .file_system
                         .expect("file system does not exist when creating partition"),
+                    partition.fs_options.clone(),
                 ));
             }
         }
@@ -284,7 +286,7 @@ pub fn get_partition_id_and_path(path: &Path, start_sector: i64) -> io::Result<(
 
 /// The final stage of disk operations, where all partitions to be formatted can be
 /// formatted in parallel.
-pub struct FormatPartitions(pub Vec<(PathBuf, FileSystem)>);
+pub struct FormatPartitions(pub Vec<(PathBuf, FileSystem, FormatOptions)>);
 
 impl FormatPartitions {
     /// Finally, format all of the modified and created partitions.
@@ -292,9 +294,9 @@ impl FormatPartitions {
         info!("executing format operations");
         self.0
             .par_iter()
-            .map(|&(ref part, fs)| {
+            .map(|&(ref part, fs, ref options)| {
                 info!("formatting {} with {:?}", part.display(), fs);
-                mkfs(part, fs).map_err(|why| {
+                mkfs_with(part, fs, options).map_err(|why| {
                     io::Error::new(
                         why.kind(),
                         format!("failed to format {} with {}: {}", part.display(), fs, why),
@@ -1,12 +1,11 @@
 use self::FileSystem::*;
 use super::{move_partition, BlockCoordinates, OffsetCoordinates, MEBIBYTE, MEGABYTE};
 use disk_types::{FileSystem, PartitionType};
-use external::{blockdev, fsck};
+use external::{blockdev, fsck, pvmove_tail, Invocation};
 use libparted::PartitionFlag;
 use std::{
-    io::{self, Write},
+    io,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
 };
 use sys_mount::*;
 use tempdir::TempDir;
@@ -21,6 +20,8 @@ pub const BTRFS: u8 = 0b100;
 pub const XFS: u8 = 0b1000;
 /// This is a NTFS partition.
 pub const NTFS: u8 = 0b10000;
+/// This is a LVM physical volume.
+pub const LVM: u8 = 0b100000;
 
 /// Defines the unit of measurement to pass on to resizing tools.
 ///
@@ -108,10 +109,7 @@ pub fn resize_partition<P: AsRef<Path>>(
 ) -> io::Result<()> {
     info!("resizing {} to {}", path.as_ref().display(), size);
 
-    let mut resize_cmd = Command::new(cmd);
-    if !args.is_empty() {
-        resize_cmd.args(args);
-    }
+    let resize_cmd = Invocation::new(cmd).args(args);
 
     // Attempt to sync three times before returning an error.
     for attempt in 0..3 {
@@ -132,7 +130,10 @@ pub fn resize_partition<P: AsRef<Path>>(
         None
     };
 
-    fsck(path.as_ref(), fsck_options).and_then(|_| {
+    // Physical volumes have no file system to check.
+    let checked = if options & LVM != 0 { Ok(()) } else { fsck(path.as_ref(), fsck_options) };
+
+    checked.and_then(|_| {
         // Btrfs is a strange case that needs resize operations to be performed while
         // it is mounted.
         let (npath, _mount) = if options & (BTRFS | XFS) != 0 {
@@ -145,34 +146,23 @@ pub fn resize_partition<P: AsRef<Path>>(
             (path.as_ref().to_path_buf(), None)
         };
 
-        if options & NO_SIZE != 0 {
-            resize_cmd.arg(&npath);
+        let resize_cmd = if options & NO_SIZE != 0 {
+            resize_cmd.arg(&npath)
         } else if options & SIZE_BEFORE_PATH != 0 {
-            resize_cmd.arg(size).arg(&npath);
+            resize_cmd.arg(size).arg(&npath)
         } else {
-            resize_cmd.arg(&npath).arg(size);
+            resize_cmd.arg(&npath).arg(size)
         };
 
-        let status = if options & NTFS != 0 {
+        let resize_cmd = if options & NTFS != 0 {
             ntfs_dry_run(&npath, size)?;
-
-            info!("executing {:?}", resize_cmd);
-            let mut child = resize_cmd.stdin(Stdio::piped()).spawn()?;
-            child.stdin.as_mut().expect("failed to get stdin").write_all(b"y\n")?;
-            child.wait()?
+            resize_cmd.stdin("y\n")
         } else {
-            info!("executing {:?}", resize_cmd);
-            resize_cmd.status()?
+            resize_cmd
         };
 
-        if status.success() {
-            Ok(())
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("resize for {:?} failed with status: {}", path.as_ref().display(), status),
-            ))
-        }
+        info!("executing {}", resize_cmd);
+        resize_cmd.run_checked().map(|_| ())
     })
 }
 
@@ -261,6 +251,12 @@ where
             ResizeUnit::AbsoluteBytes,
             SIZE_BEFORE_PATH | NTFS,
         ),
+        Some(Lvm) => (
+            "pvresize",
+            &["-y", "--setphysicalvolumesize"],
+            ResizeUnit::AbsoluteSectorsWithUnit,
+            SIZE_BEFORE_PATH | LVM,
+        ),
         Some(Swap) => unreachable!("Disk::diff() handles this"),
         Some(Xfs) => {
             if shrinking {
@@ -299,6 +295,17 @@ where
     // In addition, the partition in the partition table must be deleted before
     // moving, and recreated with the new size before attempting to grow.
     if shrinking {
+        // Extents at the end of a physical volume must be moved before it can be shrunk.
+        if change.filesystem == Some(Lvm) {
+            info!("relocating extents from the end of {}", change.path.display());
+            pvmove_tail(&change.path, resize.absolute_sectors()).map_err(|why| {
+                io::Error::new(
+                    why.kind(),
+                    format!("failed to relocate extents of {}: {}", change.path.display(), why),
+                )
+            })?;
+        }
+
         info!("shrinking {}", change.path.display());
         resize_partition(cmd, args, &size, &change.path, fs, opts).map_err(|why| {
             io::Error::new(
@@ -387,33 +394,20 @@ where
 }
 
 fn ntfs_dry_run(path: &Path, size: &str) -> io::Result<()> {
-    let mut consistency_check = Command::new("ntfsresize");
-    consistency_check.args(&["-f", "-f", "--no-action", "-s"]).arg(size).arg(path);
-
-    info!("executing {:?}", consistency_check);
-    let mut child = consistency_check.stdin(Stdio::piped()).spawn()?;
-    child.stdin.as_mut().expect("failed to get stdin").write_all(b"y\n")?;
-
-    let status = child.wait()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(io::Error::new(io::ErrorKind::Other, format!("ntfsresize exited with {:?}", status)))
-    }
+    let consistency_check = Invocation::new("ntfsresize")
+        .args(&["-f", "-f", "--no-action", "-s"])
+        .arg(size)
+        .arg(path)
+        .stdin("y\n");
+
+    info!("executing {}", consistency_check);
+    consistency_check.run_checked().map(|_| ())
 }
 
 fn ntfs_consistency_check(path: &Path) -> io::Result<()> {
-    let mut consistency_check = Command::new("ntfsresize");
-    consistency_check.args(&["-i", "-f"]).arg(path);
-
-    info!("executing {:?}", consistency_check);
-    let mut child = consistency_check.stdin(Stdio::piped()).spawn()?;
-    child.stdin.as_mut().expect("failed to get stdin").write_all(b"y\n")?;
+    let consistency_check =
+        Invocation::new("ntfsresize").args(&["-i", "-f"]).arg(path).stdin("y\n");
 
-    let status = child.wait()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(io::Error::new(io::ErrorKind::Other, format!("ntfsresize exited with {:?}", status)))
-    }
+    info!("executing {}", consistency_check);
+    consistency_check.run_checked().map(|_| ())
 }
@@ -3,11 +3,12 @@ use super::{
         serial::get_serial, BlockDeviceExt, DiskError, DiskExt, Disks, FileSystem, PartitionError,
         PartitionFlag, PartitionInfo, PartitionTable, PartitionType,
     },
+    lvm::ensure_relocatable,
     partitions::{FORMAT, REMOVE, SOURCE, SWAPPED},
     PVS,
 };
 use disk_types::{PartitionExt, PartitionTableExt, SectorExt};
-use crate::external::{is_encrypted, pvs};
+use crate::external::{is_encrypted, pv_extents, pvs};
 use libparted::{Device, DeviceType, Disk as PedDisk};
 use operations::{
     parted::{get_device, open_disk},
@@ -24,6 +25,20 @@ use std::{
 };
 use sys_mount::{unmount, UnmountFlags};
 
+/// Checks that the physical volume on a partition, if any, may be shrunk to `sectors`, which
+/// requires that its volume group has enough free extents to take those that are removed.
+fn check_pv_shrink(partition: &PartitionInfo, sectors: u64) -> Result<(), DiskError> {
+    let vg = match (partition.filesystem, partition.original_vg.as_ref()) {
+        (Some(FileSystem::Lvm), Some(vg)) => vg,
+        _ => return Ok(()),
+    };
+
+    let extents = pv_extents(&partition.device_path)
+        .map_err(|why| DiskError::VolumeGroupExtents { vg: vg.clone(), why })?;
+    let removed = extents.pe_count.saturating_sub(extents.extents_within(sectors));
+    ensure_relocatable(vg, removed, extents.vg_free)
+}
+
 /// Detects a partition on the device, if it exists.
 /// Useful for detecting if a LUKS device has a file system.
 pub fn detect_fs_on_device(path: &Path) -> Option<PartitionInfo> {
@@ -468,6 +483,18 @@ impl Disk {
             return Err(DiskError::SectorOverlaps { id });
         }
 
+        // The extents that are removed from a physical volume must be relocated elsewhere.
+        if end < backup {
+            let partition = self
+                .get_partition_mut(partition)
+                .expect("unable to find partition that should exist");
+
+            if let Err(why) = check_pv_shrink(partition, end - start) {
+                partition.end_sector = backup;
+                return Err(why);
+            }
+        }
+
         Ok(end)
     }
 
@@ -722,6 +749,7 @@ impl Disk {
                                         kind:         new.part_type,
                                         flags:        new.flags.clone(),
                                         label:        new.name.clone(),
+                                        fs_options:   new.fs_options.clone(),
                                     });
                                 } else {
                                     change_partitions.push(PartitionChange {
@@ -769,6 +797,7 @@ impl Disk {
                 kind:         partition.part_type,
                 flags:        partition.flags.clone(),
                 label:        partition.name.clone(),
+                fs_options:   partition.fs_options.clone(),
             });
         }
 
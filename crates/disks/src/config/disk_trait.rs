@@ -165,6 +165,12 @@ pub trait DiskExt: BlockDeviceExt + SectorExt + PartitionTableExt {
             })?;
         }
 
+        if let Some(fs) = partition.filesystem {
+            partition.fs_options.validate(fs).map_err(|why| {
+                DiskError::new_partition_error(partition.device_path.clone(), why)
+            })?;
+        }
+
         self.push_partition(partition);
 
         Ok(())
@@ -5,7 +5,7 @@ use super::{
     },
     detect_fs_on_device, find_partition, find_partition_mut,
     partitions::{FORMAT, REMOVE, SOURCE},
-    Disk, LvmEncryption, PartitionTable, PVS,
+    Diagnostic, DiagnosticCode, Disk, DiskImage, LvmEncryption, PartitionTable, PVS,
 };
 use disk_types::{BlockDeviceExt, PartitionExt, PartitionTableExt, SectorExt};
 use crate::external::{
@@ -30,17 +30,53 @@ use std::{
 };
 use sys_mount::{swapoff, unmount, Mount, MountFlags, Mounts, Unmount, UnmountFlags};
 
+/// The existing volume group that the partition is a physical volume of, if it is kept as one.
+fn source_group(partition: &PartitionInfo) -> Option<&str> {
+    match (partition.filesystem, partition.volume_group.as_ref()) {
+        (Some(FileSystem::Lvm), None) => partition.original_vg.as_ref().map(String::as_str),
+        _ => None,
+    }
+}
+
 /// A configuration of disks, both physical and logical.
 #[derive(Debug, Default, PartialEq)]
 pub struct Disks {
     pub physical: Vec<Disk>,
     pub logical:  Vec<LogicalDevice>,
+    /// Disk images which have been attached to loop devices, to be installed to.
+    pub images:   Vec<DiskImage>,
 }
 
 impl Disks {
     /// Adds a disk to the disks configuration.
     pub fn add(&mut self, disk: Disk) { self.physical.push(disk); }
 
+    /// Attaches a disk image file to a loop device, and adds that device to the disks
+    /// configuration. The disk may afterwards be found by the path of its image.
+    pub fn add_image<P: AsRef<Path>>(&mut self, path: P) -> Result<(), DiskError> {
+        let path = path.as_ref();
+        let image = DiskImage::attach(path)
+            .map_err(|why| DiskError::ImageAttach { image: path.to_path_buf(), why })?;
+        let disk = Disk::from_name(&image.device)?;
+        self.images.push(image);
+        self.add(disk);
+        Ok(())
+    }
+
+    /// Takes the attached disk images, so that they may be detached once the disks have
+    /// been released.
+    pub fn take_images(&mut self) -> Vec<DiskImage> {
+        std::mem::replace(&mut self.images, Vec::new())
+    }
+
+    /// Resolves the path of an attached disk image to its loop device.
+    fn resolve_image(&self, path: &Path) -> PathBuf {
+        self.images
+            .iter()
+            .find(|image| image.matches(path))
+            .map_or_else(|| path.to_path_buf(), |image| image.device.clone())
+    }
+
     /// Remove disks that aren't relevant to the install.
     pub fn remove_untouched_disks(&mut self) {
         let mut remove = Vec::with_capacity(self.physical.len() - 1);
@@ -68,11 +104,13 @@ impl Disks {
     }
 
     pub fn get_physical_device<P: AsRef<Path>>(&self, path: P) -> Option<&Disk> {
-        self.physical.iter().find(|d| d.get_device_path() == path.as_ref())
+        let path = self.resolve_image(path.as_ref());
+        self.physical.iter().find(|d| d.get_device_path() == path)
     }
 
     pub fn get_physical_device_mut<P: AsRef<Path>>(&mut self, path: P) -> Option<&mut Disk> {
-        self.physical.iter_mut().find(|d| d.get_device_path() == path.as_ref())
+        let path = self.resolve_image(path.as_ref());
+        self.physical.iter_mut().find(|d| d.get_device_path() == path)
     }
 
     /// Returns a slice of physical disks stored within the configuration.
@@ -677,13 +715,15 @@ impl Disks {
     /// Returns an immutable reference to the disk specified by its path, if it
     /// exists.
     pub fn find_disk<P: AsRef<Path>>(&self, path: P) -> Option<&Disk> {
-        self.physical.iter().find(|disk| disk.device_path == path.as_ref())
+        let path = self.resolve_image(path.as_ref());
+        self.physical.iter().find(|disk| disk.device_path == path)
     }
 
     /// Returns a mutable reference to the disk specified by its path, if it
     /// exists.
     pub fn find_disk_mut<P: AsRef<Path>>(&mut self, path: P) -> Option<&mut Disk> {
-        self.physical.iter_mut().find(|disk| disk.device_path == path.as_ref())
+        let path = self.resolve_image(path.as_ref());
+        self.physical.iter_mut().find(|disk| disk.device_path == path)
     }
 
     /// Finds the partition block path and associated partition information that is associated with
@@ -704,6 +744,46 @@ impl Disks {
         }
     }
 
+    /// Designates that a partition of the disk at `path` should be resized, as with
+    /// `Disk::resize_partition`. If the partition is a physical volume of a volume group that
+    /// has been loaded, the extents which are removed from it are also removed from the group.
+    pub fn resize_partition<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        partition: i32,
+        end: u64,
+    ) -> Result<u64, DiskError> {
+        let path = path.as_ref();
+        let disk =
+            self.find_disk_mut(path).ok_or_else(|| DiskError::DiskGet { device: path.into() })?;
+
+        let (backup, group) = match disk.get_partition(partition) {
+            Some(info) => (info.end_sector, source_group(info).map(String::from)),
+            None => return Err(DiskError::PartitionNotFound { partition }),
+        };
+
+        let end = disk.resize_partition(partition, end)?;
+        let shrunk = match (group, end < backup) {
+            (Some(group), true) => match self.get_logical_device_mut(&group) {
+                Some(device) => device.shrink_pv(backup - end),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        };
+
+        if let Err(why) = shrunk {
+            if let Some(info) =
+                self.find_disk_mut(path).and_then(|disk| disk.get_partition_mut(partition))
+            {
+                info.end_sector = backup;
+            }
+
+            return Err(why);
+        }
+
+        Ok(end)
+    }
+
     /// Returns a list of disk & partition paths that match a volume group.
     pub fn find_volume_paths<'a>(&'a self, volume_group: &str) -> Vec<(&'a Path, &'a Path)> {
         let mut volumes = Vec::new();
@@ -889,117 +969,198 @@ impl Disks {
         self.get_logical_devices().iter().any(|d| d.get_device_path() == device)
     }
 
-    /// Validates that partitions are configured correctly.
+    /// Validates that partitions are configured correctly, failing on the first error that
+    /// is found by `Disks::validate`.
+    pub fn verify_partitions(&self, bootloader: Bootloader) -> io::Result<()> {
+        match self.validate(bootloader).into_iter().find(Diagnostic::is_error) {
+            Some(diagnostic) => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, diagnostic.message))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Collects every problem with the partition configuration.
     ///
     /// - EFI installs must contain a `/boot/efi` partition as Fat16 / Fat32
     /// - MBR installs on logical devices must have a `/boot` partition
     /// - Boot partitions must not be on a logical volume
     /// - EFI boot partitions must have the ESP flag set
-    pub fn verify_partitions(&self, bootloader: Bootloader) -> io::Result<()> {
-        let (root_device, root) = self.find_partition(Path::new("/")).ok_or_else(|| {
-            io::Error::new(io::ErrorKind::InvalidInput, "root partition was not defined")
-        })?;
+    pub fn validate(&self, bootloader: Bootloader) -> Vec<Diagnostic> {
+        self.validate_with_esp(bootloader, Path::new("/boot/efi"))
+    }
 
+    /// Collects every problem with the partition configuration, as `Disks::validate` does, for
+    /// systems which mount the EFI partition at `esp`, rather than at `/boot/efi`.
+    pub fn validate_with_esp(&self, bootloader: Bootloader, esp: &Path) -> Vec<Diagnostic> {
+        use DiagnosticCode::*;
         use FileSystem::*;
-        match root.filesystem {
-            Some(Fat16) | Some(Fat32) | Some(Ntfs) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "root partition has invalid file system",
-                ));
+
+        let mut diagnostics = Vec::new();
+
+        let root_device = match self.find_partition(Path::new("/")) {
+            Some((root_device, root)) => {
+                let problem = match root.filesystem {
+                    Some(Fat16) | Some(Fat32) | Some(Ntfs) => {
+                        Some((RootInvalidFileSystem, "root partition has invalid file system"))
+                    }
+                    Some(_) => None,
+                    None => Some((RootNoFileSystem, "root partition does not have a file system")),
+                };
+
+                if let Some((code, message)) = problem {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            code,
+                            message,
+                            "format the root partition with a Linux file system, such as ext4",
+                        )
+                        .device(root_device)
+                        .partition("/"),
+                    );
+                } else if !root.will_format() {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            RootNotFormatted,
+                            "root partition will not be formatted, so its existing files will \
+                             remain",
+                            "format the root partition, unless its files are meant to be kept",
+                        )
+                        .device(root_device)
+                        .partition("/"),
+                    );
+                }
+
+                Some(root_device)
             }
-            Some(_) => (),
             None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "root partition does not have a file system",
+                diagnostics.push(Diagnostic::error(
+                    RootMissing,
+                    "root partition was not defined",
+                    "assign a partition to be mounted at /",
                 ));
+                None
             }
-        }
+        };
 
         let boot_partition = if bootloader == Bootloader::Efi {
-            Some(("/boot/efi", "EFI", true))
-        } else if self.device_is_logical(root_device) {
-            Some(("/boot", "boot", false))
+            Some((esp, "EFI", true))
+        } else if root_device.map_or(false, |device| self.device_is_logical(device)) {
+            Some((Path::new("/boot"), "boot", false))
         } else {
             None
         };
 
-        if let Some((partition, kind, is_efi)) = boot_partition {
-            let device = {
-                let (device, boot) =
-                    self.find_partition(Path::new(partition)).ok_or_else(|| {
-                        io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            format!("{} partition was not defined", kind),
-                        )
-                    })?;
-
-                let device = match self.find_disk(device) {
-                    Some(device) => device,
-                    None => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "Unable to find the disk that the boot partition exists on",
-                        ))
-                    }
-                };
+        let (target, kind, is_efi) = match boot_partition {
+            Some(boot_partition) => boot_partition,
+            None => return diagnostics,
+        };
 
-                if is_efi {
-                    // Check if the EFI partition is on a GPT disk.
-                    if device.get_partition_table() != Some(PartitionTable::Gpt) {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "EFI installs cannot be done on disks without a GPT partition layout.",
-                        ));
-                    }
+        let (device, boot) = match self.find_partition(target) {
+            Some(partition) => partition,
+            None => {
+                diagnostics.push(Diagnostic::error(
+                    BootMissing,
+                    format!("{} partition was not defined", kind),
+                    format!("assign a partition to be mounted at {}", target.display()),
+                ));
+                return diagnostics;
+            }
+        };
 
-                    if !boot.flags.contains(&PartitionFlag::PED_PARTITION_ESP) {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            format!("{} partition did not have ESP flag set", kind),
-                        ));
-                    }
+        // A boot partition on a logical device is not on any of the disks.
+        if self.device_is_logical(device) {
+            diagnostics.push(
+                Diagnostic::error(
+                    BootOnLogicalDevice,
+                    format!("{} partition cannot be on logical device", kind),
+                    format!("place the {} partition on a physical disk", kind),
+                )
+                .device(device)
+                .partition(target),
+            );
+            return diagnostics;
+        }
 
-                    match boot.filesystem {
-                        Some(Fat16) | Some(Fat32) => (),
-                        Some(_) => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidInput,
-                                format!("{} partition has invalid file system", kind),
-                            ));
-                        }
-                        None => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidInput,
-                                format!("{} partition does not have a file system", kind),
-                            ));
-                        }
-                    }
+        let disk = match self.find_disk(device) {
+            Some(disk) => disk,
+            None => {
+                diagnostics.push(
+                    Diagnostic::error(
+                        BootDiskNotFound,
+                        "Unable to find the disk that the boot partition exists on",
+                        "add the disk containing the boot partition to the configuration",
+                    )
+                    .device(device)
+                    .partition(target),
+                );
+                return diagnostics;
+            }
+        };
 
-                    // 256 MiB should be the minimal size of the ESP partition.
-                    const REQUIRED_SECTORS: u64 = 524_288;
+        if !is_efi {
+            return diagnostics;
+        }
 
-                    if boot.get_sectors() < REQUIRED_SECTORS {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "the ESP partition must be at least 256 MiB in size",
-                        ));
-                    }
-                }
+        // Check if the EFI partition is on a GPT disk.
+        if disk.get_partition_table() != Some(PartitionTable::Gpt) {
+            diagnostics.push(
+                Diagnostic::error(
+                    EfiRequiresGpt,
+                    "EFI installs cannot be done on disks without a GPT partition layout.",
+                    "create a GPT partition table on the disk containing the EFI partition",
+                )
+                .device(device)
+                .partition(target),
+            );
+        }
 
-                device
-            };
+        if !boot.flags.contains(&PartitionFlag::PED_PARTITION_ESP) {
+            diagnostics.push(
+                Diagnostic::error(
+                    EspFlagMissing,
+                    format!("{} partition did not have ESP flag set", kind),
+                    "set the ESP flag on the EFI partition",
+                )
+                .device(device)
+                .partition(target),
+            );
+        }
 
-            if device.is_logical() {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("{} partition cannot be on logical device", kind),
-                ));
-            }
+        let problem = match boot.filesystem {
+            Some(Fat16) | Some(Fat32) => None,
+            Some(_) => Some((BootInvalidFileSystem, "has invalid file system")),
+            None => Some((BootNoFileSystem, "does not have a file system")),
+        };
+
+        if let Some((code, problem)) = problem {
+            diagnostics.push(
+                Diagnostic::error(
+                    code,
+                    format!("{} partition {}", kind, problem),
+                    "format the EFI partition as FAT32",
+                )
+                .device(device)
+                .partition(target),
+            );
         }
 
-        Ok(())
+        // 256 MiB should be the minimal size of the ESP partition.
+        const REQUIRED_SECTORS: u64 = 524_288;
+
+        if boot.get_sectors() < REQUIRED_SECTORS {
+            diagnostics.push(
+                Diagnostic::error(
+                    EspTooSmall,
+                    "the ESP partition must be at least 256 MiB in size",
+                    "resize or recreate the EFI partition to be at least 256 MiB",
+                )
+                .device(device)
+                .partition(target),
+            );
+        }
+
+        diagnostics
     }
 
     /// Loads existing logical volume data into memory, excluding encrypted volumes.
@@ -1059,6 +1220,23 @@ impl Disks {
             }
 
             device.add_partitions();
+            if let Err(why) = device.load_extents() {
+                warn!("{}", why);
+                continue;
+            }
+
+            // Physical volumes whose partitions were shrunk take their extents from the group.
+            let physical_volumes = self
+                .get_physical_partitions()
+                .filter(|p| source_group(p) == Some(device.volume_group.as_str()));
+
+            for partition in physical_volumes {
+                match device.shrink_to_partition(partition) {
+                    Err(why @ DiskError::VolumeGroupShrink { .. }) => return Err(why),
+                    Err(why) => warn!("{}", why),
+                    Ok(()) => (),
+                }
+            }
         }
 
         self.logical = existing_devices;
@@ -1171,7 +1349,7 @@ impl IntoIterator for Disks {
 impl FromIterator<Disk> for Disks {
     fn from_iter<I: IntoIterator<Item = Disk>>(iter: I) -> Self {
         // TODO: Also collect LVM Devices
-        Disks { physical: iter.into_iter().collect(), logical: Vec::new() }
+        Disks { physical: iter.into_iter().collect(), logical: Vec::new(), images: Vec::new() }
     }
 }
 
@@ -0,0 +1,59 @@
+use crate::external::{losetup_attach, losetup_detach};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A disk image file which has been attached to a loop device, so that it may be
+/// partitioned and installed to as if it were a physical disk.
+///
+/// The loop device is detached when the image is dropped, if it was not detached before.
+#[derive(Debug, PartialEq)]
+pub struct DiskImage {
+    /// The canonical path of the image file.
+    pub image:  PathBuf,
+    /// The loop device which the image is attached to.
+    pub device: PathBuf,
+    attached:   bool,
+}
+
+impl DiskImage {
+    /// Attaches the image file at the given path to a loop device.
+    pub fn attach<P: AsRef<Path>>(image: P) -> io::Result<DiskImage> {
+        let image = image.as_ref().canonicalize()?;
+        if !image.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "disk image is not a file"));
+        }
+
+        let device = losetup_attach(&image)?;
+        info!("attached {:?} to {:?}", image, device);
+        Ok(DiskImage { image, device, attached: true })
+    }
+
+    /// Whether the given path refers to this image, or to its loop device.
+    pub fn matches(&self, path: &Path) -> bool {
+        path == self.device || path.canonicalize().ok().map_or(false, |path| path == self.image)
+    }
+
+    /// Detaches the loop device. Every partition on the device should have been
+    /// unmounted, and any device maps on it closed, beforehand.
+    pub fn detach(mut self) -> io::Result<()> { self.detach_device() }
+
+    fn detach_device(&mut self) -> io::Result<()> {
+        if self.attached {
+            info!("detaching {:?} from {:?}", self.image, self.device);
+            losetup_detach(&self.device)?;
+            self.attached = false;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for DiskImage {
+    fn drop(&mut self) {
+        if let Err(why) = self.detach_device() {
+            error!("failed to detach {:?}: {}", self.device, why);
+        }
+    }
+}
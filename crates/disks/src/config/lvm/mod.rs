@@ -3,20 +3,24 @@ mod encryption;
 pub use self::encryption::LvmEncryption;
 use super::{
     super::{
-        DiskError, DiskExt, PartitionError, PartitionInfo, PartitionTable, PartitionType, FORMAT,
-        REMOVE, SOURCE,
+        DiskError, DiskExt, FileSystem, FormatOptions, PartitionBuilder, PartitionError,
+        PartitionInfo, PartitionTable, PartitionType, FORMAT, REMOVE, SOURCE,
     },
     get_size,
 };
 use disk_types::{BlockDeviceExt, PartitionExt, PartitionTableExt, SectorExt};
 pub use crate::external::deactivate_devices;
-use crate::external::{blkid_partition, lvcreate, lvremove, lvs, mkfs, vgactivate, vgcreate};
+use crate::external::{
+    blkid_partition, lvcreate, lvcreate_thin, lvcreate_thin_pool, lvremove, lvresize, lvs,
+    mkfs_with, pv_extents, vg_extents, vgactivate, vgcreate,
+};
 use partition_identity::PartitionIdentifiers;
 use proc_mounts::MOUNTS;
 use std::{
     ffi::OsStr,
+    io,
     path::{Path, PathBuf},
-    thread,
+    ptr, thread,
     time::Duration,
 };
 
@@ -32,6 +36,50 @@ pub struct VgData {
     free_pe:  u64,
 }
 
+impl VgData {
+    /// Obtains the extents of the volume group, `vg`.
+    fn new(vg: &str) -> Result<VgData, DiskError> {
+        let (pe_size, total_pe, free_pe) =
+            vg_extents(vg).map_err(|why| DiskError::VolumeGroupExtents { vg: vg.into(), why })?;
+
+        Ok(VgData { pe_size, total_pe, alloc_pe: total_pe.saturating_sub(free_pe), free_pe })
+    }
+}
+
+/// Ensures that `extents` may be removed from the volume group, `vg`, which requires that
+/// there are as many free extents for the allocated extents among them to be moved to.
+pub(crate) fn ensure_relocatable(vg: &str, extents: u64, free: u64) -> Result<(), DiskError> {
+    if extents > free {
+        return Err(DiskError::VolumeGroupShrink { vg: vg.into(), extents, free });
+    }
+
+    Ok(())
+}
+
+/// What becomes of the space that remains in a volume group once its volumes are created.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RemainingSpace {
+    /// The last volume that is created is given all of the remaining space.
+    FillLast,
+    /// The remaining space is left free, so that volumes may be grown into it later.
+    Unallocated,
+}
+
+impl Default for RemainingSpace {
+    fn default() -> Self { RemainingSpace::FillLast }
+}
+
+/// A thin pool that is to be created on a volume group, from which thin volumes are
+/// provisioned as they are written to.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ThinPool {
+    pub name:    String,
+    /// The space which the pool takes from the volume group, in sectors.
+    pub sectors: u64,
+    /// The names of the thin volumes which are provisioned from this pool.
+    pub volumes: Vec<String>,
+}
+
 /// An LVM device acts similar to a Disk, but consists of one more block devices
 /// that comprise a volume group, and may optionally be encrypted.
 #[derive(Debug, Default, Clone, PartialEq)]
@@ -49,6 +97,8 @@ pub struct LogicalDevice {
     pub is_source:    bool,
     pub remove:       bool,
     pub vg_data:      VgData,
+    pub thin_pools:   Vec<ThinPool>,
+    pub remaining:    RemainingSpace,
 }
 
 impl BlockDeviceExt for LogicalDevice {
@@ -91,6 +141,19 @@ impl DiskExt for LogicalDevice {
     fn get_partitions(&self) -> &[PartitionInfo] { &self.partitions }
 
     fn push_partition(&mut self, partition: PartitionInfo) { self.partitions.push(partition); }
+
+    /// Thin volumes take space from their pools as they are written to, rather than from the
+    /// volume group, so the space of their pools is counted in their place.
+    fn get_used(&self) -> u64 {
+        let volumes = self
+            .get_partitions()
+            .iter()
+            .filter(|p| !p.flag_is_enabled(REMOVE) && self.get_thin_pool_of(p).is_none())
+            .map(|p| p.get_sectors())
+            .sum::<u64>();
+
+        volumes + self.thin_pools.iter().map(|pool| pool.sectors).sum::<u64>()
+    }
 }
 
 impl LogicalDevice {
@@ -151,9 +214,161 @@ impl LogicalDevice {
 
     pub fn get_pe_size_in_sectors(&self) -> u64 { self.vg_data.pe_size }
 
-    pub fn shrink_vg(&mut self, _pes: u64) -> Result<(), DiskError> { Ok(()) }
+    /// Reads the extents of the volume group from the system.
+    pub fn load_extents(&mut self) -> Result<(), DiskError> {
+        self.vg_data = VgData::new(&self.volume_group)?;
+        Ok(())
+    }
+
+    /// Removes `pes` extents from the volume group, as one of its physical volumes is shrunk.
+    ///
+    /// Allocated extents are relocated by `pvmove` when the change is applied, so the volume
+    /// group must have at least as many free extents as are being removed.
+    pub fn shrink_vg(&mut self, pes: u64) -> Result<(), DiskError> {
+        ensure_relocatable(&self.volume_group, pes, self.vg_data.free_pe)?;
+        self.vg_data.total_pe -= pes;
+        self.vg_data.free_pe -= pes;
+        self.sectors = self.sectors.saturating_sub(pes * self.vg_data.pe_size);
+        Ok(())
+    }
+
+    /// Shrinks one of the physical volumes of the group by `sectors`, which removes every
+    /// extent that they overlap.
+    pub fn shrink_pv(&mut self, sectors: u64) -> Result<(), DiskError> {
+        if self.vg_data.pe_size == 0 {
+            self.load_extents()?;
+        }
+
+        let pe_size = self.vg_data.pe_size.max(1);
+        self.shrink_vg((sectors + pe_size - 1) / pe_size)
+    }
+
+    /// Shrinks the physical volume on `partition` to fit within the partition, which may have
+    /// been shrunk before the volume group was loaded.
+    pub fn shrink_to_partition(&mut self, partition: &PartitionInfo) -> Result<(), DiskError> {
+        let extents = pv_extents(&partition.device_path)
+            .map_err(|why| DiskError::VolumeGroupExtents { vg: self.volume_group.clone(), why })?;
+
+        let removed =
+            extents.pe_count.saturating_sub(extents.extents_within(partition.get_sectors()));
+        if removed == 0 {
+            return Ok(());
+        }
 
-    pub fn shrink_pv(&mut self, _sectors: u64) -> Result<(), DiskError> { Ok(()) }
+        self.shrink_pv(removed * extents.extent_size)
+    }
+
+    /// Sets what becomes of the space that remains once the volumes are created.
+    pub fn set_remaining_space(&mut self, remaining: RemainingSpace) { self.remaining = remaining; }
+
+    /// Resizes the volume, `volume`, to the given number of sectors.
+    ///
+    /// Volumes which already exist are resized with `lvresize --resizefs` when the layout is
+    /// committed, so their file system must be one which it can resize in that direction,
+    /// unless the volume is to be formatted.
+    pub fn resize_volume(&mut self, volume: &str, sectors: u64) -> Result<(), DiskError> {
+        let group = self.volume_group.clone();
+        let thin = self.get_partition(volume).map_or(false, |p| self.get_thin_pool_of(p).is_some());
+        let available = self.sectors.saturating_sub(self.get_used());
+
+        let partition = self
+            .get_partition_mut(volume)
+            .filter(|p| !p.flag_is_enabled(REMOVE))
+            .ok_or_else(|| DiskError::LogicalPartitionNotFound {
+                group:  group.clone(),
+                volume: volume.into(),
+            })?;
+
+        let current = partition.get_sectors();
+        if !thin && sectors > current && sectors - current > available {
+            return Err(DiskError::PartitionOOB);
+        }
+
+        if partition.flag_is_enabled(SOURCE) && !partition.flag_is_enabled(FORMAT) {
+            resizable(partition.filesystem, sectors < current).map_err(|why| {
+                DiskError::LogicalVolumeResize { group, volume: volume.into(), why }
+            })?;
+        }
+
+        partition.end_sector = partition.start_sector + sectors;
+        Ok(())
+    }
+
+    /// Adds a thin pool to the volume group, which takes `sectors` from the group.
+    pub fn add_thin_pool(&mut self, name: &str, sectors: u64) -> Result<(), DiskError> {
+        if self.get_used() + sectors > self.sectors {
+            return Err(DiskError::PartitionOOB);
+        }
+
+        self.thin_pools.push(ThinPool { name: name.into(), sectors, volumes: Vec::new() });
+        Ok(())
+    }
+
+    /// Removes a thin pool which was to be created, along with the volumes provisioned from it.
+    pub fn remove_thin_pool(&mut self, name: &str) -> Result<(), DiskError> {
+        let pool = match self.thin_pools.iter().position(|pool| pool.name == name) {
+            Some(id) => self.thin_pools.remove(id),
+            None => {
+                return Err(DiskError::ThinPoolNotFound {
+                    group: self.volume_group.clone(),
+                    pool:  name.into(),
+                })
+            }
+        };
+
+        let volumes = pool.volumes;
+        self.partitions.retain(|p| p.name.as_ref().map_or(true, |name| !volumes.contains(name)));
+        Ok(())
+    }
+
+    /// Adds a thin volume which is provisioned from the thin pool, `pool`. The size of the
+    /// volume is virtual, so it may exceed the size of the pool.
+    pub fn add_thin_volume(
+        &mut self,
+        pool: &str,
+        builder: PartitionBuilder,
+    ) -> Result<(), DiskError> {
+        let partition = builder.build();
+        let name = partition.name.clone().ok_or_else(|| DiskError::VolumePartitionLacksLabel {
+            device: self.get_device_path().to_path_buf(),
+        })?;
+
+        if let Some(fs) = partition.filesystem {
+            let path = &partition.device_path;
+            fs.validate_size(partition.get_sectors() * self.sector_size)
+                .map_err(|why| DiskError::new_partition_error(path.clone(), why))?;
+            partition
+                .fs_options
+                .validate(fs)
+                .map_err(|why| DiskError::new_partition_error(path.clone(), why))?;
+        }
+
+        let group = &self.volume_group;
+        let thin_pool = self.thin_pools.iter_mut().find(|p| p.name == pool).ok_or_else(|| {
+            DiskError::ThinPoolNotFound { group: group.clone(), pool: pool.into() }
+        })?;
+
+        thin_pool.volumes.push(name);
+        self.partitions.push(partition);
+        Ok(())
+    }
+
+    /// Obtains the thin pool that a volume is provisioned from, if it is a thin volume.
+    pub fn get_thin_pool_of(&self, partition: &PartitionInfo) -> Option<&ThinPool> {
+        let name = partition.name.as_ref()?;
+        self.thin_pools.iter().find(|pool| pool.volumes.contains(name))
+    }
+
+    /// The volumes which exist on the system, but which are to be resized, along with the
+    /// number of sectors that each of them currently has.
+    pub fn get_resized_volumes(&self) -> Vec<(&PartitionInfo, u64)> {
+        self.partitions
+            .iter()
+            .filter(|p| p.flag_is_enabled(SOURCE) && !p.flag_is_enabled(REMOVE))
+            .filter_map(|p| get_size(&p.device_path).ok().map(|sectors| (p, sectors)))
+            .filter(|&(p, sectors)| p.get_sectors() != sectors)
+            .collect()
+    }
 
     pub fn get_last_sector(&self) -> u64 {
         self.get_partitions()
@@ -229,6 +444,7 @@ impl LogicalDevice {
                     volume_group: None,
                     key_id: None,
                     identifiers,
+                    fs_options: FormatOptions::default(),
                 };
 
                 start_sector += length + 1;
@@ -243,6 +459,8 @@ impl LogicalDevice {
         for partition in &mut self.partitions {
             partition.remove();
         }
+
+        self.thin_pools.clear();
     }
 
     pub fn remove_partition(&mut self, volume: &str) -> Result<(), DiskError> {
@@ -264,57 +482,268 @@ impl LogicalDevice {
     }
 
     /// Create & modify all logical volumes on the volume group, and format them.
+    ///
+    /// Space is reclaimed by removing and shrinking volumes before any is allocated, and the
+    /// volume which takes the remaining space is created after every other volume.
     pub fn modify_partitions(&self) -> Result<(), DiskError> {
-        let nparts = if self.partitions.is_empty() {
-            if self.file_system.is_some() {
-                0
-            } else {
-                return Ok(());
+        let group = self.volume_group.as_str();
+        let partitions = self.file_system.iter().chain(self.partitions.iter()).collect::<Vec<_>>();
+        let resized = self.get_resized_volumes();
+        let fill = self.get_fill_volume();
+
+        for partition in partitions.iter().copied() {
+            if partition.flag_is_enabled(SOURCE) && partition.flag_is_enabled(REMOVE) {
+                lvremove(group, volume_name(partition))
+                    .map_err(|why| DiskError::PartitionRemove { partition: -1, why })?;
             }
-        } else {
-            self.partitions.len() - 1
-        };
+        }
 
-        let partitions = self
-            .file_system
-            .as_ref()
-            .into_iter()
-            .map(|part| (0, part))
-            .chain(self.partitions.iter().enumerate());
-
-        for (id, partition) in partitions {
-            let label =
-                partition.name.as_ref().expect("logical partitions should have names").as_str();
-
-            // Don't create a partition if it already exists.
-            if !partition.flag_is_enabled(SOURCE) {
-                lvcreate(
-                    &self.volume_group,
-                    label,
-                    if id == nparts {
-                        None
-                    } else {
-                        Some(partition.get_sectors() * self.sector_size)
-                    },
-                )
+        for &(partition, sectors) in &resized {
+            if partition.get_sectors() < sectors {
+                self.resize_on_system(partition)?;
+            }
+        }
+
+        for pool in &self.thin_pools {
+            lvcreate_thin_pool(group, &pool.name, Some(pool.sectors * self.sector_size))
                 .map_err(|why| DiskError::LogicalVolumeCreate { why })?;
+        }
+
+        let created = partitions
+            .iter()
+            .copied()
+            .filter(|p| !p.flag_is_enabled(SOURCE) && !p.flag_is_enabled(REMOVE))
+            .collect::<Vec<_>>();
+
+        for partition in created.iter().copied() {
+            let is_fill = fill.map_or(false, |fill| ptr::eq(fill, partition));
+            if !is_fill && self.get_thin_pool_of(partition).is_none() {
+                let size = partition.get_sectors() * self.sector_size;
+                lvcreate(group, volume_name(partition), Some(size))
+                    .map_err(|why| DiskError::LogicalVolumeCreate { why })?;
             }
+        }
 
-            if partition.flag_is_enabled(REMOVE) {
-                lvremove(&self.volume_group, label)
-                    .map_err(|why| DiskError::PartitionRemove { partition: -1, why })?;
-            } else if partition.flag_is_enabled(FORMAT) {
-                if let Some(fs) = partition.filesystem {
-                    mkfs(&partition.device_path, fs).map_err(|why| {
-                        DiskError::new_partition_error(
-                            partition.device_path.clone(),
-                            PartitionError::PartitionFormat { why },
-                        )
-                    })?;
-                }
+        for &(partition, sectors) in &resized {
+            if partition.get_sectors() > sectors {
+                self.resize_on_system(partition)?;
+            }
+        }
+
+        if let Some(partition) = fill {
+            lvcreate(group, volume_name(partition), None)
+                .map_err(|why| DiskError::LogicalVolumeCreate { why })?;
+        }
+
+        for partition in created.iter().copied() {
+            if let Some(pool) = self.get_thin_pool_of(partition) {
+                let size = partition.get_sectors() * self.sector_size;
+                lvcreate_thin(group, &pool.name, volume_name(partition), size)
+                    .map_err(|why| DiskError::LogicalVolumeCreate { why })?;
+            }
+        }
+
+        for partition in partitions.iter().copied() {
+            if partition.flag_is_enabled(REMOVE) || !partition.flag_is_enabled(FORMAT) {
+                continue;
+            }
+
+            if let Some(fs) = partition.filesystem {
+                let options = &partition.fs_options;
+                mkfs_with(&partition.device_path, fs, options).map_err(|why| {
+                    DiskError::new_partition_error(
+                        partition.device_path.clone(),
+                        PartitionError::PartitionFormat { why },
+                    )
+                })?;
             }
         }
 
         Ok(())
     }
+
+    /// Resizes an existing volume to its configured size, along with its file system, unless
+    /// the volume is to be formatted.
+    fn resize_on_system(&self, partition: &PartitionInfo) -> Result<(), DiskError> {
+        let volume = volume_name(partition);
+        let resize_fs = partition.filesystem.is_some() && !partition.flag_is_enabled(FORMAT);
+        let size = partition.get_sectors() * self.sector_size;
+
+        lvresize(&self.volume_group, volume, size, resize_fs).map_err(|why| {
+            DiskError::LogicalVolumeResize {
+                group: self.volume_group.clone(),
+                volume: volume.into(),
+                why,
+            }
+        })
+    }
+
+    /// The volume which is to be created with the space that remains in the volume group.
+    pub fn get_fill_volume(&self) -> Option<&PartitionInfo> {
+        if self.remaining != RemainingSpace::FillLast {
+            return None;
+        }
+
+        self.file_system
+            .iter()
+            .chain(self.partitions.iter())
+            .filter(|p| !p.flag_is_enabled(SOURCE) && !p.flag_is_enabled(REMOVE))
+            .filter(|p| self.get_thin_pool_of(p).is_none())
+            .last()
+    }
+}
+
+fn volume_name(partition: &PartitionInfo) -> &str {
+    partition.name.as_ref().expect("logical partitions should have names").as_str()
+}
+
+/// Checks that `lvresize --resizefs` is able to shrink or grow a file system.
+fn resizable(fs: Option<FileSystem>, shrinking: bool) -> io::Result<()> {
+    match fs {
+        None | Some(FileSystem::Ext2) | Some(FileSystem::Ext3) | Some(FileSystem::Ext4) => Ok(()),
+        Some(FileSystem::Xfs) if !shrinking => Ok(()),
+        Some(fs) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} file systems cannot be {}",
+                <&str>::from(fs),
+                if shrinking { "shrunk" } else { "grown" }
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        external::{with_runner, CommandOutput, ScriptedRunner},
+        Disk, Disks,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn shrink_volume_group() {
+        let mut device = LogicalDevice {
+            volume_group: "data".into(),
+            sectors: 100 * 8192,
+            vg_data: VgData { pe_size: 8192, total_pe: 100, alloc_pe: 70, free_pe: 30 },
+            ..LogicalDevice::default()
+        };
+
+        device.shrink_pv(8192 * 20 + 1).unwrap();
+        assert_eq!(device.get_pe_free(), 9);
+        assert_eq!(device.sectors, 79 * 8192);
+
+        match device.shrink_vg(10) {
+            Err(DiskError::VolumeGroupShrink { extents: 10, free: 9, .. }) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        assert_eq!(device.get_pe_free(), 9);
+    }
+
+    #[test]
+    fn thin_volumes() {
+        const MIB: u64 = 2048;
+        let volume = |name: &str, sectors: u64| {
+            PartitionBuilder::new(0, sectors, FileSystem::Ext4).name(name.into())
+        };
+
+        let mut device = LogicalDevice {
+            volume_group: "data".into(),
+            sectors: 1000 * MIB,
+            sector_size: 512,
+            ..LogicalDevice::default()
+        };
+
+        device.add_partition(volume("root", 400 * MIB)).unwrap();
+        device.add_thin_pool("pool", 500 * MIB).unwrap();
+        device.add_thin_volume("pool", volume("home", 2000 * MIB)).unwrap();
+        assert!(device.add_thin_volume("missing", volume("srv", MIB)).is_err());
+        assert!(device.add_thin_pool("more", 200 * MIB).is_err());
+        assert_eq!(device.get_used(), 900 * MIB - 1);
+
+        assert!(device.resize_volume("root", 600 * MIB).is_err());
+        device.resize_volume("root", 450 * MIB).unwrap();
+        device.resize_volume("home", 4000 * MIB).unwrap();
+        assert_eq!(device.get_used(), 950 * MIB);
+
+        let fill = device.get_fill_volume().and_then(|p| p.name.clone());
+        assert_eq!(fill, Some("root".into()));
+        device.set_remaining_space(RemainingSpace::Unallocated);
+        assert_eq!(device.get_fill_volume(), None);
+
+        device.remove_thin_pool("pool").unwrap();
+        assert!(device.get_partition("home").is_none());
+        assert_eq!(device.get_used(), 450 * MIB);
+    }
+
+    #[test]
+    fn shrink_physical_volume() {
+        let start = 2048;
+        let mut disks = Disks {
+            physical: vec![Disk {
+                mklabel:     false,
+                model_name:  "Test Disk".into(),
+                serial:      "Test Disk 123".into(),
+                device_path: "/dev/sdz".into(),
+                file_system: None,
+                mount_point: None,
+                size:        1953525168,
+                device_type: "TEST".into(),
+                table_type:  Some(PartitionTable::Gpt),
+                read_only:   false,
+                partitions:  vec![PartitionInfo {
+                    bitflags:     SOURCE,
+                    device_path:  "/dev/sdz1".into(),
+                    flags:        vec![],
+                    mount_point:  None,
+                    target:       None,
+                    start_sector: start,
+                    end_sector:   start + 100 * 8192,
+                    filesystem:   Some(FileSystem::Lvm),
+                    name:         None,
+                    number:       1,
+                    ordering:     1,
+                    part_type:    PartitionType::Primary,
+                    key_id:       None,
+                    original_vg:  Some("data".into()),
+                    volume_group: None,
+                    identifiers:  PartitionIdentifiers::default(),
+                    fs_options:   FormatOptions::default(),
+                }],
+            }],
+            logical:  vec![LogicalDevice {
+                volume_group: "data".into(),
+                sectors: 100 * 8192,
+                vg_data: VgData { pe_size: 8192, total_pe: 100, alloc_pe: 70, free_pe: 30 },
+                ..LogicalDevice::default()
+            }],
+            images:   Vec::new(),
+        };
+
+        // The system is asked whether each shrink of the partition may be relocated.
+        let runner = Arc::new(ScriptedRunner::new());
+        let stdout = |stdout: &str| CommandOutput::stdout(stdout.as_bytes().to_vec());
+        for vg_free in &[30, 60] {
+            runner
+                .respond("pvs", stdout(&format!("  2048:100:8192:{}\n", vg_free)))
+                .respond("pvs", stdout("  0:70:root\n  70:30:\n"));
+        }
+
+        with_runner(runner, || {
+            disks.resize_partition("/dev/sdz", 1, start + 80 * 8192).unwrap();
+            assert_eq!(disks.get_logical_device("data").unwrap().get_pe_free(), 10);
+
+            // The group has fewer free extents than are removed by the second shrink.
+            match disks.resize_partition("/dev/sdz", 1, start + 60 * 8192) {
+                Err(DiskError::VolumeGroupShrink { extents: 20, free: 10, .. }) => (),
+                result => panic!("unexpected result: {:?}", result),
+            }
+        });
+
+        let partition = disks.find_disk("/dev/sdz").unwrap().get_partition(1).unwrap();
+        assert_eq!(partition.end_sector, start + 80 * 8192);
+    }
 }
@@ -4,15 +4,19 @@
 mod disk;
 mod disk_trait;
 mod disks;
+mod image;
 mod lvm;
 mod partitions;
+mod validate;
 
 pub use self::{
     disk::*,
     disk_trait::{find_partition, find_partition_mut, DiskExt},
     disks::*,
+    image::DiskImage,
     lvm::*,
     partitions::*,
+    validate::{Diagnostic, DiagnosticCode, Severity},
 };
 pub use disk_types::{PartitionTable, Sector};
 
@@ -43,6 +47,7 @@ pub fn get_size(path: &Path) -> io::Result<u64> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Bootloader, PartitionFlag};
     use operations::*;
     use partition_identity::PartitionIdentifiers;
 
@@ -77,6 +82,7 @@ mod tests {
                         original_vg:  None,
                         volume_group: None,
                         identifiers:  PartitionIdentifiers::default(),
+                        fs_options:   FormatOptions::default(),
                     },
                     PartitionInfo {
                         bitflags:     ACTIVE | BUSY | SOURCE,
@@ -95,6 +101,7 @@ mod tests {
                         original_vg:  None,
                         volume_group: None,
                         identifiers:  PartitionIdentifiers::default(),
+                        fs_options:   FormatOptions::default(),
                     },
                     PartitionInfo {
                         bitflags:     SOURCE,
@@ -113,6 +120,7 @@ mod tests {
                         original_vg:  None,
                         volume_group: None,
                         identifiers:  PartitionIdentifiers::default(),
+                        fs_options:   FormatOptions::default(),
                     },
                     PartitionInfo {
                         bitflags:     ACTIVE | SOURCE,
@@ -131,10 +139,12 @@ mod tests {
                         original_vg:  None,
                         volume_group: None,
                         identifiers:  PartitionIdentifiers::default(),
+                        fs_options:   FormatOptions::default(),
                     },
                 ],
             }],
             logical:  Vec::new(),
+            images:   Vec::new(),
         }
     }
 
@@ -154,6 +164,7 @@ mod tests {
                 partitions:  Vec::new(),
             }],
             logical:  Vec::new(),
+            images:   Vec::new(),
         }
     }
 
@@ -198,6 +209,7 @@ mod tests {
                         format:       true,
                         label:        None,
                         path:         PathBuf::from("/dev/sdz"),
+                        fs_options:   FormatOptions::default(),
                     },
                     PartitionCreate {
                         start_sector: 2048,
@@ -208,6 +220,7 @@ mod tests {
                         format:       true,
                         label:        None,
                         path:         PathBuf::from("/dev/sdz"),
+                        fs_options:   FormatOptions::default(),
                     },
                     PartitionCreate {
                         start_sector: 1026_048,
@@ -218,6 +231,7 @@ mod tests {
                         format:       true,
                         label:        None,
                         path:         PathBuf::from("/dev/sdz"),
+                        fs_options:   FormatOptions::default(),
                     },
                 ],
             }
@@ -272,4 +286,81 @@ mod tests {
             .unwrap();
         assert!(source.validate_layout(&duplicate).is_ok());
     }
+
+    #[test]
+    fn validation_diagnostics() {
+        let mut disks = get_empty();
+        disks.physical[0]
+            .add_partition(
+                boot_part(2048)
+                    .flag(PartitionFlag::PED_PARTITION_ESP)
+                    .mount(Path::new("/boot/efi").to_path_buf()),
+            )
+            .unwrap();
+        disks.physical[0]
+            .add_partition(root_part(1026_048).mount(Path::new("/").to_path_buf()))
+            .unwrap();
+        assert_eq!(disks.validate(Bootloader::Efi), Vec::new());
+        assert!(disks.verify_partitions(Bootloader::Efi).is_ok());
+
+        // Reusing the root partition without formatting it is only a warning.
+        disks.physical[0].partitions[1].bitflags &= !FORMAT;
+        let diagnostics = disks.validate(Bootloader::Efi);
+        assert_eq!(
+            diagnostics.iter().map(|diagnostic| diagnostic.code).collect::<Vec<_>>(),
+            vec![DiagnosticCode::RootNotFormatted]
+        );
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert!(disks.verify_partitions(Bootloader::Efi).is_ok());
+
+        // Systems which mount the ESP at /boot validate it there instead.
+        let mut disks = get_empty();
+        disks.physical[0]
+            .add_partition(
+                boot_part(2048)
+                    .flag(PartitionFlag::PED_PARTITION_ESP)
+                    .mount(Path::new("/boot").to_path_buf()),
+            )
+            .unwrap();
+        disks.physical[0]
+            .add_partition(root_part(1026_048).mount(Path::new("/").to_path_buf()))
+            .unwrap();
+        assert_eq!(disks.validate_with_esp(Bootloader::Efi, Path::new("/boot")), Vec::new());
+        assert_eq!(
+            disks
+                .validate(Bootloader::Efi)
+                .iter()
+                .map(|diagnostic| diagnostic.code)
+                .collect::<Vec<_>>(),
+            vec![DiagnosticCode::BootMissing]
+        );
+
+        // Every problem should be reported, rather than only the first.
+        let mut disks = get_empty();
+        disks.physical[0].table_type = Some(PartitionTable::Msdos);
+        disks.physical[0]
+            .add_partition(
+                PartitionBuilder::new(2048, 204_800 + 2048, FileSystem::Ext4)
+                    .mount(Path::new("/boot/efi").to_path_buf()),
+            )
+            .unwrap();
+
+        let diagnostics = disks.validate(Bootloader::Efi);
+        assert_eq!(
+            diagnostics.iter().map(|diagnostic| diagnostic.code).collect::<Vec<_>>(),
+            vec![
+                DiagnosticCode::RootMissing,
+                DiagnosticCode::EfiRequiresGpt,
+                DiagnosticCode::EspFlagMissing,
+                DiagnosticCode::BootInvalidFileSystem,
+                DiagnosticCode::EspTooSmall,
+            ]
+        );
+        assert_eq!(diagnostics[1].device, Some(PathBuf::from("/dev/sdz")));
+        assert_eq!(diagnostics[1].partition, Some(PathBuf::from("/boot/efi")));
+        assert!(diagnostics.iter().all(Diagnostic::is_error));
+
+        let why = disks.verify_partitions(Bootloader::Efi).unwrap_err();
+        assert_eq!(why.to_string(), "root partition was not defined");
+    }
 }
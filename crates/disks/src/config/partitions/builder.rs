@@ -1,6 +1,6 @@
 use super::{
-    FileSystem, LvmEncryption, PartitionFlag, PartitionIdentifiers, PartitionInfo, PartitionType,
-    FORMAT,
+    FileSystem, FormatOptions, LvmEncryption, PartitionFlag, PartitionIdentifiers, PartitionInfo,
+    PartitionType, FORMAT,
 };
 use std::path::PathBuf;
 
@@ -15,6 +15,7 @@ pub struct PartitionBuilder {
     pub mount:        Option<PathBuf>,
     pub volume_group: Option<(String, Option<LvmEncryption>)>,
     pub key_id:       Option<String>,
+    pub fs_options:   FormatOptions,
 }
 
 impl PartitionBuilder {
@@ -30,6 +31,7 @@ impl PartitionBuilder {
             mount:        None,
             volume_group: None,
             key_id:       None,
+            fs_options:   FormatOptions::default(),
         }
     }
 
@@ -81,6 +83,13 @@ impl PartitionBuilder {
         self
     }
 
+    /// Defines the options that the file system will be created with, such as its label.
+    /// These are validated against the file system when the partition is added to a disk.
+    pub fn format_options(mut self, options: FormatOptions) -> PartitionBuilder {
+        self.fs_options = options;
+        self
+    }
+
     /// Builds a brand new Partition from the current state of the builder.
     pub fn build(self) -> PartitionInfo {
         PartitionInfo {
@@ -108,6 +117,7 @@ impl PartitionBuilder {
             volume_group: self.volume_group.clone(),
             key_id:       self.key_id,
             identifiers:  PartitionIdentifiers::default(),
+            fs_options:   self.fs_options,
         }
     }
 }
@@ -6,6 +6,7 @@ use super::{
     PVS,
 };
 pub use disk_types::{BlockDeviceExt, FileSystem, PartitionExt, PartitionType};
+pub use crate::external::{BtrfsProfile, FormatOptions, FormatOptionsError};
 use crate::external::{get_label, is_encrypted};
 use fstab_generate::BlockInfo;
 use libparted::{Partition, PartitionFlag};
@@ -83,6 +84,8 @@ pub struct PartitionInfo {
     pub key_id:       Option<String>,
     /// Possible identifiers for this partition.
     pub identifiers:  PartitionIdentifiers,
+    /// The options that the file system is created with, when the partition is formatted.
+    pub fs_options:   FormatOptions,
 }
 
 impl BlockDeviceExt for PartitionInfo {
@@ -138,6 +141,7 @@ impl PartitionInfo {
             volume_group: None,
             key_id: None,
             identifiers,
+            fs_options: FormatOptions::default(),
         }))
     }
 
@@ -240,6 +244,21 @@ impl PartitionInfo {
         self.bitflags |= FORMAT;
         self.filesystem = Some(fs);
         self.name = None;
+        self.fs_options = FormatOptions::default();
+    }
+
+    /// Defines that a new file system will be applied to this partition, which is created
+    /// with the given options, if the file system supports them.
+    /// NOTE: this will also unset the partition's name.
+    pub fn format_with_options(
+        &mut self,
+        fs: FileSystem,
+        options: FormatOptions,
+    ) -> Result<(), PartitionError> {
+        options.validate(fs)?;
+        self.format_with(fs);
+        self.fs_options = options;
+        Ok(())
     }
 
     /// Defines that a new file system will be applied to this partition.
@@ -324,6 +343,7 @@ mod tests {
             original_vg:  None,
             volume_group: None,
             identifiers:  PartitionIdentifiers::default(),
+            fs_options:   FormatOptions::default(),
         }
     }
 
@@ -345,6 +365,7 @@ mod tests {
             original_vg:  None,
             volume_group: None,
             identifiers:  PartitionIdentifiers::default(),
+            fs_options:   FormatOptions::default(),
         }
     }
 
@@ -394,6 +415,7 @@ mod tests {
             original_vg:  None,
             volume_group: Some(("LVM_GROUP".into(), None)),
             identifiers:  PartitionIdentifiers::default(),
+            fs_options:   FormatOptions::default(),
         }
     }
 
@@ -415,6 +437,7 @@ mod tests {
             original_vg:  None,
             volume_group: None,
             identifiers:  PartitionIdentifiers::default(),
+            fs_options:   FormatOptions::default(),
         }
     }
 
@@ -473,4 +496,20 @@ mod tests {
         assert!(root.is_same_partition_as(&root_dup));
         assert!(!root.is_same_partition_as(&efi));
     }
+
+    #[test]
+    fn partition_format_options() {
+        let mut root = root_partition();
+        let label = FormatOptions::new().label("a label that is too long");
+        assert!(root.format_with_options(FileSystem::Ext4, label).is_err());
+        assert!(!root.will_format());
+
+        let label = FormatOptions::new().label("root");
+        root.format_with_options(FileSystem::Ext4, label.clone()).unwrap();
+        assert!(root.will_format());
+        assert_eq!(root.fs_options, label);
+
+        root.format_with(FileSystem::Xfs);
+        assert_eq!(root.fs_options, FormatOptions::default());
+    }
 }
@@ -0,0 +1,106 @@
+use std::{fmt, path::PathBuf};
+
+/// How severe a problem found by `Disks::validate` is.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    /// The installation may proceed, but the configuration is likely not what was intended.
+    Warning,
+    /// The installation cannot proceed until the problem has been fixed.
+    Error,
+}
+
+/// A machine-readable identifier for each problem that validation may report.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DiagnosticCode {
+    RootMissing,
+    RootInvalidFileSystem,
+    RootNoFileSystem,
+    RootNotFormatted,
+    BootMissing,
+    BootDiskNotFound,
+    BootInvalidFileSystem,
+    BootNoFileSystem,
+    BootOnLogicalDevice,
+    EfiRequiresGpt,
+    EspFlagMissing,
+    EspTooSmall,
+}
+
+impl DiagnosticCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiagnosticCode::RootMissing => "root-missing",
+            DiagnosticCode::RootInvalidFileSystem => "root-invalid-file-system",
+            DiagnosticCode::RootNoFileSystem => "root-no-file-system",
+            DiagnosticCode::RootNotFormatted => "root-not-formatted",
+            DiagnosticCode::BootMissing => "boot-missing",
+            DiagnosticCode::BootDiskNotFound => "boot-disk-not-found",
+            DiagnosticCode::BootInvalidFileSystem => "boot-invalid-file-system",
+            DiagnosticCode::BootNoFileSystem => "boot-no-file-system",
+            DiagnosticCode::BootOnLogicalDevice => "boot-on-logical-device",
+            DiagnosticCode::EfiRequiresGpt => "efi-requires-gpt",
+            DiagnosticCode::EspFlagMissing => "esp-flag-missing",
+            DiagnosticCode::EspTooSmall => "esp-too-small",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+/// A problem with the disk configuration, which was found by `Disks::validate`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub severity:   Severity,
+    pub code:       DiagnosticCode,
+    /// The disk, or logical device, which the problem was found on.
+    pub device:     Option<PathBuf>,
+    /// The mount target of the partition which the problem was found on.
+    pub partition:  Option<PathBuf>,
+    /// A description of the problem.
+    pub message:    String,
+    /// Describes how the problem may be fixed.
+    pub suggestion: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn error<M: Into<String>, S: Into<String>>(
+        code: DiagnosticCode,
+        message: M,
+        suggestion: S,
+    ) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            device: None,
+            partition: None,
+            message: message.into(),
+            suggestion: suggestion.into(),
+        }
+    }
+
+    pub(crate) fn warning<M: Into<String>, S: Into<String>>(
+        code: DiagnosticCode,
+        message: M,
+        suggestion: S,
+    ) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(code, message, suggestion) }
+    }
+
+    pub(crate) fn device<P: Into<PathBuf>>(mut self, device: P) -> Diagnostic {
+        self.device = Some(device.into());
+        self
+    }
+
+    pub(crate) fn partition<P: Into<PathBuf>>(mut self, partition: P) -> Diagnostic {
+        self.partition = Some(partition.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool { self.severity == Severity::Error }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(&self.message) }
+}
@@ -1,5 +1,6 @@
 pub use disk_types::PartitionSizeError;
 use disk_types::{FileSystem, PartitionTableError};
+use external_::FormatOptionsError;
 use std::{io, path::PathBuf};
 
 /// Defines a variety of errors that may arise from configuring and committing changes to disks.
@@ -28,6 +29,8 @@ pub enum DiskError {
     EncryptionOpen { volume: PathBuf, why: io::Error },
     #[fail(display = "problem executing external command: {}", why)]
     ExternalCommand { why: io::Error },
+    #[fail(display = "unable to attach disk image at {:?}: {}", image, why)]
+    ImageAttach { image: PathBuf, why: io::Error },
     #[fail(display = "serial model does not match")]
     InvalidSerial,
     #[fail(display = "{}", why)]
@@ -52,6 +55,8 @@ pub enum DiskError {
     LogicalVolumeCreate { why: io::Error },
     #[fail(display = "logical partition '{}-{}' does not exist", group, volume)]
     LogicalPartitionNotFound { group: String, volume: String },
+    #[fail(display = "unable to resize logical volume '{}-{}': {}", group, volume, why)]
+    LogicalVolumeResize { group: String, volume: String, why: io::Error },
     #[fail(display = "unable to get mount points: {}", why)]
     MountsObtain { why: io::Error },
     #[fail(display = "new partition could not be found")]
@@ -76,10 +81,20 @@ pub enum DiskError {
     SectorOverlaps { id: i32 },
     #[fail(display = "unable to get serial model of device: {}", why)]
     SerialGet { why: io::Error },
+    #[fail(display = "thin pool '{}-{}' does not exist", group, pool)]
+    ThinPoolNotFound { group: String, pool: String },
     #[fail(display = "unable to unmount partition(s) on {:?}: {}", device, why)]
     Unmount { device: PathBuf, why: io::Error },
     #[fail(display = "unable to create volume group '{}' on {:?}: {}", vg, device, why)]
     VolumeGroupCreate { device: PathBuf, vg: String, why: io::Error },
+    #[fail(display = "unable to obtain the extents of volume group '{}': {}", vg, why)]
+    VolumeGroupExtents { vg: String, why: io::Error },
+    #[fail(
+        display = "unable to shrink volume group '{}' by {} extents, as only {} extents are free \
+                   to relocate its data to",
+        vg, extents, free
+    )]
+    VolumeGroupShrink { vg: String, extents: u64, free: u64 },
     #[fail(display = "logical partition on {:?} lacks a label", device)]
     VolumePartitionLacksLabel { device: PathBuf },
 }
@@ -87,6 +102,8 @@ pub enum DiskError {
 #[derive(Debug, Fail)]
 /// An error that involves partitions.
 pub enum PartitionError {
+    #[fail(display = "invalid file system options: {}", why)]
+    FormatOptions { why: FormatOptionsError },
     #[fail(display = "no file system was found on the partition")]
     NoFilesystem,
     #[fail(display = "unable to format partition: {}", why)]
@@ -154,6 +171,10 @@ impl From<PartitionSizeError> for PartitionError {
     }
 }
 
+impl From<FormatOptionsError> for PartitionError {
+    fn from(why: FormatOptionsError) -> PartitionError { PartitionError::FormatOptions { why } }
+}
+
 impl From<PartitionTableError> for DiskError {
     fn from(why: PartitionTableError) -> DiskError { DiskError::PartitionTable { why } }
 }
@@ -3,7 +3,8 @@
 //! unlikely, that a user could hot swap drives after obtaining device
 //! information, but before writing their changes to the disk.
 
-use std::{io, path::Path, process::Command};
+use crate::external::Invocation;
+use std::{io, path::Path};
 
 const PATTERN: &str = "E: ID_SERIAL=";
 
@@ -12,9 +13,9 @@ const PATTERN: &str = "E: ID_SERIAL=";
 /// The `path` should be a value like `/dev/sda`.
 pub fn get_serial(path: &Path) -> io::Result<String> {
     info!("obtaining serial model from {}", path.display());
-    Command::new("udevadm")
+    Invocation::new("udevadm")
         .args(&["info", "--query=all", &format!("--name={}", path.display())])
-        .output()
+        .run()
         .and_then(|output| parse_serial(&output.stdout))
 }
 
@@ -1,12 +1,11 @@
 use self::FileSystem::*;
-use super::exec;
+use super::{exec, FormatOptions, Invocation};
 use disk_types::FileSystem;
 use crate::retry::Retry;
 use std::{
     ffi::{OsStr, OsString},
     io,
-    path::Path,
-    process::{Command, Stdio},
+    path::{Path, PathBuf},
 };
 
 /// Erase all signatures on a disk
@@ -28,15 +27,32 @@ pub fn blockdev<P: AsRef<Path>, S: AsRef<OsStr>, I: IntoIterator<Item = S>>(
     })
 }
 
+/// Attaches a disk image to the first unused loop device, with partition scanning
+/// enabled, and returns the path of the loop device.
+pub fn losetup_attach<P: AsRef<Path>>(image: P) -> io::Result<PathBuf> {
+    info!("attaching {:?} to a loop device", image.as_ref());
+    let output = Invocation::new("losetup")
+        .args(&["--find", "--show", "--partscan"])
+        .arg(image.as_ref())
+        .run_checked()?;
+
+    let device = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if device.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::Other, "losetup did not report a loop device"));
+    }
+
+    Ok(PathBuf::from(device))
+}
+
+/// Detaches a loop device. If the device is still in use, it will be detached by the
+/// kernel once it has been released.
+pub fn losetup_detach<P: AsRef<Path>>(device: P) -> io::Result<()> {
+    exec("losetup", None, None, &["--detach".into(), device.as_ref().into()])
+}
+
 /// Obtains the file system on a partition via blkid
 pub fn blkid_partition<P: AsRef<Path>>(part: P) -> Option<FileSystem> {
-    let output = Command::new("blkid")
-        .arg(part.as_ref())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()
-        .ok()?
-        .stdout;
+    let output = Invocation::new("blkid").arg(part.as_ref()).run().ok()?.stdout;
 
     String::from_utf8_lossy(&output).split_whitespace().nth(2).and_then(|type_| {
         info!("blkid found '{}'", type_);
@@ -61,6 +77,18 @@ pub fn fsck<P: AsRef<Path>>(part: P, cmd: Option<(&str, &str)>) -> io::Result<()
 
 /// Formats the supplied `part` device with the file system specified.
 pub fn mkfs<P: AsRef<Path>>(part: P, kind: FileSystem) -> io::Result<()> {
+    mkfs_with(part, kind, &FormatOptions::default())
+}
+
+/// Formats the supplied `part` device with the file system specified, and the given options,
+/// which are validated against the file system first.
+pub fn mkfs_with<P: AsRef<Path>>(
+    part: P,
+    kind: FileSystem,
+    options: &FormatOptions,
+) -> io::Result<()> {
+    options.validate(kind).map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+
     let (cmd, args): (&'static str, &'static [&'static str]) = match kind {
         Btrfs => ("mkfs.btrfs", &["-f"]),
         // Exfat => ("mkfs.exfat", &[]),
@@ -73,7 +101,8 @@ pub fn mkfs<P: AsRef<Path>>(part: P, kind: FileSystem) -> io::Result<()> {
         Fat32 => ("mkfs.fat", &["-F", "32"]),
         Ntfs => ("mkfs.ntfs", &["-FQ", "-q"]),
         Swap => {
-            if swap_exists(part.as_ref()) {
+            // An existing swap is kept, unless it is to be recreated with a new label or UUID.
+            if swap_exists(part.as_ref()) && *options == FormatOptions::default() {
                 return Ok(());
             }
 
@@ -85,6 +114,7 @@ pub fn mkfs<P: AsRef<Path>>(part: P, kind: FileSystem) -> io::Result<()> {
 
     exec(cmd, None, None, &{
         let mut args = args.iter().map(Into::into).collect::<Vec<OsString>>();
+        args.extend(options.args(kind));
         args.push(part.as_ref().into());
         args
     })
@@ -94,14 +124,7 @@ pub fn mkfs<P: AsRef<Path>>(part: P, kind: FileSystem) -> io::Result<()> {
 pub fn get_label<P: AsRef<Path>>(part: P, kind: FileSystem) -> Option<String> {
     let (cmd, args) = get_label_cmd(kind)?;
 
-    let output = Command::new(cmd)
-        .args(args)
-        .arg(part.as_ref())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()
-        .ok()?
-        .stdout;
+    let output = Invocation::new(cmd).args(args).arg(part.as_ref()).run().ok()?.stdout;
 
     let output: String = String::from_utf8_lossy(&output).into();
 
@@ -143,5 +166,5 @@ pub fn remount_rw<P: AsRef<Path>>(path: P) -> io::Result<()> {
 }
 
 fn swap_exists(path: &Path) -> bool {
-    Command::new("swaplabel").arg(path).status().ok().map_or(false, |stat| stat.success())
+    Invocation::new("swaplabel").arg(path).run().ok().map_or(false, |output| output.success())
 }
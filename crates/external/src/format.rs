@@ -0,0 +1,383 @@
+//! Options that a file system is created with, beyond its type, and the flags which each
+//! `mkfs` is given for them.
+
+use disk_types::FileSystem::{self, *};
+use std::{error::Error, ffi::OsString, fmt, io, str::FromStr};
+
+/// Characters which may not be used within the label of a FAT file system.
+const FAT_RESERVED: &str = "\"*+,./:;<=>?[\\]|";
+
+/// How btrfs stores data or metadata across the devices of the file system.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BtrfsProfile {
+    Single,
+    Dup,
+    Raid0,
+    Raid1,
+    Raid10,
+    Raid5,
+    Raid6,
+}
+
+impl fmt::Display for BtrfsProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BtrfsProfile::Single => "single",
+            BtrfsProfile::Dup => "dup",
+            BtrfsProfile::Raid0 => "raid0",
+            BtrfsProfile::Raid1 => "raid1",
+            BtrfsProfile::Raid10 => "raid10",
+            BtrfsProfile::Raid5 => "raid5",
+            BtrfsProfile::Raid6 => "raid6",
+        })
+    }
+}
+
+impl FromStr for BtrfsProfile {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> io::Result<Self> {
+        match input {
+            "single" => Ok(BtrfsProfile::Single),
+            "dup" => Ok(BtrfsProfile::Dup),
+            "raid0" => Ok(BtrfsProfile::Raid0),
+            "raid1" => Ok(BtrfsProfile::Raid1),
+            "raid10" => Ok(BtrfsProfile::Raid10),
+            "raid5" => Ok(BtrfsProfile::Raid5),
+            "raid6" => Ok(BtrfsProfile::Raid6),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a btrfs profile", input),
+            )),
+        }
+    }
+}
+
+/// Options for creating a file system. Each option is only supported by some file systems,
+/// so the options must be validated against the file system before it is created.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FormatOptions {
+    /// The label of the file system.
+    pub label:          Option<String>,
+    /// The UUID of the file system, in place of a random one. FAT file systems take a volume
+    /// ID of eight hexadecimal digits instead, which may be written as `XXXX-XXXX`.
+    pub uuid:           Option<String>,
+    /// Features to enable on an ext file system, or to disable when prefixed with `^`.
+    pub ext_features:   Vec<String>,
+    /// The profile of the data on a btrfs file system.
+    pub btrfs_data:     Option<BtrfsProfile>,
+    /// The profile of the metadata on a btrfs file system.
+    pub btrfs_metadata: Option<BtrfsProfile>,
+    /// Whether an XFS file system supports reflinks, which share data between files.
+    pub xfs_reflink:    Option<bool>,
+}
+
+impl FormatOptions {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn label<S: Into<String>>(mut self, label: S) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn uuid<S: Into<String>>(mut self, uuid: S) -> Self {
+        self.uuid = Some(uuid.into());
+        self
+    }
+
+    pub fn ext_feature<S: Into<String>>(mut self, feature: S) -> Self {
+        self.ext_features.push(feature.into());
+        self
+    }
+
+    pub fn btrfs_profiles(mut self, data: BtrfsProfile, metadata: BtrfsProfile) -> Self {
+        self.btrfs_data = Some(data);
+        self.btrfs_metadata = Some(metadata);
+        self
+    }
+
+    pub fn xfs_reflink(mut self, reflink: bool) -> Self {
+        self.xfs_reflink = Some(reflink);
+        self
+    }
+
+    /// Ensures that the file system supports each of the options, and that the label and
+    /// UUID are valid for it.
+    pub fn validate(&self, fs: FileSystem) -> Result<(), FormatOptionsError> {
+        let is_ext = fs == Ext2 || fs == Ext3 || fs == Ext4;
+        let unsupported = |option| Err(FormatOptionsError::Unsupported { fs, option });
+
+        if let Some(ref label) = self.label {
+            validate_label(fs, label)?;
+        }
+
+        if let Some(ref uuid) = self.uuid {
+            let valid = match fs {
+                Fat16 | Fat32 => volume_id(uuid).is_some(),
+                Btrfs | Ext2 | Ext3 | Ext4 | F2fs | Swap | Xfs => is_uuid(uuid),
+                _ => return unsupported("UUID"),
+            };
+
+            if !valid {
+                return Err(FormatOptionsError::Uuid { fs, uuid: uuid.clone() });
+            }
+        }
+
+        if !self.ext_features.is_empty() && !is_ext {
+            return unsupported("ext features");
+        }
+
+        for feature in &self.ext_features {
+            let name = if feature.starts_with('^') { &feature[1..] } else { &feature[..] };
+            if name.is_empty()
+                || !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                return Err(FormatOptionsError::Feature { feature: feature.clone() });
+            }
+        }
+
+        if (self.btrfs_data.is_some() || self.btrfs_metadata.is_some()) && fs != Btrfs {
+            return unsupported("btrfs profiles");
+        }
+
+        if self.xfs_reflink.is_some() && fs != Xfs {
+            return unsupported("reflink");
+        }
+
+        Ok(())
+    }
+
+    /// The arguments which `mkfs` is given for these options, which should be validated first.
+    pub(crate) fn args(&self, fs: FileSystem) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+
+        if let Some(ref label) = self.label {
+            let flag = match fs {
+                Fat16 | Fat32 => "-n",
+                F2fs => "-l",
+                _ => "-L",
+            };
+
+            args.extend(vec![flag.into(), label.into()]);
+        }
+
+        let uuid = self.uuid.as_ref();
+        match fs {
+            Fat16 | Fat32 => {
+                if let Some(id) = uuid.and_then(|uuid| volume_id(uuid)) {
+                    args.extend(vec!["-i".into(), id.into()]);
+                }
+            }
+            Xfs => {
+                let mut options = Vec::new();
+                if let Some(uuid) = uuid {
+                    options.push(["uuid=", uuid].concat());
+                }
+
+                if let Some(reflink) = self.xfs_reflink {
+                    options.push(["reflink=", if reflink { "1" } else { "0" }].concat());
+                }
+
+                if !options.is_empty() {
+                    args.extend(vec!["-m".into(), options.join(",").into()]);
+                }
+            }
+            _ => {
+                if let Some(uuid) = uuid {
+                    args.extend(vec!["-U".into(), uuid.into()]);
+                }
+            }
+        }
+
+        if !self.ext_features.is_empty() {
+            args.extend(vec!["-O".into(), self.ext_features.join(",").into()]);
+        }
+
+        if let Some(profile) = self.btrfs_data {
+            args.extend(vec!["-d".into(), profile.to_string().into()]);
+        }
+
+        if let Some(profile) = self.btrfs_metadata {
+            args.extend(vec!["-m".into(), profile.to_string().into()]);
+        }
+
+        args
+    }
+}
+
+/// Options that a file system does not support, or which are not valid for it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormatOptionsError {
+    LabelEmpty { fs: FileSystem },
+    LabelTooLong { fs: FileSystem, label: String, max: usize },
+    LabelCharacter { fs: FileSystem, label: String, character: char },
+    Uuid { fs: FileSystem, uuid: String },
+    Feature { feature: String },
+    Unsupported { fs: FileSystem, option: &'static str },
+}
+
+impl fmt::Display for FormatOptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatOptionsError::LabelEmpty { fs } => write!(f, "{} label is empty", fs),
+            FormatOptionsError::LabelTooLong { fs, ref label, max } => {
+                write!(f, "{} label '{}' is longer than {} characters", fs, label, max)
+            }
+            FormatOptionsError::LabelCharacter { fs, ref label, character } => {
+                write!(f, "{} label '{}' may not contain {:?}", fs, label, character)
+            }
+            FormatOptionsError::Uuid { fs, ref uuid } => {
+                write!(f, "'{}' is not a valid UUID for {}", uuid, fs)
+            }
+            FormatOptionsError::Feature { ref feature } => {
+                write!(f, "'{}' is not a valid ext feature", feature)
+            }
+            FormatOptionsError::Unsupported { fs, option } => {
+                write!(f, "{} does not support setting the {}", fs, option)
+            }
+        }
+    }
+}
+
+impl Error for FormatOptionsError {}
+
+fn validate_label(fs: FileSystem, label: &str) -> Result<(), FormatOptionsError> {
+    // NTFS and F2FS store their labels as UTF-16, and the rest as bytes.
+    let (max, length) = match fs {
+        Btrfs => (255, label.len()),
+        Ext2 | Ext3 | Ext4 | Swap => (16, label.len()),
+        F2fs => (512, label.encode_utf16().count()),
+        Fat16 | Fat32 => (11, label.len()),
+        Ntfs => (128, label.encode_utf16().count()),
+        Xfs => (12, label.len()),
+        _ => return Err(FormatOptionsError::Unsupported { fs, option: "label" }),
+    };
+
+    if label.is_empty() {
+        return Err(FormatOptionsError::LabelEmpty { fs });
+    } else if length > max {
+        return Err(FormatOptionsError::LabelTooLong { fs, label: label.into(), max });
+    }
+
+    let is_fat = fs == Fat16 || fs == Fat32;
+    let invalid = label
+        .chars()
+        .find(|&c| c.is_control() || (is_fat && (!c.is_ascii() || FAT_RESERVED.contains(c))));
+
+    match invalid {
+        Some(character) => {
+            Err(FormatOptionsError::LabelCharacter { fs, label: label.into(), character })
+        }
+        None => Ok(()),
+    }
+}
+
+/// True if the input is a UUID, written as hexadecimal groups of 8-4-4-4-12 digits.
+fn is_uuid(uuid: &str) -> bool {
+    let groups = uuid.split('-').map(str::len).collect::<Vec<_>>();
+    groups == [8, 4, 4, 4, 12] && uuid.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+}
+
+/// The volume ID of a FAT file system, as the eight hexadecimal digits which `mkfs.fat`
+/// expects.
+fn volume_id(uuid: &str) -> Option<String> {
+    let id = match uuid.find('-') {
+        Some(4) if uuid.len() == 9 => [&uuid[..4], &uuid[5..]].concat(),
+        Some(_) => return None,
+        None => uuid.to_owned(),
+    };
+
+    Some(id).filter(|id| id.len() == 8 && id.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mkfs_with, runner::*};
+    use std::sync::Arc;
+
+    #[test]
+    fn validation() {
+        let label = |label: &str| FormatOptions::new().label(label);
+
+        assert!(label("Pop_OS").validate(Ext4).is_ok());
+        assert!(label("0123456789abcdef").validate(Ext4).is_ok());
+        assert_eq!(
+            label("0123456789abc").validate(Xfs),
+            Err(FormatOptionsError::LabelTooLong {
+                fs:    Xfs,
+                label: "0123456789abc".into(),
+                max:   12,
+            })
+        );
+        assert_eq!(
+            label("EFI/BOOT").validate(Fat32),
+            Err(FormatOptionsError::LabelCharacter {
+                fs:        Fat32,
+                label:     "EFI/BOOT".into(),
+                character: '/',
+            })
+        );
+        assert!(label("données").validate(Fat32).is_err());
+        assert!(label("données").validate(Ntfs).is_ok());
+        assert!(label("").validate(Btrfs).is_err());
+        assert!(label("data").validate(Lvm).is_err());
+
+        let uuid = |uuid: &str| FormatOptions::new().uuid(uuid);
+        assert!(uuid("3b8f7a0e-5c2d-4e61-9f1a-2b7c8d9e0f1a").validate(Btrfs).is_ok());
+        assert!(uuid("3b8f7a0e-5c2d-4e61-9f1a").validate(Ext4).is_err());
+        assert!(uuid("A1B2-C3D4").validate(Fat32).is_ok());
+        assert!(uuid("A1B2-C3D4").validate(Ext4).is_err());
+        assert!(uuid("3b8f7a0e-5c2d-4e61-9f1a-2b7c8d9e0f1a").validate(Ntfs).is_err());
+
+        assert!(FormatOptions::new().ext_feature("^has_journal").validate(Ext4).is_ok());
+        assert!(FormatOptions::new().ext_feature("-O").validate(Ext4).is_err());
+        assert!(FormatOptions::new().ext_feature("metadata_csum").validate(Xfs).is_err());
+        assert!(FormatOptions::new().xfs_reflink(true).validate(Btrfs).is_err());
+        assert!(FormatOptions::new()
+            .btrfs_profiles(BtrfsProfile::Dup, BtrfsProfile::Dup)
+            .validate(Ext4)
+            .is_err());
+    }
+
+    #[test]
+    fn mkfs_flags() {
+        let runner = Arc::new(ScriptedRunner::new());
+        let uuid = "3b8f7a0e-5c2d-4e61-9f1a-2b7c8d9e0f1a";
+
+        with_runner(runner.clone(), || {
+            let ext4 = FormatOptions::new()
+                .label("root")
+                .uuid(uuid)
+                .ext_feature("^metadata_csum")
+                .ext_feature("64bit");
+            let btrfs = FormatOptions::new()
+                .label("data")
+                .btrfs_profiles(BtrfsProfile::Single, BtrfsProfile::Dup);
+            let xfs = FormatOptions::new().uuid(uuid).xfs_reflink(true);
+            let fat = FormatOptions::new().label("EFI").uuid("a1b2-c3d4");
+
+            mkfs_with("/dev/sda1", Ext4, &ext4).unwrap();
+            mkfs_with("/dev/sda2", Btrfs, &btrfs).unwrap();
+            mkfs_with("/dev/sda3", Xfs, &xfs).unwrap();
+            mkfs_with("/dev/sda4", Fat32, &fat).unwrap();
+            assert!(
+                mkfs_with("/dev/sda5", Fat32, &FormatOptions::new().xfs_reflink(false)).is_err()
+            );
+        });
+
+        assert_eq!(
+            runner.command_lines(),
+            vec![
+                format!(
+                    "mkfs.ext4 -F -q -E lazy_itable_init -L root -U {} -O ^metadata_csum,64bit \
+                     /dev/sda1",
+                    uuid
+                ),
+                "mkfs.btrfs -f -L data -d single -m dup /dev/sda2".into(),
+                format!("mkfs.xfs -f -m uuid={},reflink=1 /dev/sda3", uuid),
+                "mkfs.fat -F 32 -n EFI -i a1b2c3d4 /dev/sda4".into(),
+            ]
+        );
+    }
+}
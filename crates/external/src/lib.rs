@@ -3,6 +3,8 @@
 extern crate disk_types;
 extern crate distinst_utils as misc;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 extern crate proc_mounts;
 extern crate rand;
@@ -12,56 +14,41 @@ extern crate sys_mount;
 extern crate tempdir;
 
 pub mod block;
+pub mod format;
 pub mod luks;
 pub mod lvm;
 pub(crate) mod retry;
+pub mod runner;
 
-pub use self::{block::*, luks::*, lvm::*};
+pub use self::{block::*, format::*, luks::*, lvm::*, runner::*};
 
-use std::{
-    ffi::OsString,
-    io::{self, Write},
-    process::{Command, Stdio},
-};
+use std::{ffi::OsString, io};
 
 /// A generic function for executing a variety of external commands.
+///
+/// The input is treated as a secret, such as a passphrase, and is never logged. When the
+/// command fails, the error contains a `CommandError` with the tail of its standard error.
 pub fn exec(
     cmd: &str,
     stdin: Option<&[u8]>,
     valid_codes: Option<&'static [i32]>,
     args: &[OsString],
 ) -> io::Result<()> {
-    info!("executing {} with {:?}", cmd, args);
-
-    let mut child = Command::new(cmd)
-        .args(args)
-        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
-        .stdout(Stdio::null())
-        .spawn()?;
-
+    let mut command = Invocation::new(cmd).args(args);
     if let Some(stdin) = stdin {
-        child.stdin.as_mut().expect("stdin not obtained").write_all(stdin)?;
+        command = command.secret_stdin(stdin);
     }
 
-    let status = child.wait()?;
-    let success = status.success()
+    info!("executing {}", command);
+    let output = command.run()?;
+    let success = output.success()
         || valid_codes
-            .map_or(false, |codes| status.code().map_or(false, |code| codes.contains(&code)));
+            .map_or(false, |codes| output.code.map_or(false, |code| codes.contains(&code)));
 
     if success {
         Ok(())
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "{} failed with status: {}",
-                cmd,
-                match status.code() {
-                    Some(code) => format!("{} ({})", code, io::Error::from_raw_os_error(code)),
-                    None => "unknown".into(),
-                }
-            ),
-        ))
+        Err(command.failed(&output))
     }
 }
 
@@ -4,11 +4,10 @@ use proc_mounts::{MOUNTS, SWAPS};
 use rand::{self, distributions::Alphanumeric, Rng};
 use std::{
     collections::BTreeMap,
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     fs::read_link,
-    io::{self, BufRead, BufReader},
+    io,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
 };
 use sys_mount::{swapoff, unmount, UnmountFlags};
 
@@ -78,27 +77,14 @@ pub fn physical_volumes_to_deactivate<P: AsRef<Path>>(paths: &[P]) -> Vec<PathBu
 
 /// Get a vector of logical devices.
 pub fn dmlist() -> io::Result<Vec<String>> {
-    let mut current_line = String::with_capacity(64);
-    let mut output = Vec::new();
-
-    let mut reader = BufReader::new(
-        Command::new("dmsetup")
-            .arg("ls")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute dmsetup command"),
-    );
+    let stdout = Invocation::new("dmsetup").arg("ls").run()?.stdout;
 
     // Parse the output of `dmsetup ls`, only taking the first field from each line.
-    while reader.read_line(&mut current_line)? != 0 {
-        if let Some(dm) = current_line.split_whitespace().next() {
-            output.push(dm.into());
-        }
-
-        current_line.clear();
-    }
+    let mut output = String::from_utf8_lossy(&stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect::<Vec<_>>();
 
     // Also add lvm volume groups from `vgdisplay`, which `dmsetup ls` does not list.
     output.extend_from_slice(&vgdisplay()?);
@@ -149,6 +135,54 @@ pub fn lvcreate(group: &str, name: &str, size: Option<u64>) -> io::Result<()> {
     )
 }
 
+/// Creates a thin pool on a volume group, which fills the remaining space of the group if a
+/// size is not given.
+pub fn lvcreate_thin_pool(group: &str, name: &str, size: Option<u64>) -> io::Result<()> {
+    let mut args: Vec<OsString> = vec!["-y".into(), "--type".into(), "thin-pool".into()];
+    match size {
+        Some(size) => args.extend_from_slice(&["-L".into(), mebibytes(size).into()]),
+        None => args.extend_from_slice(&["-l".into(), "100%FREE".into()]),
+    }
+
+    args.extend_from_slice(&[group.into(), "-n".into(), name.into()]);
+    exec("lvcreate", None, None, &args)
+}
+
+/// Creates a thin volume with the given virtual size, which is provisioned from the thin pool,
+/// `pool`, as it is written to.
+pub fn lvcreate_thin(group: &str, pool: &str, name: &str, size: u64) -> io::Result<()> {
+    exec(
+        "lvcreate",
+        None,
+        None,
+        &[
+            "-y".into(),
+            "--type".into(),
+            "thin".into(),
+            "-V".into(),
+            mebibytes(size).into(),
+            "--thinpool".into(),
+            pool.into(),
+            group.into(),
+            "-n".into(),
+            name.into(),
+        ],
+    )
+}
+
+/// Resizes the logical volume, `name`, to the given size, along with the file system on it
+/// when `resize_fs` is set.
+pub fn lvresize(group: &str, name: &str, size: u64, resize_fs: bool) -> io::Result<()> {
+    let mut args: Vec<OsString> = vec!["-y".into()];
+    if resize_fs {
+        args.push("--resizefs".into());
+    }
+
+    let volume = [group, "/", name].concat();
+    args.extend_from_slice(&["-L".into(), mebibytes(size).into(), volume.into()]);
+    exec("lvresize", None, None, &args)
+}
+
 /// Remove the logical volume, `name`, from the volume group, `group`.
 pub fn lvremove(group: &str, name: &str) -> io::Result<()> {
     exec("lvremove", None, None, &["-y".into(), ["/dev/mapper/", group, "-", name].concat().into()])
@@ -157,40 +191,18 @@ pub fn lvremove(group: &str, name: &str) -> io::Result<()> {
 /// Obtains a list of logical volumes associated with the given volume group.
 pub fn lvs(vg: &str) -> io::Result<Vec<PathBuf>> {
     info!("obtaining logical volumes on {}", vg);
-    let mut current_line = String::with_capacity(128);
+    let stdout = Invocation::new("lvs").arg(vg).run()?.stdout;
     let mut output = Vec::new();
 
-    let mut reader = BufReader::new(
-        Command::new("lvs")
-            .arg(vg)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute lvs command"),
-    );
-
     // Skip the first line of output
-    let _ = reader.read_line(&mut current_line);
-    current_line.clear();
-
-    while reader.read_line(&mut current_line)? != 0 {
-        {
-            let line = &current_line[2..];
-            if let Some(pos) = line.find(' ') {
-                output.push(PathBuf::from(
-                    [
-                        "/dev/mapper/",
-                        &vg.replace("-", "--"),
-                        "-",
-                        &(&line[..pos].replace("-", "--")),
-                    ]
+    for line in String::from_utf8_lossy(&stdout).lines().skip(1) {
+        let line = line.get(2..).unwrap_or("");
+        if let Some(pos) = line.find(' ') {
+            output.push(PathBuf::from(
+                ["/dev/mapper/", &vg.replace("-", "--"), "-", &(&line[..pos].replace("-", "--"))]
                     .concat(),
-                ));
-            }
+            ));
         }
-
-        current_line.clear();
     }
 
     Ok(output)
@@ -205,39 +217,145 @@ pub fn pvcreate<P: AsRef<Path>>(device: P) -> io::Result<()> {
 /// groups.
 pub fn pvs() -> io::Result<BTreeMap<PathBuf, Option<String>>> {
     info!("obtaining list of physical volumes");
-    let mut current_line = String::with_capacity(64);
+    let stdout = Invocation::new("pvs").run()?.stdout;
     let mut output = BTreeMap::new();
 
-    let mut reader = BufReader::new(
-        Command::new("pvs")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute pvs command"),
-    );
-
     // Skip the first line of output
-    let _ = reader.read_line(&mut current_line);
-    current_line.clear();
-
-    while reader.read_line(&mut current_line)? != 0 {
-        {
-            let mut fields = current_line[2..].split_whitespace();
-            fields.next().map(|pv| {
-                fields.next().map(|vg| {
-                    output.insert(
-                        PathBuf::from(pv),
-                        if vg.is_empty() || vg == "lvm2" { None } else { Some(vg.into()) },
-                    )
-                })
-            });
+    for line in String::from_utf8_lossy(&stdout).lines().skip(1) {
+        let mut fields = line.get(2..).unwrap_or("").split_whitespace();
+        fields.next().map(|pv| {
+            fields.next().map(|vg| {
+                output.insert(
+                    PathBuf::from(pv),
+                    if vg.is_empty() || vg == "lvm2" { None } else { Some(vg.into()) },
+                )
+            })
+        });
+    }
+
+    Ok(output)
+}
+
+/// The extents of a physical volume, and the free extents of the volume group that it belongs
+/// to. Sizes are measured in 512-byte sectors.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PvExtents {
+    /// Where the first extent begins, from the start of the physical volume.
+    pub pe_start:    u64,
+    /// The size of each extent.
+    pub extent_size: u64,
+    /// The number of extents on the physical volume.
+    pub pe_count:    u64,
+    /// The number of extents which are free across the whole volume group.
+    pub vg_free:     u64,
+    /// The first extent, and the number of extents, of each allocated segment.
+    pub allocated:   Vec<(u64, u64)>,
+}
+
+impl PvExtents {
+    /// The number of extents which fit within a physical volume of the given size.
+    pub fn extents_within(&self, sectors: u64) -> u64 {
+        if self.extent_size == 0 {
+            0
+        } else {
+            sectors.saturating_sub(self.pe_start) / self.extent_size
         }
+    }
+}
+
+/// The extents of the physical volume, `pv`, and of its volume group.
+pub fn pv_extents(pv: &Path) -> io::Result<PvExtents> {
+    let output = Invocation::new("pvs")
+        .args(&["--noheadings", "--nosuffix", "--units", "s", "--separator", ":", "-o"])
+        .arg("pe_start,pv_pe_count,vg_extent_size,vg_free_count")
+        .arg(pv)
+        .run_checked()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields = stdout.trim().split(':').map(|field| field.parse::<u64>()).collect::<Vec<_>>();
+    let mut extents = match fields.as_slice() {
+        [Ok(pe_start), Ok(pe_count), Ok(extent_size), Ok(vg_free)] => PvExtents {
+            pe_start:    *pe_start,
+            extent_size: *extent_size,
+            pe_count:    *pe_count,
+            vg_free:     *vg_free,
+            allocated:   Vec::new(),
+        },
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected extents reported for {}: {}", pv.display(), stdout.trim()),
+            ))
+        }
+    };
 
-        current_line.clear();
+    let output = Invocation::new("pvs")
+        .args(&["--noheadings", "--segments", "--separator", ":", "-o"])
+        .arg("pvseg_start,pvseg_size,lv_name")
+        .arg(pv)
+        .run_checked()?;
+
+    // Free segments have no logical volume.
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut fields = line.trim().split(':');
+        let start = fields.next().and_then(|field| field.parse::<u64>().ok());
+        let length = fields.next().and_then(|field| field.parse::<u64>().ok());
+        let allocated = fields.next().map_or(false, |lv| !lv.is_empty());
+        if let (Some(start), Some(length), true) = (start, length, allocated) {
+            extents.allocated.push((start, length));
+        }
     }
 
-    Ok(output)
+    Ok(extents)
+}
+
+/// Moves every allocated extent that lies beyond the first `sectors` of the physical volume,
+/// `pv`, into the free extents before them, or onto the other physical volumes of its volume
+/// group, so that the physical volume may be shrunk to that size.
+pub fn pvmove_tail(pv: &Path, sectors: u64) -> io::Result<()> {
+    let extents = pv_extents(pv)?;
+    let kept = extents.extents_within(sectors);
+
+    let tail = extents
+        .allocated
+        .iter()
+        .filter(|&&(start, length)| start + length > kept)
+        .map(|&(start, length)| (start.max(kept), start + length - 1))
+        .collect::<Vec<_>>();
+
+    if tail.is_empty() {
+        return Ok(());
+    }
+
+    if kept == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} cannot be shrunk to {} sectors", pv.display(), sectors),
+        ));
+    }
+
+    let pvs = pvs()?;
+    let group = pvs.get(pv).cloned().unwrap_or(None);
+    let others = pvs
+        .into_iter()
+        .filter(|&(ref other, ref vg)| other != pv && vg.is_some() && *vg == group)
+        .map(|(other, _)| other.into_os_string())
+        .collect::<Vec<_>>();
+
+    let pv = pv.to_string_lossy();
+    for (first, last) in tail {
+        info!("moving extents {}-{} of {}", first, last, pv);
+        let mut args: Vec<OsString> = vec![
+            "--alloc".into(),
+            "anywhere".into(),
+            format!("{}:{}-{}", pv, first, last).into(),
+            format!("{}:0-{}", pv, kept - 1).into(),
+        ];
+        args.extend_from_slice(&others);
+        exec("pvmove", None, None, &args)?;
+    }
+
+    Ok(())
 }
 
 /// Deactivates all logical volumes in the supplied volume group
@@ -265,28 +383,34 @@ pub fn vgdeactivate(volume_group: &str) -> io::Result<()> {
     exec("vgchange", None, None, args)
 }
 
+/// The size of the extents of a volume group, in sectors, and the number of extents which it
+/// has in total, and which are free.
+pub fn vg_extents(group: &str) -> io::Result<(u64, u64, u64)> {
+    let output = Invocation::new("vgs")
+        .args(&["--noheadings", "--nosuffix", "--units", "s", "--separator", ":", "-o"])
+        .arg("vg_extent_size,vg_extent_count,vg_free_count")
+        .arg(group)
+        .run_checked()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields = stdout.trim().split(':').map(|field| field.parse::<u64>()).collect::<Vec<_>>();
+    match fields.as_slice() {
+        [Ok(size), Ok(count), Ok(free)] => Ok((*size, *count, *free)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected extents reported for {}: {}", group, stdout.trim()),
+        )),
+    }
+}
+
 /// Get a list of all volume groups.
 fn vgdisplay() -> io::Result<Vec<String>> {
-    let mut current_line = String::with_capacity(64);
-    let mut output = Vec::new();
-
-    let mut reader = BufReader::new(
-        Command::new("vgdisplay")
-            .arg("-s")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute vgdisplay command"),
-    );
-
-    while reader.read_line(&mut current_line)? != 0 {
-        if let Some(dm) = current_line.split_whitespace().next() {
-            output.push(dm[1..dm.len() - 1].into());
-        }
-
-        current_line.clear();
-    }
+    let stdout = Invocation::new("vgdisplay").arg("-s").run()?.stdout;
+    let output = String::from_utf8_lossy(&stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|dm| dm[1..dm.len() - 1].into())
+        .collect();
 
     Ok(output)
 }
@@ -301,3 +425,66 @@ pub fn pvremove(physical_volume: &Path) -> io::Result<()> {
     let args = &["-ffy".into(), physical_volume.into()];
     exec("pvremove", None, None, args)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn pvmove_tail_extents() {
+        let runner = Arc::new(ScriptedRunner::new());
+        let stdout = |stdout: &str| CommandOutput::stdout(stdout.as_bytes().to_vec());
+        for _ in 0..2 {
+            runner
+                .respond("pvs", stdout("  2048:100:8192:30\n"))
+                .respond("pvs", stdout("  0:50:root\n  50:20:\n  70:20:home\n  90:10:\n"));
+        }
+
+        let pvs = concat!(
+            "  PV         VG   Fmt  Attr PSize   PFree\n",
+            "  /dev/sda3  data lvm2 a--  400.00m 120.00m\n",
+            "  /dev/sdb1  data lvm2 a--  100.00m 100.00m\n",
+            "  /dev/sdc1  misc lvm2 a--  100.00m 100.00m\n",
+        );
+        runner.respond("pvs", stdout(pvs));
+
+        let pv = Path::new("/dev/sda3");
+        let extents = with_runner(runner.clone(), || {
+            let extents = pv_extents(pv).unwrap();
+            pvmove_tail(pv, 2048 + 80 * 8192).unwrap();
+            extents
+        });
+
+        assert_eq!(extents.allocated, vec![(0, 50), (70, 20)]);
+        assert_eq!(extents.extents_within(2048 + 80 * 8192 + 100), 80);
+        assert_eq!(
+            runner.command_lines()[5..],
+            ["pvmove --alloc anywhere /dev/sda3:80-89 /dev/sda3:0-79 /dev/sdb1".to_owned()]
+        );
+    }
+
+    #[test]
+    fn thin_volumes() {
+        let runner = Arc::new(ScriptedRunner::new());
+        with_runner(runner.clone(), || {
+            lvcreate_thin_pool("data", "pool", Some(64 << 30)).unwrap();
+            lvcreate_thin("data", "pool", "home", 128 << 30).unwrap();
+            lvcreate_thin_pool("data", "rest", None).unwrap();
+            lvresize("data", "root", 20 << 30, true).unwrap();
+            lvresize("data", "swap", 4 << 30, false).unwrap();
+        });
+
+        assert_eq!(
+            runner.command_lines(),
+            vec![
+                "lvcreate -y --type thin-pool -L 65536 data -n pool",
+                "lvcreate -y --type thin -V 131072 --thinpool pool data -n home",
+                "lvcreate -y --type thin-pool -l 100%FREE data -n rest",
+                "lvresize -y --resizefs -L 20480 data/root",
+                "lvresize -y -L 4096 data/swap",
+            ]
+        );
+    }
+}
@@ -0,0 +1,657 @@
+//! Every external command is executed through a runner, which may be replaced so that the
+//! code above it can be tested against scripted outputs, without root or real disks.
+//!
+//! The exceptions are the commands that `distinst-chroot` executes within the target, and the
+//! extraction of the base system by `distinst-squashfs`, which reads its progress from a
+//! pseudo-terminal. Both need a mounted target, so they cannot be scripted.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    error::Error,
+    ffi::{OsStr, OsString},
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{ChildStderr, Command, Stdio},
+    sync::{Arc, Condvar, Mutex, RwLock},
+    thread::{self, ThreadId},
+};
+
+/// The most output that is kept from the end of a command's standard error. Its standard
+/// output is kept in full, as callers parse it.
+const OUTPUT_LIMIT: usize = 1024 * 1024;
+
+/// How many lines, from the end of a failed command's standard error, are kept by its error.
+const STDERR_TAIL: usize = 20;
+
+lazy_static! {
+    static ref RUNNER: RwLock<Arc<dyn CommandRunner>> = RwLock::new(Arc::new(SystemRunner));
+    /// The thread which holds the runner through `exclusive_runner`, and how many of its
+    /// guards have yet to be dropped.
+    static ref OWNER: Mutex<Option<(ThreadId, usize)>> = Mutex::new(None);
+    static ref RELEASED: Condvar = Condvar::new();
+}
+
+/// Replaces the runner that every external command is executed by, for the whole process,
+/// returning the runner which was replaced.
+pub fn set_runner(runner: Arc<dyn CommandRunner>) -> Arc<dyn CommandRunner> {
+    let mut current = RUNNER.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    ::std::mem::replace(&mut *current, runner)
+}
+
+/// Replaces the runner that every external command is executed by until the returned guard is
+/// dropped, which restores the runner that was replaced.
+pub fn scoped_runner(runner: Arc<dyn CommandRunner>) -> RunnerGuard {
+    RunnerGuard { previous: Some(set_runner(runner)), exclusive: false }
+}
+
+/// Holds the runner exclusively until the returned guard is dropped, replacing it with
+/// `runner` as `scoped_runner` would, or else keeping the current runner.
+///
+/// Other threads which call this wait until the guard has been dropped. The thread which
+/// holds the guard may call this again, and its own guards are restored in turn. Commands
+/// executed by threads which do not hold a guard are still executed by the current runner.
+pub fn exclusive_runner(runner: Option<Arc<dyn CommandRunner>>) -> RunnerGuard {
+    let thread = thread::current().id();
+    let mut owner = OWNER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    while owner.map_or(false, |(holder, _)| holder != thread) {
+        owner = RELEASED.wait(owner).unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+
+    let depth = owner.map_or(0, |(_, depth)| depth);
+    *owner = Some((thread, depth + 1));
+    drop(owner);
+
+    RunnerGuard { previous: runner.map(set_runner), exclusive: true }
+}
+
+/// Restores the runner which was replaced by `scoped_runner` or `exclusive_runner` when it
+/// is dropped.
+#[must_use = "the runner is restored as soon as the guard is dropped"]
+pub struct RunnerGuard {
+    previous:  Option<Arc<dyn CommandRunner>>,
+    exclusive: bool,
+}
+
+impl Drop for RunnerGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            set_runner(previous);
+        }
+
+        if self.exclusive {
+            let mut owner = OWNER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            *owner = match *owner {
+                Some((thread, depth)) if depth > 1 => Some((thread, depth - 1)),
+                _ => None,
+            };
+            RELEASED.notify_all();
+        }
+    }
+}
+
+/// The runner that external commands are currently executed by.
+pub fn runner() -> Arc<dyn CommandRunner> {
+    RUNNER.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// Executes external commands.
+pub trait CommandRunner: Send + Sync {
+    /// Runs the command to completion, capturing its output.
+    fn run(&self, command: &Invocation) -> io::Result<CommandOutput>;
+
+    /// Runs the command to completion, passing each line of its standard error to `line` as
+    /// soon as it has been written. Those lines are also kept in the output, as they would be
+    /// by `run`.
+    fn run_streamed(
+        &self,
+        command: &Invocation,
+        line: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<CommandOutput>;
+}
+
+/// An external command, with its arguments, environment, and input.
+#[derive(Clone, Default, PartialEq)]
+pub struct Invocation {
+    pub program: String,
+    pub args:    Vec<OsString>,
+    pub env:     Vec<(OsString, OsString)>,
+    pub stdin:   Option<Vec<u8>>,
+    /// Values which are redacted wherever they would be shown, such as passphrases.
+    pub secrets: Vec<String>,
+}
+
+impl Invocation {
+    pub fn new<S: Into<String>>(program: S) -> Self {
+        Invocation { program: program.into(), ..Self::default() }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.args.push(arg.as_ref().into());
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(mut self, args: I) -> Self {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().into()));
+        self
+    }
+
+    /// An argument which is redacted from the command line that is logged, or reported when
+    /// the command fails.
+    pub fn secret_arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.secrets.push(arg.as_ref().to_string_lossy().into_owned());
+        self.arg(arg)
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(mut self, key: K, value: V) -> Self {
+        self.env.push((key.as_ref().into(), value.as_ref().into()));
+        self
+    }
+
+    /// Input which is written to the command's standard input.
+    pub fn stdin<B: Into<Vec<u8>>>(mut self, input: B) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    /// Input which is secret, and so each of its lines is redacted from the command's output
+    /// wherever that is logged or reported.
+    pub fn secret_stdin<B: Into<Vec<u8>>>(self, input: B) -> Self {
+        let mut command = self.stdin(input);
+        let input = String::from_utf8_lossy(command.stdin.as_ref().expect("stdin was just set"));
+        let lines = input.lines().filter(|line| !line.trim().is_empty()).map(String::from);
+        command.secrets.extend(lines.collect::<Vec<_>>());
+        command
+    }
+
+    /// Runs the command with the current runner.
+    pub fn run(&self) -> io::Result<CommandOutput> { runner().run(self) }
+
+    /// Runs the command with the current runner, streaming each line of its standard error.
+    pub fn run_streamed(&self, line: &mut dyn FnMut(&[u8])) -> io::Result<CommandOutput> {
+        runner().run_streamed(self, line)
+    }
+
+    /// Runs the command with the current runner, returning a `CommandError` if it fails.
+    pub fn run_checked(&self) -> io::Result<CommandOutput> {
+        let output = self.run()?;
+        if output.success() {
+            Ok(output)
+        } else {
+            Err(self.failed(&output))
+        }
+    }
+
+    /// Logs the output of the command, which did not succeed, and returns an error containing
+    /// the `CommandError` that describes why.
+    pub fn failed(&self, output: &CommandOutput) -> io::Error {
+        let error = CommandError::new(self, output);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !stdout.trim().is_empty() {
+            debug!("{} wrote to stdout: {}", self.program, self.redact(stdout.trim_end()));
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.trim().is_empty() {
+            warn!("{} wrote to stderr: {}", self.program, self.redact(stderr.trim_end()));
+        }
+
+        io::Error::new(io::ErrorKind::Other, error)
+    }
+
+    fn arguments(&self) -> impl Iterator<Item = Cow<str>> {
+        self.args.iter().map(move |arg| {
+            let arg = arg.to_string_lossy();
+            if self.secrets.iter().any(|secret| *secret == arg) {
+                Cow::Borrowed("<redacted>")
+            } else {
+                arg
+            }
+        })
+    }
+
+    /// Hides each of the secrets wherever they appear in `text`.
+    fn redact(&self, text: &str) -> String {
+        self.secrets
+            .iter()
+            .filter(|secret| !secret.is_empty())
+            .fold(text.to_owned(), |text, secret| text.replace(secret.as_str(), "<redacted>"))
+    }
+}
+
+/// The input and secret arguments are never shown, as they may contain passphrases.
+impl fmt::Debug for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Invocation")
+            .field("program", &self.program)
+            .field("args", &self.arguments().collect::<Vec<_>>())
+            .field("env", &self.env)
+            .field("stdin", &self.stdin.as_ref().map(|input| format!("<{} bytes>", input.len())))
+            .finish()
+    }
+}
+
+/// Formats the command line, as it would be written in a shell, with secrets redacted.
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.program)?;
+        for arg in self.arguments() {
+            write!(f, " {}", arg)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How a command exited, and the output that it wrote.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommandOutput {
+    /// The exit code, which is `None` when the command was terminated by a signal.
+    pub code:   Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl CommandOutput {
+    /// The output of a command which succeeded after writing `stdout`.
+    pub fn stdout<B: Into<Vec<u8>>>(stdout: B) -> Self {
+        CommandOutput { code: Some(0), stdout: stdout.into(), stderr: Vec::new() }
+    }
+
+    /// The output of a command which exited with `code`.
+    pub fn exit(code: i32) -> Self { CommandOutput { code: Some(code), ..Self::default() } }
+
+    pub fn with_stderr<B: Into<Vec<u8>>>(mut self, stderr: B) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+
+    pub fn success(&self) -> bool { self.code == Some(0) }
+
+    /// Describes how the command exited, for error messages.
+    pub fn status(&self) -> String { status(self.code) }
+}
+
+fn status(code: Option<i32>) -> String {
+    match code {
+        Some(code) => format!("exit code {}", code),
+        None => "terminated by a signal".into(),
+    }
+}
+
+/// A command which did not succeed, with the reason that it gave for failing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandError {
+    /// The command line, with its secrets redacted.
+    pub command: String,
+    /// The exit code, which is `None` when the command was terminated by a signal.
+    pub code:    Option<i32>,
+    /// The last lines that the command wrote to its standard error.
+    pub stderr:  String,
+}
+
+impl CommandError {
+    pub fn new(command: &Invocation, output: &CommandOutput) -> Self {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines = stderr.lines().filter(|line| !line.trim().is_empty()).collect::<Vec<_>>();
+        let tail = lines[lines.len().saturating_sub(STDERR_TAIL)..].join("\n");
+
+        CommandError {
+            command: command.to_string(),
+            code:    output.code,
+            stderr:  command.redact(&tail),
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` failed with {}", self.command, status(self.code))?;
+        if !self.stderr.is_empty() {
+            write!(f, ":\n{}", self.stderr)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for CommandError {}
+
+/// Executes commands on the running system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner;
+
+impl SystemRunner {
+    /// Runs the command, while its input is written and its standard output is read on other
+    /// threads, as the command could block on any of its pipes if they were handled in turn.
+    /// Its standard error is read by `read_stderr`.
+    fn communicate<F>(command: &Invocation, read_stderr: F) -> io::Result<CommandOutput>
+    where
+        F: FnOnce(ChildStderr) -> io::Result<Vec<u8>>,
+    {
+        let mut child = Command::new(&command.program)
+            .args(&command.args)
+            .envs(command.env.iter().map(|&(ref key, ref value)| (key, value)))
+            .stdin(if command.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let stdout = thread::spawn(move || {
+            let mut output = Vec::new();
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+
+        // Dropping the handle closes the pipe, so that the command knows the input has ended.
+        let stdin = match (child.stdin.take(), command.stdin.clone()) {
+            (Some(mut pipe), Some(input)) => Some(thread::spawn(move || pipe.write_all(&input))),
+            _ => None,
+        };
+
+        let stderr = read_stderr(child.stderr.take().expect("stderr is piped"));
+
+        // The command is always waited on, so that it is never left behind as a zombie.
+        if stderr.is_err() {
+            let _ = child.kill();
+        }
+
+        let status = child.wait();
+        let stdout = stdout.join().unwrap_or_else(|_| Ok(Vec::new()));
+        let written = stdin.map_or(Ok(()), |stdin| stdin.join().unwrap_or(Ok(())));
+
+        let stderr = stderr?;
+        let status = status?;
+        match written {
+            // Commands may exit without reading all of their input.
+            Err(ref why) if why.kind() == io::ErrorKind::BrokenPipe => (),
+            written => written?,
+        }
+
+        Ok(CommandOutput { code: status.code(), stdout: stdout?, stderr })
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, command: &Invocation) -> io::Result<CommandOutput> {
+        Self::communicate(command, read_tail)
+    }
+
+    fn run_streamed(
+        &self,
+        command: &Invocation,
+        line: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<CommandOutput> {
+        Self::communicate(command, |pipe| {
+            let mut stderr = Vec::new();
+            for stderr_line in BufReader::new(pipe).split(b'\n') {
+                match stderr_line {
+                    Ok(stderr_line) => {
+                        line(&stderr_line);
+                        stderr.extend_from_slice(&stderr_line);
+                        stderr.push(b'\n');
+                        keep_tail(&mut stderr, 2 * OUTPUT_LIMIT);
+                    }
+                    Err(why) => {
+                        warn!("failed to read the output of {}: {}", command.program, why);
+                        break;
+                    }
+                }
+            }
+
+            keep_tail(&mut stderr, OUTPUT_LIMIT);
+            Ok(stderr)
+        })
+    }
+}
+
+/// Reads everything from `pipe`, so that the command never blocks on it, but keeps no more
+/// than the last `OUTPUT_LIMIT` bytes.
+fn read_tail<R: Read>(mut pipe: R) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 8 * 1024];
+    loop {
+        let read = match pipe.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref why) if why.kind() == io::ErrorKind::Interrupted => continue,
+            Err(why) => return Err(why),
+        };
+
+        output.extend_from_slice(&buffer[..read]);
+        keep_tail(&mut output, 2 * OUTPUT_LIMIT);
+    }
+
+    keep_tail(&mut output, OUTPUT_LIMIT);
+    Ok(output)
+}
+
+/// Discards the start of the output once it exceeds `threshold`, keeping its last
+/// `OUTPUT_LIMIT` bytes. The threshold may be larger than the limit, so that the output is not
+/// shifted after every read.
+fn keep_tail(output: &mut Vec<u8>, threshold: usize) {
+    if output.len() > threshold {
+        let excess = output.len() - OUTPUT_LIMIT;
+        output.drain(..excess);
+    }
+}
+
+/// A runner which records each command that it is given, and replays the output which was
+/// scripted for its program, so that callers may be tested without executing anything.
+///
+/// Commands which have no output scripted for them succeed without writing anything.
+#[derive(Debug, Default)]
+pub struct ScriptedRunner {
+    outputs:     Mutex<HashMap<String, VecDeque<CommandOutput>>>,
+    invocations: Mutex<Vec<Invocation>>,
+}
+
+impl ScriptedRunner {
+    pub fn new() -> Self { Self::default() }
+
+    /// Scripts the output of the next execution of `program`. Outputs which are scripted for
+    /// the same program are replayed in the order that they were scripted.
+    pub fn respond<S: Into<String>>(&self, program: S, output: CommandOutput) -> &Self {
+        let mut outputs = self.outputs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        outputs.entry(program.into()).or_insert_with(VecDeque::new).push_back(output);
+        self
+    }
+
+    /// Every command that has been run, in the order that they were run.
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// The command line of every command that has been run.
+    pub fn command_lines(&self) -> Vec<String> {
+        self.invocations().iter().map(ToString::to_string).collect()
+    }
+}
+
+impl CommandRunner for ScriptedRunner {
+    fn run(&self, command: &Invocation) -> io::Result<CommandOutput> {
+        self.invocations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(command.clone());
+
+        let output = self
+            .outputs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get_mut(&command.program)
+            .and_then(VecDeque::pop_front);
+
+        Ok(output.unwrap_or_else(|| CommandOutput::stdout(Vec::new())))
+    }
+
+    fn run_streamed(
+        &self,
+        command: &Invocation,
+        line: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<CommandOutput> {
+        let output = self.run(command)?;
+        if !output.stderr.is_empty() {
+            let stderr = &output.stderr;
+            let stderr =
+                if stderr.ends_with(b"\n") { &stderr[..stderr.len() - 1] } else { &stderr[..] };
+            stderr.split(|&byte| byte == b'\n').for_each(|stderr_line| line(stderr_line));
+        }
+
+        Ok(output)
+    }
+}
+
+/// Replaces the runner for the duration of a test, so that tests which replace it cannot
+/// interfere with each other. Tests of other crates may use it with the `test-util` feature.
+#[cfg(any(test, feature = "test-util"))]
+pub fn with_runner<T, F: FnOnce() -> T>(runner: Arc<dyn CommandRunner>, test: F) -> T {
+    lazy_static! {
+        static ref LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    let _lock = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _runner = scoped_runner(runner);
+    test()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{exec, pvs};
+    use std::{path::PathBuf, sync::mpsc, time::Duration};
+
+    #[test]
+    fn scripted_outputs() {
+        let runner = ScriptedRunner::new();
+        runner
+            .respond("cryptsetup", CommandOutput::exit(4))
+            .respond("cryptsetup", CommandOutput::exit(5));
+
+        let close = || {
+            exec("cryptsetup", Some(b"secret"), Some(&[4]), &["close".into(), "cryptdata".into()])
+        };
+        let runner = Arc::new(runner);
+        let results = with_runner(runner.clone(), || (close(), close(), close()));
+
+        assert!(results.0.is_ok());
+        assert!(results.1.is_err());
+        assert!(results.2.is_ok());
+        assert_eq!(runner.command_lines(), vec!["cryptsetup close cryptdata"; 3]);
+        assert_eq!(runner.invocations()[0].stdin, Some(b"secret".to_vec()));
+        assert!(!format!("{:?}", runner.invocations()[0]).contains("secret"));
+    }
+
+    #[test]
+    fn scripted_pvs() {
+        let runner = ScriptedRunner::new();
+        runner.respond(
+            "pvs",
+            CommandOutput::stdout(concat!(
+                "  PV                    VG     Fmt  Attr PSize   PFree\n",
+                "  /dev/mapper/cryptdata data   lvm2 a--  100.00g    0 \n",
+                "  /dev/sdb1                    lvm2 ---   20.00g 20.00g\n",
+            )),
+        );
+
+        let volumes = with_runner(Arc::new(runner), pvs).unwrap();
+        assert_eq!(
+            volumes.get(&PathBuf::from("/dev/mapper/cryptdata")),
+            Some(&Some("data".into()))
+        );
+        assert_eq!(volumes.get(&PathBuf::from("/dev/sdb1")), Some(&None));
+    }
+
+    #[test]
+    fn streamed_stderr() {
+        let runner = ScriptedRunner::new();
+        runner.respond(
+            "nixos-install",
+            CommandOutput::exit(1).with_stderr("copying channel...\nbuilding\n"),
+        );
+
+        let mut lines = Vec::new();
+        let output = runner
+            .run_streamed(&Invocation::new("nixos-install"), &mut |line| lines.push(line.to_vec()))
+            .unwrap();
+
+        assert_eq!(lines, vec![b"copying channel...".to_vec(), b"building".to_vec()]);
+        assert_eq!(output.stderr, b"copying channel...\nbuilding\n".to_vec());
+        assert_eq!(output.status(), "exit code 1");
+    }
+
+    #[test]
+    fn failed_commands() {
+        let runner = ScriptedRunner::new();
+        let stderr = (1..=30).map(|line| format!("line {}\n", line)).collect::<String>();
+        runner.respond(
+            "cryptsetup",
+            CommandOutput::exit(2).with_stderr(stderr + "passphrase hunter2 was rejected\n"),
+        );
+
+        let command = Invocation::new("cryptsetup")
+            .arg("open")
+            .secret_arg("--token=hunter2")
+            .arg("/dev/sda3")
+            .secret_stdin("hunter2\n");
+        let why = with_runner(Arc::new(runner), || command.run_checked()).unwrap_err();
+        let error = why.get_ref().and_then(|why| why.downcast_ref::<CommandError>()).unwrap();
+
+        assert_eq!(error.command, "cryptsetup open <redacted> /dev/sda3");
+        assert_eq!(error.code, Some(2));
+        assert_eq!(error.stderr.lines().count(), STDERR_TAIL);
+        assert!(error.stderr.starts_with("line 12\n"));
+        assert!(error.stderr.ends_with("passphrase <redacted> was rejected"));
+        assert!(!why.to_string().contains("hunter2"));
+        assert!(!format!("{:?}", command).contains("hunter2"));
+    }
+
+    #[test]
+    fn exclusive_runners() {
+        with_runner(Arc::new(ScriptedRunner::new()), || {
+            let guard = exclusive_runner(None);
+            // The thread which holds the runner may take it again.
+            drop(exclusive_runner(None));
+
+            let (sender, receiver) = mpsc::channel();
+            let waiting = thread::spawn(move || {
+                let _guard = exclusive_runner(None);
+                sender.send(()).unwrap();
+            });
+
+            assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(guard);
+            receiver.recv().unwrap();
+            waiting.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn bounded_output() {
+        let mut output = b"head".to_vec();
+        output.extend_from_slice(&vec![b'a'; OUTPUT_LIMIT * 3 + 7]);
+        output.extend_from_slice(b"end");
+        let read = read_tail(&output[..]).unwrap();
+        assert_eq!(read.len(), OUTPUT_LIMIT);
+        assert!(read.ends_with(b"aend"));
+
+        // Standard output is never truncated, as it is parsed.
+        let script = format!("head -c {} /dev/zero", OUTPUT_LIMIT * 2 + 7);
+        let output = SystemRunner.run(&Invocation::new("sh").args(&["-c", &script])).unwrap();
+        assert_eq!(output.stdout.len(), OUTPUT_LIMIT * 2 + 7);
+    }
+
+    #[test]
+    fn system_stdin() {
+        // More than a pipe can buffer, so that the command blocks on its output while its
+        // input is still being written.
+        let input = (0..64 * 1024).map(|line| format!("{}\n", line)).collect::<String>();
+        let output = SystemRunner.run(&Invocation::new("cat").stdin(input.clone())).unwrap();
+        assert!(output.success());
+        assert!(output.stdout == input.into_bytes());
+
+        let output =
+            SystemRunner.run(&Invocation::new("true").stdin(vec![0; 1024 * 1024])).unwrap();
+        assert!(output.success());
+    }
+}
@@ -35,6 +35,10 @@ fn main() {
         lang:             "en_US.UTF-8".into(),
         remove:           "/cdrom/casper/filesystem.manifest-remove".into(),
         squashfs:         "/cdrom/casper/filesystem.squashfs".into(),
+        distribution:     None,
+        hooks:            Vec::new(),
+        boot_manager:     BootManager::Grub,
+        nixos:            NixosConfig::default(),
     };
 
     eprintln!("Options: {:#?}", options);
@@ -203,6 +207,7 @@ fn main() {
                     Step::Extract => "Extracting filesystem ",
                     Step::Configure => "Configuring installation",
                     Step::Bootloader => "Installing bootloader ",
+                    Step::Hooks => "Running hooks ",
                 });
                 *pb_opt.borrow_mut() = Some(pb);
             }
@@ -1,7 +1,7 @@
-use distinst::{Config, UserAccountCreate};
+use distinst::{BootManager, Config, NixosConfig, UserAccountCreate};
 use crate::get_str;
 use libc;
-use std::io;
+use std::{convert::TryFrom, io, path::PathBuf, slice};
 
 /// Installer configuration
 #[repr(C)]
@@ -16,6 +16,17 @@ pub struct DistinstConfig {
     remove:           *const libc::c_char,
     squashfs:         *const libc::c_char,
     flags:            u8,
+    distribution:     *const libc::c_char,
+    hooks:            *const *const libc::c_char,
+    hooks_len:        libc::size_t,
+    boot_manager:     *const libc::c_char,
+    nixos_template:   *const libc::c_char,
+    nixos_seed:       *const libc::c_char,
+    nixos_installer:  *const libc::c_char,
+    nixos_json:       *const libc::c_char,
+    nixos_closure:    *const libc::c_char,
+    nixos_flake:      u8,
+    nixos_wipe:       u8,
 }
 
 impl DistinstConfig {
@@ -30,15 +41,55 @@ impl DistinstConfig {
             old_root:         get_str(self.old_root).ok().map(String::from),
             remove:           get_str(self.remove)?.to_string(),
             flags:            self.flags,
+            distribution:     match get_str(self.distribution) {
+                Ok(distribution) => Some(distribution.parse()?),
+                Err(_) => None,
+            },
+            hooks:            get_strings(self.hooks, self.hooks_len)?
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            boot_manager:     match get_str(self.boot_manager) {
+                Ok(manager) => manager.parse()?,
+                Err(_) => BootManager::default(),
+            },
+            nixos:            self.nixos_config(),
         })
     }
+
+    /// Undefined fields are replaced with their defaults.
+    unsafe fn nixos_config(&self) -> NixosConfig {
+        let defaults = NixosConfig::default();
+        NixosConfig {
+            template:   get_str(self.nixos_template).ok().map_or(defaults.template, String::from),
+            seed:       get_str(self.nixos_seed).ok().map_or(defaults.seed, PathBuf::from),
+            installer:  get_str(self.nixos_installer).ok().map_or(defaults.installer, String::from),
+            extra_json: get_str(self.nixos_json).ok().map(String::from),
+            closure:    get_str(self.nixos_closure).ok().map(PathBuf::from),
+            flake:      self.nixos_flake != 0,
+            wipe:       self.nixos_wipe != 0,
+        }
+    }
 }
 
+/// A user account to create. Strings and arrays which are not defined must be null, and
+/// an ID of 0 or -1 is not defined, so that zero-initialized structures request the default
+/// IDs rather than those of root.
+///
+/// The fields after `password` were appended to this structure, which changes its size and
+/// layout: callers that were compiled against the three-field structure must be rebuilt.
 #[repr(C)]
 pub struct DistinstUserAccountCreate {
-    pub username: *const libc::c_char,
-    pub realname: *const libc::c_char,
-    pub password: *const libc::c_char,
+    pub username:     *const libc::c_char,
+    pub realname:     *const libc::c_char,
+    pub password:     *const libc::c_char,
+    pub groups:       *const *const libc::c_char,
+    pub groups_len:   libc::size_t,
+    pub shell:        *const libc::c_char,
+    pub uid:          i64,
+    pub gid:          i64,
+    pub ssh_keys:     *const *const libc::c_char,
+    pub ssh_keys_len: libc::size_t,
 }
 
 impl DistinstUserAccountCreate {
@@ -47,6 +98,35 @@ impl DistinstUserAccountCreate {
             username: get_str(self.username)?.to_owned(),
             realname: get_str(self.realname).ok().map(String::from),
             password: get_str(self.password).ok().map(String::from),
+            groups:   get_strings(self.groups, self.groups_len)?,
+            shell:    get_str(self.shell).ok().map(String::from),
+            uid:      get_id(self.uid)?,
+            gid:      get_id(self.gid)?,
+            ssh_keys: get_strings(self.ssh_keys, self.ssh_keys_len)?,
         })
     }
 }
+
+unsafe fn get_strings(
+    strings: *const *const libc::c_char,
+    len: libc::size_t,
+) -> io::Result<Vec<String>> {
+    if strings.is_null() {
+        return Ok(Vec::new());
+    }
+
+    slice::from_raw_parts(strings, len)
+        .iter()
+        .map(|&string| get_str(string).map(String::from))
+        .collect()
+}
+
+fn get_id(id: i64) -> io::Result<Option<u32>> {
+    if id == 0 || id == -1 {
+        return Ok(None);
+    }
+
+    u32::try_from(id)
+        .map(Some)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid ID: {}", id)))
+}
@@ -2,18 +2,21 @@ use libc;
 
 use std::{
     ffi::{CStr, CString, OsStr},
+    io,
     os::unix::ffi::OsStrExt,
     path::Path,
     ptr,
 };
 
 use distinst::{
-    BlockDeviceExt, DecryptionError, Disk, DiskExt, Disks, FileSystem, LogicalDevice,
-    LvmEncryption, PartitionBuilder, PartitionInfo, PartitionTable, PartitionTableExt, Sector,
-    SectorExt,
+    plan::{ChangeReport, DisksPlan, InstallPlan},
+    BlockDeviceExt, Bootloader, DecryptionError, Diagnostic, Disk, DiskExt, Disks, FileSystem,
+    LogicalDevice, LvmEncryption, PartitionBuilder, PartitionInfo, PartitionTable,
+    PartitionTableExt, Sector, SectorExt, Severity,
 };
 
-use super::{get_str, null_check};
+use super::{get_str, null_check, to_cstr};
+use crate::config::{DistinstConfig, DistinstUserAccountCreate};
 use crate::ffi::AsMutPtr;
 use crate::filesystem::DISTINST_FILE_SYSTEM;
 use crate::gen_object_ptr;
@@ -441,6 +444,31 @@ pub unsafe extern "C" fn distinst_disks_push(disks: *mut DistinstDisks, disk: *c
     disks.add(ptr::read(disk as *const Disk));
 }
 
+/// Attaches a disk image file to a loop device, and adds the device to the configuration.
+///
+/// Returns zero on success, or an errno value on failure.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_add_image(
+    disks: *mut DistinstDisks,
+    path: *const libc::c_char,
+) -> libc::c_int {
+    if disks.is_null() {
+        return libc::EIO;
+    }
+
+    let disks = &mut *(disks as *mut Disks);
+    match get_str(path) {
+        Ok(path) => match disks.add_image(path) {
+            Ok(()) => 0,
+            Err(why) => {
+                error!("failed to add disk image: {}", why);
+                libc::EIO
+            }
+        },
+        Err(why) => why.raw_os_error().unwrap_or(libc::EINVAL),
+    }
+}
+
 /// Probes the disk for information about every disk in the device.
 ///
 /// On error, a null pointer will be returned.
@@ -647,6 +675,31 @@ pub unsafe extern "C" fn distinst_disks_find_partition(
         .unwrap_or(ptr::null_mut())
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_resize_partition(
+    disks: *mut DistinstDisks,
+    path: *const libc::c_char,
+    partition: libc::c_int,
+    end: u64,
+) -> libc::c_int {
+    if null_check(disks).or_else(|_| null_check(path)).is_err() {
+        return -1;
+    }
+
+    let disks = &mut *(disks as *mut Disks);
+    let path = match get_str(path) {
+        Ok(path) => path,
+        Err(_) => return -1,
+    };
+
+    if let Err(why) = disks.resize_partition(path, partition, end) {
+        info!("libdistinst: unable to resize partition: {}", why);
+        -1
+    } else {
+        0
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_disks_decrypt_partition(
     disks: *mut DistinstDisks,
@@ -686,3 +739,136 @@ pub unsafe extern "C" fn distinst_disks_decrypt_partition(
         })
     })
 }
+
+/// Write the disks configuration, config, and optional user account as a plan file to `path`.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_export_plan(
+    disks: *const DistinstDisks,
+    config: *const DistinstConfig,
+    user: *const DistinstUserAccountCreate,
+    path: *const libc::c_char,
+) -> libc::c_int {
+    if disks.is_null() || config.is_null() {
+        return libc::EIO;
+    }
+
+    let result = (*config).as_config().and_then(|config| {
+        let users = if user.is_null() { Vec::new() } else { vec![(*user).as_config()?] };
+        let plan =
+            InstallPlan { disks: DisksPlan::from_disks(&*(disks as *const Disks)), config, users };
+
+        get_str(path).and_then(|path| plan.write(path).map_err(io::Error::from))
+    });
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            error!("failed to export install plan: {}", err);
+            err.raw_os_error().unwrap_or(libc::EIO)
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DISTINST_SEVERITY {
+    WARNING = 1,
+    ERROR = 2,
+}
+
+impl From<Severity> for DISTINST_SEVERITY {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Warning => DISTINST_SEVERITY::WARNING,
+            Severity::Error => DISTINST_SEVERITY::ERROR,
+        }
+    }
+}
+
+/// A problem with the disks configuration. The device and partition are null when the
+/// problem does not concern a specific device or partition.
+#[repr(C)]
+pub struct DistinstDiagnostic {
+    pub severity:   DISTINST_SEVERITY,
+    pub code:       *mut libc::c_char,
+    pub device:     *mut libc::c_char,
+    pub partition:  *mut libc::c_char,
+    pub message:    *mut libc::c_char,
+    pub suggestion: *mut libc::c_char,
+}
+
+impl From<Diagnostic> for DistinstDiagnostic {
+    fn from(diagnostic: Diagnostic) -> Self {
+        let path = |path: Option<&Path>| {
+            path.map_or(ptr::null_mut(), |path| to_cstr(path.to_string_lossy().into_owned()))
+        };
+
+        DistinstDiagnostic {
+            severity:   diagnostic.severity.into(),
+            code:       to_cstr(diagnostic.code.as_str().into()),
+            device:     path(diagnostic.device.as_ref().map(AsRef::as_ref)),
+            partition:  path(diagnostic.partition.as_ref().map(AsRef::as_ref)),
+            message:    to_cstr(diagnostic.message),
+            suggestion: to_cstr(diagnostic.suggestion),
+        }
+    }
+}
+
+/// Frees the strings within a diagnostic returned by `distinst_disks_validate`.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_diagnostic_destroy(diagnostic: *mut DistinstDiagnostic) {
+    if diagnostic.is_null() {
+        error!("DistinstDiagnostic was to be destroyed even though it is null");
+        return;
+    }
+
+    let diagnostic = &*diagnostic;
+    for &string in &[
+        diagnostic.code,
+        diagnostic.device,
+        diagnostic.partition,
+        diagnostic.message,
+        diagnostic.suggestion,
+    ] {
+        if !string.is_null() {
+            CString::from_raw(string);
+        }
+    }
+}
+
+/// Collects every problem with the partition configuration, for the detected boot loader.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_validate(
+    disks: *const DistinstDisks,
+    len: *mut libc::c_int,
+) -> *mut DistinstDiagnostic {
+    if null_check(disks).or_else(|_| null_check(len)).is_err() {
+        return ptr::null_mut();
+    }
+
+    let diagnostics = (*(disks as *const Disks))
+        .validate(Bootloader::detect())
+        .into_iter()
+        .map(DistinstDiagnostic::from)
+        .collect::<Vec<_>>();
+
+    *len = diagnostics.len() as libc::c_int;
+    Box::into_raw(diagnostics.into_boxed_slice()) as *mut DistinstDiagnostic
+}
+
+/// Describes the changes that installing the disks configuration will make, as a JSON array
+/// of devices and their changes. Returns null if the devices could not be probed.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_disks_changes(disks: *const DistinstDisks) -> *mut libc::c_char {
+    if null_check(disks).is_err() {
+        return ptr::null_mut();
+    }
+
+    match ChangeReport::new(&*(disks as *const Disks)) {
+        Ok(report) => to_cstr(report.to_json()),
+        Err(why) => {
+            error!("failed to generate changes report: {}", why);
+            ptr::null_mut()
+        }
+    }
+}
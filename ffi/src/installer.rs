@@ -1,11 +1,15 @@
 use libc;
 
-use std::io;
+use std::{ffi::CString, io, ptr, slice};
 
 use crate::config::DistinstConfig;
 use crate::disk::DistinstDisks;
-use distinst::{timezones::Region, Disks, Error, Installer, Status, Step};
-use crate::gen_object_ptr;
+use crate::lvm::DistinstLvmEncryption;
+use distinst::{
+    plan::InstallPlan, timezones::Region, CounterUnit, Disks, Error, Event, Installer,
+    LvmEncryption, Progress, Status, Step,
+};
+use crate::{gen_object_ptr, get_str};
 use crate::DistinstRegion;
 use crate::DistinstUserAccountCreate;
 
@@ -19,6 +23,7 @@ pub enum DISTINST_STEP {
     EXTRACT,
     CONFIGURE,
     BOOTLOADER,
+    HOOKS,
 }
 
 impl From<DISTINST_STEP> for Step {
@@ -31,6 +36,7 @@ impl From<DISTINST_STEP> for Step {
             EXTRACT => Step::Extract,
             CONFIGURE => Step::Configure,
             BOOTLOADER => Step::Bootloader,
+            HOOKS => Step::Hooks,
         }
     }
 }
@@ -45,6 +51,7 @@ impl From<Step> for DISTINST_STEP {
             Step::Extract => EXTRACT,
             Step::Configure => CONFIGURE,
             Step::Bootloader => BOOTLOADER,
+            Step::Hooks => HOOKS,
         }
     }
 }
@@ -73,6 +80,56 @@ pub struct DistinstStatus {
 pub type DistinstStatusCallback =
     extern "C" fn(status: *const DistinstStatus, user_data: *mut libc::c_void);
 
+/// The kind of progress described by an installer event
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum DISTINST_PROGRESS {
+    STARTED,
+    PERCENT,
+    SUBSTEP,
+    COUNTER,
+    LOG,
+    FINISHED,
+}
+
+/// The unit of an installer event's counter
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum DISTINST_COUNTER_UNIT {
+    ITEMS,
+    BYTES,
+}
+
+impl From<CounterUnit> for DISTINST_COUNTER_UNIT {
+    fn from(unit: CounterUnit) -> Self {
+        match unit {
+            CounterUnit::Items => DISTINST_COUNTER_UNIT::ITEMS,
+            CounterUnit::Bytes => DISTINST_COUNTER_UNIT::BYTES,
+        }
+    }
+}
+
+/// Installer progress event
+///
+/// The message is only valid for the duration of the callback. The expected count is 0
+/// when it is not known, and the remaining seconds are -1 until they can be estimated.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct DistinstEvent {
+    step:              DISTINST_STEP,
+    kind:              DISTINST_PROGRESS,
+    percent:           libc::c_int,
+    message:           *const libc::c_char,
+    unit:              DISTINST_COUNTER_UNIT,
+    done:              u64,
+    expected:          u64,
+    remaining_seconds: i64,
+}
+
+/// Installer progress event callback
+pub type DistinstEventCallback =
+    extern "C" fn(event: *const DistinstEvent, user_data: *mut libc::c_void);
+
 /// Installer timezone callback
 pub type DistinstTimezoneCallback =
     extern "C" fn(user_data: *mut libc::c_void) -> *const DistinstRegion;
@@ -81,6 +138,13 @@ pub type DistinstTimezoneCallback =
 pub type DistinstUserAccountCallback =
     extern "C" fn(user_data: *mut libc::c_void) -> DistinstUserAccountCreate;
 
+/// Installer callback for creating multiple user accounts, which returns an array of
+/// accounts and stores its length in `len`.
+pub type DistinstUserAccountsCallback = extern "C" fn(
+    len: *mut libc::c_int,
+    user_data: *mut libc::c_void,
+) -> *const DistinstUserAccountCreate;
+
 /// An installer object
 #[repr(C)]
 pub struct DistinstInstaller;
@@ -147,6 +211,62 @@ pub unsafe extern "C" fn distinst_installer_on_status(
     });
 }
 
+/// Set the installer progress event callback
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_on_event(
+    installer: *mut DistinstInstaller,
+    callback: DistinstEventCallback,
+    user_data: *mut libc::c_void,
+) {
+    (*(installer as *mut Installer)).on_event(move |event: &Event| {
+        let mut ffi_event = DistinstEvent {
+            step:              event.step.into(),
+            kind:              DISTINST_PROGRESS::FINISHED,
+            percent:           0,
+            message:           ptr::null(),
+            unit:              DISTINST_COUNTER_UNIT::ITEMS,
+            done:              0,
+            expected:          0,
+            remaining_seconds: event.remaining.map_or(-1, |time| time.as_secs() as i64),
+        };
+
+        let message = match event.progress {
+            Progress::Started(ref message) => {
+                ffi_event.kind = DISTINST_PROGRESS::STARTED;
+                Some(message)
+            }
+            Progress::Percent(percent) => {
+                ffi_event.kind = DISTINST_PROGRESS::PERCENT;
+                ffi_event.percent = percent;
+                None
+            }
+            Progress::SubStep(ref message) => {
+                ffi_event.kind = DISTINST_PROGRESS::SUBSTEP;
+                Some(message)
+            }
+            Progress::Counter(counter) => {
+                ffi_event.kind = DISTINST_PROGRESS::COUNTER;
+                ffi_event.unit = counter.unit.into();
+                ffi_event.done = counter.done;
+                ffi_event.expected = counter.expected.unwrap_or(0);
+                None
+            }
+            Progress::Log(ref message) => {
+                ffi_event.kind = DISTINST_PROGRESS::LOG;
+                Some(message)
+            }
+            Progress::Finished => None,
+        };
+
+        let message = message.and_then(|message| CString::new(message.as_str()).ok());
+        if let Some(ref message) = message {
+            ffi_event.message = message.as_ptr();
+        }
+
+        callback(&ffi_event as *const DistinstEvent, user_data)
+    });
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn distinst_installer_set_timezone_callback(
     installer: *mut DistinstInstaller,
@@ -168,6 +288,32 @@ pub unsafe extern "C" fn distinst_installer_set_user_callback(
     });
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_set_users_callback(
+    installer: *mut DistinstInstaller,
+    callback: DistinstUserAccountsCallback,
+    user_data: *mut libc::c_void,
+) {
+    (*(installer as *mut Installer)).set_users_callback(move || {
+        let mut len = 0;
+        let users = callback(&mut len, user_data);
+        if users.is_null() {
+            return Vec::new();
+        }
+
+        slice::from_raw_parts(users, len as usize)
+            .iter()
+            .filter_map(|user| match user.as_config() {
+                Ok(user) => Some(user),
+                Err(why) => {
+                    error!("skipping invalid user account from the users callback: {}", why);
+                    None
+                }
+            })
+            .collect()
+    });
+}
+
 /// Install using this installer, whilst retaining home & user accounts.
 #[no_mangle]
 pub unsafe extern "C" fn distinst_installer_install(
@@ -198,6 +344,135 @@ pub unsafe extern "C" fn distinst_installer_install(
     }
 }
 
+/// Install the disks, config, and user account described by the plan file at `path`.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_install_plan(
+    installer: *mut DistinstInstaller,
+    path: *const libc::c_char,
+) -> libc::c_int {
+    if installer.is_null() {
+        return libc::EIO;
+    }
+
+    let plan = get_str(path).and_then(|path| InstallPlan::from_file(path).map_err(io::Error::from));
+
+    match plan {
+        Ok(plan) => match (*(installer as *mut Installer)).install_plan(plan) {
+            Ok(()) => 0,
+            Err(err) => {
+                info!("Install error: {}", err);
+                err.raw_os_error().unwrap_or(libc::EIO)
+            }
+        },
+        Err(err) => {
+            info!("Plan error: {}", err);
+            let errno = err.raw_os_error().unwrap_or(libc::EINVAL);
+            (*(installer as *mut Installer)).emit_error(&Error { step: Step::Init, err });
+            errno
+        }
+    }
+}
+
+/// Set the path where the installation checkpoint is written, and read from when resuming.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_set_checkpoint_path(
+    installer: *mut DistinstInstaller,
+    path: *const libc::c_char,
+) -> libc::c_int {
+    if installer.is_null() {
+        return libc::EIO;
+    }
+
+    match get_str(path) {
+        Ok(path) => {
+            (*(installer as *mut Installer)).set_checkpoint_path(path);
+            0
+        }
+        Err(err) => err.raw_os_error().unwrap_or(libc::EINVAL),
+    }
+}
+
+/// Set the directory where partition tables are saved before the disks are modified.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_set_snapshot_dir(
+    installer: *mut DistinstInstaller,
+    path: *const libc::c_char,
+) -> libc::c_int {
+    if installer.is_null() {
+        return libc::EIO;
+    }
+
+    match get_str(path) {
+        Ok(path) => {
+            (*(installer as *mut Installer)).set_snapshot_dir(path);
+            0
+        }
+        Err(err) => err.raw_os_error().unwrap_or(libc::EINVAL),
+    }
+}
+
+/// Restore the partition tables which were saved by a failed installation.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_restore_snapshots(
+    installer: *const DistinstInstaller,
+) -> libc::c_int {
+    if installer.is_null() {
+        return libc::EIO;
+    }
+
+    match (*(installer as *const Installer)).restore_snapshots() {
+        Ok(()) => 0,
+        Err(err) => {
+            info!("Restore error: {}", err);
+            err.raw_os_error().unwrap_or(libc::EIO)
+        }
+    }
+}
+
+/// Resume an installation which failed after partitioning, from its checkpoint.
+///
+/// The `encryption` array supplies the passwords of the physical volumes which are reopened.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_resume(
+    installer: *mut DistinstInstaller,
+    config: *const DistinstConfig,
+    encryption: *const DistinstLvmEncryption,
+    len: libc::c_int,
+) -> libc::c_int {
+    if installer.is_null() || config.is_null() || (encryption.is_null() && len != 0) {
+        return libc::EIO;
+    }
+
+    let encryption = if len <= 0 { &[] } else { slice::from_raw_parts(encryption, len as usize) };
+    let mut keys = Vec::with_capacity(encryption.len());
+    for enc in encryption {
+        let pv = match get_str(enc.physical_volume) {
+            Ok(pv) => pv.to_owned(),
+            Err(_) => return libc::EINVAL,
+        };
+
+        let password = get_str(enc.password).ok().map(String::from);
+        let keydata = get_str(enc.keydata).ok().map(String::from);
+        keys.push(LvmEncryption::new(pv, password, keydata));
+    }
+
+    match (*config).as_config() {
+        Ok(config) => match (*(installer as *mut Installer)).resume(&config, &keys) {
+            Ok(()) => 0,
+            Err(err) => {
+                info!("Resume error: {}", err);
+                err.raw_os_error().unwrap_or(libc::EIO)
+            }
+        },
+        Err(err) => {
+            info!("Config error: {}", err);
+            let errno = err.raw_os_error().unwrap_or(libc::EIO);
+            (*(installer as *mut Installer)).emit_error(&Error { step: Step::Init, err });
+            errno
+        }
+    }
+}
+
 /// Destroy an installer object
 #[no_mangle]
 pub unsafe extern "C" fn distinst_installer_destroy(installer: *mut DistinstInstaller) {
@@ -0,0 +1,378 @@
+//! The distribution-specific stages of an installation, which are performed by a backend
+//! selected for the image that is being installed.
+
+use super::{steps, Counter, CounterUnit, Progress, RecoveryEnv, UserAccountCreate};
+use crate::{
+    disks::{Bootloader, Disks},
+    errors::IoContext,
+    squashfs,
+    timezones::Region,
+    Config,
+};
+use os_release::OsRelease;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The family of distribution that is being installed, which determines the backend.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Distribution {
+    /// Debian-based images, which are extracted from a squashfs and configured in a chroot.
+    Debian,
+    /// NixOS images, which are built into the target with `nixos-install`.
+    Nixos,
+}
+
+impl Distribution {
+    /// Determines the distribution from the live system's os-release, defaulting to Debian.
+    pub fn detect() -> Distribution {
+        match OsRelease::new() {
+            Ok(ref release) if Distribution::is_nixos(release) => Distribution::Nixos,
+            Ok(_) => Distribution::Debian,
+            Err(why) => {
+                warn!("unable to read os-release, so assuming a Debian image: {}", why);
+                Distribution::Debian
+            }
+        }
+    }
+
+    /// Creates the backend which installs this distribution.
+    pub fn backend(self) -> Box<dyn DistributionBackend> {
+        match self {
+            Distribution::Debian => Box::new(DebianBackend::default()),
+            Distribution::Nixos => Box::new(NixosBackend),
+        }
+    }
+
+    fn is_nixos(release: &OsRelease) -> bool {
+        release.id == "nixos" || release.id_like.split_whitespace().any(|id| id == "nixos")
+    }
+}
+
+impl FromStr for Distribution {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> io::Result<Self> {
+        match input {
+            "debian" => Ok(Distribution::Debian),
+            "nixos" => Ok(Distribution::Nixos),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a supported distribution", input),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Distribution::Debian => "debian",
+            Distribution::Nixos => "nixos",
+        })
+    }
+}
+
+/// The boot loader which is installed to an EFI system partition.
+///
+/// BIOS installs are always booted with GRUB.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BootManager {
+    Grub,
+    SystemdBoot,
+}
+
+impl Default for BootManager {
+    fn default() -> Self { BootManager::Grub }
+}
+
+impl FromStr for BootManager {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> io::Result<Self> {
+        match input {
+            "grub" => Ok(BootManager::Grub),
+            "systemd-boot" => Ok(BootManager::SystemdBoot),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a supported boot manager", input),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for BootManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BootManager::Grub => "grub",
+            BootManager::SystemdBoot => "systemd-boot",
+        })
+    }
+}
+
+/// How NixOS configurations are generated, and built into the target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NixosConfig {
+    /// The conf-tool template that the configuration is initialized from.
+    pub template:   String,
+    /// A JSON file which the generated `conf-tool.json` is based on, if it exists.
+    pub seed:       PathBuf,
+    /// The command which builds the system into the target, in place of `nixos-install`.
+    pub installer:  String,
+    /// A JSON object which is merged into the generated `conf-tool.json`, on top of the seed.
+    pub extra_json: Option<String>,
+    /// A prebuilt system closure in the live image's store, which is installed instead of
+    /// building the generated configuration.
+    pub closure:    Option<PathBuf>,
+    /// Generates a `flake.nix`, pinned to the live image's nixpkgs, and installs the system
+    /// from it with `nixos-install --flake`.
+    pub flake:      bool,
+    /// Removes everything from the target when the install fails, so that it may be retried
+    /// from an empty file system.
+    pub wipe:       bool,
+}
+
+impl Default for NixosConfig {
+    fn default() -> Self {
+        NixosConfig {
+            template:   "solaros".into(),
+            seed:       PathBuf::from("/etc/conf-tool-seed.json"),
+            installer:  "nixos-install-wrapped".into(),
+            extra_json: None,
+            closure:    None,
+            flake:      false,
+            wipe:       false,
+        }
+    }
+}
+
+/// The mounted target, and the configuration to apply to it.
+pub struct InstallTarget<'a> {
+    pub disks:      &'a Disks,
+    pub mount_dir:  &'a Path,
+    pub config:     &'a Config,
+    pub bootloader: Bootloader,
+    pub region:     Option<&'a Region>,
+    pub users:      &'a [UserAccountCreate],
+    /// Mount targets of the partitions which the installation formatted, as recorded in its
+    /// checkpoint, so that they are also known when the installation is resumed.
+    pub formatted:  &'a [PathBuf],
+}
+
+/// Performs each distribution-specific stage of an installation.
+///
+/// Stages are executed in order, each as its own `Step`. A stage may be skipped when an
+/// installation is resumed, so backends must not depend on state from a previous stage
+/// being present.
+pub trait DistributionBackend {
+    /// Validates the partitions that have been configured, before anything is modified.
+    fn verify_partitions(
+        &self,
+        disks: &Disks,
+        bootloader: Bootloader,
+        _config: &Config,
+    ) -> io::Result<()> {
+        disks.verify_partitions(bootloader)
+    }
+
+    /// Validates the configuration, and prepares the disks before they are partitioned.
+    fn prepare(
+        &mut self,
+        disks: &mut Disks,
+        config: &Config,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<()>;
+
+    /// Populates the mounted target with the base system.
+    fn populate(
+        &mut self,
+        target: &InstallTarget,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<()>;
+
+    /// Configures the system within the target, returning the configuration files that
+    /// were generated, relative to the root of the target.
+    fn configure(
+        &mut self,
+        target: &InstallTarget,
+        recovery_conf: Option<&mut RecoveryEnv>,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<Vec<PathBuf>>;
+
+    /// Installs the boot loader to the target.
+    fn bootloader(
+        &mut self,
+        target: &InstallTarget,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<()>;
+}
+
+/// Extracts a squashfs image, and configures it from within a chroot.
+#[derive(Default)]
+pub struct DebianBackend {
+    squashfs:    Option<PathBuf>,
+    remove_pkgs: Option<Vec<String>>,
+    os_release:  Option<OsRelease>,
+}
+
+impl DebianBackend {
+    /// The os-release of the image, which may differ from that of the live system.
+    fn os_release(&mut self, mount_dir: &Path) -> io::Result<&OsRelease> {
+        if self.os_release.is_none() {
+            let release =
+                OsRelease::new_from(&mount_dir.join("etc/os-release")).with_context(|why| {
+                    format!("failed to parse /etc/os-release from extracted image: {}", why)
+                })?;
+            self.os_release = Some(release);
+        }
+
+        Ok(self.os_release.as_ref().expect("os-release was just read"))
+    }
+}
+
+impl DistributionBackend for DebianBackend {
+    fn prepare(
+        &mut self,
+        disks: &mut Disks,
+        config: &Config,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<()> {
+        let (squashfs, remove_pkgs) = steps::initialize(disks, config, callback)?;
+        self.squashfs = Some(squashfs);
+        self.remove_pkgs = Some(remove_pkgs);
+        Ok(())
+    }
+
+    fn populate(
+        &mut self,
+        target: &InstallTarget,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<()> {
+        let squashfs = match self.squashfs {
+            Some(ref squashfs) => squashfs.clone(),
+            None => Path::new(&target.config.squashfs)
+                .canonicalize()
+                .with_context(|why| format!("config.squashfs: {}", why))?,
+        };
+
+        info!("Extracting {}", squashfs.display());
+        callback(Progress::SubStep(format!("extracting {}", squashfs.display())));
+
+        // The extractor only reports a percentage, from which the bytes of the archive that
+        // have been extracted are estimated.
+        let size = fs::metadata(&squashfs).ok().map(|meta| meta.len());
+        squashfs::extract(&squashfs, target.mount_dir, |percent| {
+            callback(Progress::Percent(percent));
+            if let Some(size) = size {
+                callback(Progress::Counter(Counter {
+                    unit:     CounterUnit::Bytes,
+                    done:     size * percent as u64 / 100,
+                    expected: Some(size),
+                }));
+            }
+        })?;
+        self.os_release = None;
+        self.os_release(target.mount_dir).map(|_| ())
+    }
+
+    fn configure(
+        &mut self,
+        target: &InstallTarget,
+        recovery_conf: Option<&mut RecoveryEnv>,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<Vec<PathBuf>> {
+        let remove_pkgs = match self.remove_pkgs.take() {
+            Some(remove_pkgs) => remove_pkgs,
+            None => steps::remove_packages(target.config)?,
+        };
+
+        let os_release = self.os_release(target.mount_dir)?;
+        steps::configure(
+            recovery_conf,
+            target.disks,
+            target.mount_dir,
+            target.config,
+            os_release,
+            target.region,
+            target.users,
+            &remove_pkgs,
+            callback,
+        )?;
+
+        Ok(vec![PathBuf::from("etc/fstab"), PathBuf::from("etc/crypttab")])
+    }
+
+    fn bootloader(
+        &mut self,
+        target: &InstallTarget,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<()> {
+        let os_release = self.os_release(target.mount_dir)?;
+        steps::bootloader(
+            target.disks,
+            target.mount_dir,
+            target.bootloader,
+            target.config,
+            os_release,
+            callback,
+        )
+    }
+}
+
+/// Generates a NixOS configuration for the target, and builds it with `nixos-install`.
+pub struct NixosBackend;
+
+impl DistributionBackend for NixosBackend {
+    /// NixOS may mount the ESP at `/boot`, and systemd-boot requires a larger ESP. Offline
+    /// installs are also checked before the disks are modified.
+    fn verify_partitions(
+        &self,
+        disks: &Disks,
+        bootloader: Bootloader,
+        config: &Config,
+    ) -> io::Result<()> {
+        steps::validate_offline(config)?;
+        steps::validate_boot(disks, bootloader, config.boot_manager)
+    }
+
+    /// The live image has nothing to prepare, as the system is built from its configuration.
+    fn prepare(
+        &mut self,
+        _: &mut Disks,
+        _: &Config,
+        _: &mut dyn FnMut(Progress),
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The target is populated by `nixos-install` when the system is configured.
+    fn populate(&mut self, _: &InstallTarget, _: &mut dyn FnMut(Progress)) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn configure(
+        &mut self,
+        target: &InstallTarget,
+        recovery_conf: Option<&mut RecoveryEnv>,
+        callback: &mut dyn FnMut(Progress),
+    ) -> io::Result<Vec<PathBuf>> {
+        steps::nixos(
+            recovery_conf,
+            target.bootloader,
+            target.disks,
+            target.mount_dir,
+            target.config,
+            target.region,
+            target.users,
+            target.formatted,
+            callback,
+        )
+    }
+
+    /// The boot loader is installed by `nixos-install`, as described by `boot.nix`.
+    fn bootloader(&mut self, _: &InstallTarget, _: &mut dyn FnMut(Progress)) -> io::Result<()> {
+        Ok(())
+    }
+}
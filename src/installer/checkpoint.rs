@@ -0,0 +1,496 @@
+//! Records the progress of an installation, so that an installation which fails after its
+//! partitions have been committed may be resumed with `Installer::resume`.
+
+use super::Step;
+use crate::{
+    disks::{DecryptionError, DiskError, Disks, LvmEncryption, FORMAT},
+    misc,
+};
+use disk_types::FileSystem;
+use json::{object, JsonValue};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The version of the checkpoint format that is read and written by this library.
+const CHECKPOINT_VERSION: u64 = 1;
+
+const STEPS: &[(&str, Step)] = &[
+    ("backup", Step::Backup),
+    ("init", Step::Init),
+    ("partition", Step::Partition),
+    ("extract", Step::Extract),
+    ("configure", Step::Configure),
+    ("bootloader", Step::Bootloader),
+    ("hooks", Step::Hooks),
+];
+
+#[derive(Debug, Fail)]
+pub enum CheckpointError {
+    #[fail(display = "failed to read checkpoint at {:?}: {}", path, why)]
+    Read { path: PathBuf, why: io::Error },
+    #[fail(display = "failed to write checkpoint to {:?}: {}", path, why)]
+    Write { path: PathBuf, why: io::Error },
+    #[fail(display = "checkpoint is not valid: {}", why)]
+    Parse { why: String },
+    #[fail(display = "partitioning was not completed, so the installation must be restarted")]
+    NotPartitioned,
+    #[fail(display = "partition {:?} for {:?} no longer exists", device, target)]
+    PartitionMissing { device: PathBuf, target: PathBuf },
+    #[fail(display = "partition {:?} for {:?} has changed since it was committed", device, target)]
+    PartitionChanged { device: PathBuf, target: PathBuf },
+    #[fail(display = "no password was supplied to open {:?} as '{}'", device, physical_volume)]
+    PasswordMissing { device: PathBuf, physical_volume: String },
+    #[fail(display = "failed to reopen encrypted volume: {}", why)]
+    Reopen { why: DecryptionError },
+    #[fail(display = "failed to load volume groups: {}", why)]
+    VolumeGroups { why: DiskError },
+}
+
+impl From<CheckpointError> for io::Error {
+    fn from(why: CheckpointError) -> io::Error {
+        let kind = match why {
+            CheckpointError::Read { ref why, .. } | CheckpointError::Write { ref why, .. } => {
+                why.kind()
+            }
+            _ => io::ErrorKind::InvalidData,
+        };
+
+        io::Error::new(kind, format!("{}", why))
+    }
+}
+
+/// A committed partition which was mounted to the target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckpointPartition {
+    pub device:    PathBuf,
+    pub target:    PathBuf,
+    pub fs:        Option<FileSystem>,
+    pub uuid:      Option<String>,
+    /// Whether the partition was formatted by the installation.
+    pub formatted: bool,
+}
+
+/// The partition which stores the keyfile of a LUKS partition, and where it is mounted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckpointKeyfile {
+    pub id:     String,
+    pub device: PathBuf,
+    pub target: PathBuf,
+}
+
+/// A LUKS partition which was opened while the disks were committed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckpointVolume {
+    pub device:          PathBuf,
+    pub physical_volume: String,
+    pub keyfile:         Option<CheckpointKeyfile>,
+}
+
+impl CheckpointVolume {
+    /// The key that the volume is reopened with. As in the crypttab, the keyfile that the
+    /// volume was encrypted with is preferred to a password from `keys`.
+    fn key(&self, keys: &[LvmEncryption]) -> Result<LvmEncryption, CheckpointError> {
+        if let Some(ref keyfile) = self.keyfile {
+            return Ok(LvmEncryption {
+                physical_volume: self.physical_volume.clone(),
+                password:        None,
+                keydata:         Some((
+                    keyfile.id.clone(),
+                    Some((keyfile.device.clone(), keyfile.target.clone())),
+                )),
+            });
+        }
+
+        keys.iter()
+            .find(|key| key.physical_volume == self.physical_volume && key.password.is_some())
+            .cloned()
+            .ok_or_else(|| CheckpointError::PasswordMissing {
+                device:          self.device.clone(),
+                physical_volume: self.physical_volume.clone(),
+            })
+    }
+}
+
+/// The steps of an installation which have been completed, and their outputs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Checkpoint {
+    /// Steps which completed successfully, in the order that they were completed.
+    pub steps:      Vec<Step>,
+    /// Partitions that were committed by the partitioning step.
+    pub partitions: Vec<CheckpointPartition>,
+    /// Encrypted volumes that the committed partitions are stored within.
+    pub volumes:    Vec<CheckpointVolume>,
+    /// Configuration files generated for the target, relative to its root.
+    pub configs:    Vec<PathBuf>,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint stored at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Checkpoint, CheckpointError> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|why| CheckpointError::Read { path: path.to_path_buf(), why })?;
+        Checkpoint::parse(&data)
+    }
+
+    /// Parses a checkpoint from its JSON representation.
+    pub fn parse(data: &str) -> Result<Checkpoint, CheckpointError> {
+        let value =
+            json::parse(data).map_err(|why| CheckpointError::Parse { why: why.to_string() })?;
+
+        match value["version"].as_u64() {
+            Some(CHECKPOINT_VERSION) => (),
+            _ => return Err(parse_error("version", &value["version"])),
+        }
+
+        let steps = value["steps"]
+            .members()
+            .map(|step| {
+                step.as_str()
+                    .and_then(|step| STEPS.iter().find(|&&(name, _)| name == step))
+                    .map(|&(_, step)| step)
+                    .ok_or_else(|| parse_error("steps", step))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let partitions = value["partitions"]
+            .members()
+            .map(|part| {
+                let fs = match part["fs"].as_str() {
+                    Some(fs) => Some(
+                        fs.parse::<FileSystem>()
+                            .map_err(|_| parse_error("partitions.fs", &part["fs"]))?,
+                    ),
+                    None => None,
+                };
+
+                Ok(CheckpointPartition {
+                    device: path(&part["device"], "partitions.device")?,
+                    target: path(&part["target"], "partitions.target")?,
+                    fs,
+                    uuid: part["uuid"].as_str().map(String::from),
+                    formatted: part["formatted"].as_bool().unwrap_or(false),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let volumes = value["volumes"]
+            .members()
+            .map(|volume| {
+                let keyfile = &volume["keyfile"];
+                let keyfile = if keyfile.is_null() {
+                    None
+                } else {
+                    Some(CheckpointKeyfile {
+                        id:     keyfile["id"]
+                            .as_str()
+                            .map(String::from)
+                            .ok_or_else(|| parse_error("volumes.keyfile.id", &keyfile["id"]))?,
+                        device: path(&keyfile["device"], "volumes.keyfile.device")?,
+                        target: path(&keyfile["target"], "volumes.keyfile.target")?,
+                    })
+                };
+
+                Ok(CheckpointVolume {
+                    device: path(&volume["device"], "volumes.device")?,
+                    physical_volume: volume["physical_volume"]
+                        .as_str()
+                        .map(String::from)
+                        .ok_or_else(|| {
+                            parse_error("volumes.physical_volume", &volume["physical_volume"])
+                        })?,
+                    keyfile,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let configs = value["configs"]
+            .members()
+            .map(|config| path(config, "configs"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Checkpoint { steps, partitions, volumes, configs })
+    }
+
+    /// Serializes the checkpoint into its JSON representation.
+    pub fn to_json(&self) -> String {
+        let steps = self
+            .steps
+            .iter()
+            .filter_map(|step| STEPS.iter().find(|&&(_, s)| s == *step).map(|&(name, _)| name))
+            .collect::<Vec<_>>();
+
+        let partitions = self
+            .partitions
+            .iter()
+            .map(|part| {
+                object! {
+                    "device" => part.device.to_string_lossy().into_owned(),
+                    "target" => part.target.to_string_lossy().into_owned(),
+                    "fs" => part.fs.map(<&'static str>::from),
+                    "uuid" => part.uuid.clone(),
+                    "formatted" => part.formatted
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let volumes = self
+            .volumes
+            .iter()
+            .map(|volume| {
+                object! {
+                    "device" => volume.device.to_string_lossy().into_owned(),
+                    "physical_volume" => volume.physical_volume.clone(),
+                    "keyfile" => volume.keyfile.as_ref().map(|keyfile| object! {
+                        "id" => keyfile.id.clone(),
+                        "device" => keyfile.device.to_string_lossy().into_owned(),
+                        "target" => keyfile.target.to_string_lossy().into_owned()
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let configs = self
+            .configs
+            .iter()
+            .map(|config| config.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        object! {
+            "version" => CHECKPOINT_VERSION,
+            "steps" => steps,
+            "partitions" => partitions,
+            "volumes" => volumes,
+            "configs" => configs
+        }
+        .pretty(4)
+    }
+
+    /// Writes the checkpoint to the given path.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json())
+            .map_err(|why| CheckpointError::Write { path: path.to_path_buf(), why })
+    }
+
+    /// Whether the given step has been completed.
+    pub fn is_complete(&self, step: Step) -> bool { self.steps.contains(&step) }
+
+    /// Marks the given step as completed.
+    pub fn complete(&mut self, step: Step) {
+        if !self.is_complete(step) {
+            self.steps.push(step);
+        }
+    }
+
+    /// The mount targets of the partitions which the installation formatted.
+    pub fn formatted_targets(&self) -> Vec<PathBuf> {
+        self.partitions
+            .iter()
+            .filter(|part| part.formatted)
+            .map(|part| part.target.clone())
+            .collect()
+    }
+
+    /// Records every partition with a mount target, and every LUKS partition that they may
+    /// be stored within, after the disks have been committed.
+    pub fn record_partitions(&mut self, disks: &Disks) {
+        self.partitions = disks
+            .get_partitions()
+            .filter_map(|part| {
+                part.target.as_ref().map(|target| CheckpointPartition {
+                    device:    part.device_path.clone(),
+                    target:    target.clone(),
+                    fs:        part.filesystem,
+                    uuid:      part.identifiers.uuid.clone(),
+                    formatted: part.flag_is_enabled(FORMAT),
+                })
+            })
+            .collect();
+
+        self.volumes = disks
+            .get_physical_partitions()
+            .filter_map(|part| match part.volume_group {
+                Some((_, Some(ref enc))) => Some(CheckpointVolume {
+                    device:          part.device_path.clone(),
+                    physical_volume: enc.physical_volume.clone(),
+                    keyfile:         match enc.keydata {
+                        Some((ref id, Some((ref device, ref target)))) => Some(CheckpointKeyfile {
+                            id:     id.clone(),
+                            device: device.clone(),
+                            target: target.clone(),
+                        }),
+                        _ => None,
+                    },
+                }),
+                _ => None,
+            })
+            .collect();
+    }
+
+    /// Loads the volume groups of freshly-probed disks, and reopens each of the recorded
+    /// LUKS partitions with its keyfile, or the password for its physical volume in `keys`,
+    /// so that the logical volumes within them may be validated by `restore_targets`.
+    pub fn reopen_volumes(
+        &self,
+        disks: &mut Disks,
+        keys: &[LvmEncryption],
+    ) -> Result<(), CheckpointError> {
+        disks.initialize_volume_groups().map_err(|why| CheckpointError::VolumeGroups { why })?;
+
+        for volume in &self.volumes {
+            let key = volume.key(keys)?;
+            disks
+                .decrypt_partition(&volume.device, &key)
+                .map_err(|why| CheckpointError::Reopen { why })?;
+        }
+
+        Ok(())
+    }
+
+    /// Validates freshly-probed disks against the partitions committed by the failed
+    /// installation, and assigns each recorded partition its mount target once more.
+    pub fn restore_targets(&self, disks: &mut Disks) -> Result<(), CheckpointError> {
+        if !self.is_complete(Step::Partition) {
+            return Err(CheckpointError::NotPartitioned);
+        }
+
+        for record in &self.partitions {
+            // Partitions are found by their canonical paths, as logical volumes are symlinks.
+            let device = misc::canonicalize(&record.device);
+            let part = disks.get_partition_by_path_mut(&device).ok_or_else(|| {
+                CheckpointError::PartitionMissing {
+                    device: record.device.clone(),
+                    target: record.target.clone(),
+                }
+            })?;
+
+            let uuid_changed = match (&record.uuid, &part.identifiers.uuid) {
+                (Some(ref old), Some(ref new)) => old != new,
+                _ => false,
+            };
+
+            if uuid_changed || part.filesystem != record.fs {
+                return Err(CheckpointError::PartitionChanged {
+                    device: record.device.clone(),
+                    target: record.target.clone(),
+                });
+            }
+
+            part.set_mount(record.target.clone());
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_error(field: &str, value: &JsonValue) -> CheckpointError {
+    CheckpointError::Parse { why: format!("invalid value for '{}': {}", field, value.dump()) }
+}
+
+fn path(value: &JsonValue, field: &str) -> Result<PathBuf, CheckpointError> {
+    value.as_str().map(PathBuf::from).ok_or_else(|| parse_error(field, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let checkpoint = Checkpoint {
+            steps:      vec![Step::Init, Step::Partition],
+            partitions: vec![
+                CheckpointPartition {
+                    device:    "/dev/sda2".into(),
+                    target:    "/".into(),
+                    fs:        Some(FileSystem::Ext4),
+                    uuid:      Some("2f1a3b5c-0000-4000-8000-000000000000".into()),
+                    formatted: true,
+                },
+                CheckpointPartition {
+                    device:    "/dev/sda4".into(),
+                    target:    "/home".into(),
+                    fs:        Some(FileSystem::Xfs),
+                    uuid:      None,
+                    formatted: false,
+                },
+            ],
+            volumes:    vec![
+                CheckpointVolume {
+                    device:          "/dev/sda3".into(),
+                    physical_volume: "cryptdata".into(),
+                    keyfile:         None,
+                },
+                CheckpointVolume {
+                    device:          "/dev/sdb1".into(),
+                    physical_volume: "cryptstore".into(),
+                    keyfile:         Some(keyfile()),
+                },
+            ],
+            configs:    vec!["etc/nixos/boot.nix".into()],
+        };
+
+        let parsed = Checkpoint::parse(&checkpoint.to_json()).unwrap();
+        assert_eq!(parsed, checkpoint);
+        assert!(parsed.is_complete(Step::Partition));
+        assert!(!parsed.is_complete(Step::Configure));
+        assert_eq!(parsed.formatted_targets(), vec![PathBuf::from("/")]);
+
+        assert!(Checkpoint::parse(r#"{ "version": 2 }"#).is_err());
+        assert!(Checkpoint::parse(r#"{ "version": 1, "steps": ["unknown"] }"#).is_err());
+    }
+
+    #[test]
+    fn reopen_without_password() {
+        let checkpoint = Checkpoint {
+            volumes: vec![CheckpointVolume {
+                device:          "/dev/sda3".into(),
+                physical_volume: "cryptdata".into(),
+                keyfile:         None,
+            }],
+            ..Checkpoint::default()
+        };
+
+        let keys = [LvmEncryption::new("cryptdata".into(), None, Some("key".into()))];
+        match checkpoint.reopen_volumes(&mut Disks::default(), &keys) {
+            Err(CheckpointError::PasswordMissing { ref physical_volume, .. })
+                if physical_volume == "cryptdata" => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reopen_with_keyfile() {
+        let volume = CheckpointVolume {
+            device:          "/dev/sdb1".into(),
+            physical_volume: "cryptstore".into(),
+            keyfile:         Some(keyfile()),
+        };
+
+        let expected = Some(("store".into(), Some(("/dev/sda4".into(), "/etc/cryptkeys".into()))));
+        let key = volume.key(&[]).unwrap();
+        assert_eq!(key.password, None);
+        assert_eq!(key.keydata, expected);
+
+        let password = LvmEncryption::new("cryptstore".into(), Some("pass".into()), None);
+        assert_eq!(volume.key(&[password]).unwrap().keydata, expected);
+
+        // No password is required, so reopening only fails on the missing partition.
+        let checkpoint = Checkpoint { volumes: vec![volume], ..Checkpoint::default() };
+        match checkpoint.reopen_volumes(&mut Disks::default(), &[]) {
+            Err(CheckpointError::Reopen { .. }) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    fn keyfile() -> CheckpointKeyfile {
+        CheckpointKeyfile {
+            id:     "store".into(),
+            device: "/dev/sda4".into(),
+            target: "/etc/cryptkeys".into(),
+        }
+    }
+}
@@ -1,12 +1,28 @@
 pub mod bitflags;
 pub mod traits;
 
+mod backend;
+mod checkpoint;
 mod conf;
+mod progress;
+mod snapshot;
 mod state;
 
 pub(crate) mod steps;
 
-pub use self::{conf::RecoveryEnv, steps::Step};
+pub use self::{
+    backend::{
+        BootManager, DebianBackend, Distribution, DistributionBackend, InstallTarget, NixosBackend,
+        NixosConfig,
+    },
+    checkpoint::{
+        Checkpoint, CheckpointError, CheckpointKeyfile, CheckpointPartition, CheckpointVolume,
+    },
+    conf::RecoveryEnv,
+    progress::{Counter, CounterUnit, Event, Progress},
+    snapshot::TableSnapshot,
+    steps::{NixosInstallError, NixosPhase, Step, NIXOS_INSTALL_LOG},
+};
 
 use self::state::InstallerState;
 
@@ -15,17 +31,16 @@ use crate::auto::{
     AccountFiles, Backup, ReinstallError,
 };
 use disk_types::BlockDeviceExt;
-use crate::disks::{Bootloader, Disks};
+use crate::disks::{Bootloader, Disks, LvmEncryption};
 use crate::errors::IoContext;
-use crate::external::luks::deactivate_logical_devices;
+use crate::external::{self, luks::deactivate_logical_devices, CommandRunner};
 use crate::hostname;
-use os_release::OsRelease;
+use crate::plan::InstallPlan;
 use partition_identity::PartitionID;
-use crate::squashfs;
 use std::{
-    io,
+    fmt, io,
     path::{Path, PathBuf},
-    sync::atomic::Ordering,
+    sync::{atomic::Ordering, mpsc, Arc},
 };
 use tempdir::TempDir;
 use crate::timezones::Region;
@@ -34,14 +49,18 @@ use crate::PARTITIONING_TEST;
 pub const MODIFY_BOOT_ORDER: u8 = 0b01;
 pub const INSTALL_HARDWARE_SUPPORT: u8 = 0b10;
 pub const KEEP_OLD_ROOT: u8 = 0b100;
+/// Install without the network. NixOS installs must then be satisfied by the live image's store.
+pub const OFFLINE: u8 = 0b1000;
 
 macro_rules! percent {
     ($steps:expr) => {
-        |percent| {
-            $steps.status.percent = percent;
-            let status = $steps.status;
-            $steps.emit_status(status);
-        }
+        |percent| $steps.emit_progress(Progress::Percent(percent))
+    };
+}
+
+macro_rules! progress {
+    ($steps:expr) => {
+        |progress| $steps.emit_progress(progress)
     };
 }
 
@@ -65,14 +84,51 @@ pub struct Config {
     pub squashfs:         String,
     /// Some flags to control the behavior of the installation.
     pub flags:            u8,
+    /// The distribution being installed, or `None` to detect it from the live system.
+    pub distribution:     Option<Distribution>,
+    /// Scripts, or directories of scripts, which are executed in order within the target
+    /// once it has been installed.
+    pub hooks:            Vec<PathBuf>,
+    /// The boot loader to install on EFI systems. Only NixOS installs honor this.
+    pub boot_manager:     BootManager,
+    /// How the configuration of NixOS installs is generated and built.
+    pub nixos:            NixosConfig,
 }
 
-/// Credentials for creating a new user account.
-#[derive(Clone)]
+/// Credentials and attributes for creating a new user account.
+///
+/// The password is redacted from the `Debug` representation, so that it never reaches logs.
+#[derive(Clone, Default, PartialEq)]
 pub struct UserAccountCreate {
     pub username: String,
     pub realname: Option<String>,
     pub password: Option<String>,
+    /// Supplementary groups to add the account to. When empty, the distribution's
+    /// administrator groups are used instead.
+    pub groups:   Vec<String>,
+    /// The login shell, or `None` for the distribution's default shell.
+    pub shell:    Option<String>,
+    /// A fixed user ID for the account.
+    pub uid:      Option<u32>,
+    /// A fixed ID for the account's primary group, which is named after the account.
+    pub gid:      Option<u32>,
+    /// Public keys which are authorized to log in to the account over SSH.
+    pub ssh_keys: Vec<String>,
+}
+
+impl fmt::Debug for UserAccountCreate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserAccountCreate")
+            .field("username", &self.username)
+            .field("realname", &self.realname)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("groups", &self.groups)
+            .field("shell", &self.shell)
+            .field("uid", &self.uid)
+            .field("gid", &self.gid)
+            .field("ssh_keys", &self.ssh_keys)
+            .finish()
+    }
 }
 
 /// Installer error
@@ -93,8 +149,12 @@ pub struct Status {
 pub struct Installer {
     error_cb:         Option<Box<dyn FnMut(&Error)>>,
     status_cb:        Option<Box<dyn FnMut(&Status)>>,
+    event_cb:         Option<Box<dyn FnMut(&Event)>>,
     timezone_cb:      Option<Box<dyn FnMut() -> Region>>,
-    user_creation_cb: Option<Box<dyn FnMut() -> UserAccountCreate>>,
+    user_creation_cb: Option<Box<dyn FnMut() -> Vec<UserAccountCreate>>>,
+    checkpoint_path:  PathBuf,
+    snapshot_dir:     PathBuf,
+    runner:           Option<Arc<dyn CommandRunner>>,
 }
 
 impl Default for Installer {
@@ -108,14 +168,22 @@ impl Default for Installer {
         Self {
             error_cb:         None,
             status_cb:        None,
+            event_cb:         None,
             timezone_cb:      None,
             user_creation_cb: None,
+            checkpoint_path:  PathBuf::from(Self::CHECKPOINT),
+            snapshot_dir:     PathBuf::from(Self::SNAPSHOTS),
+            runner:           None,
         }
     }
 }
 
 impl Installer {
     const CHROOT_ROOT: &'static str = "distinst";
+    /// The default location of the checkpoint file, which is used to resume installs.
+    pub const CHECKPOINT: &'static str = "/tmp/distinst-checkpoint.json";
+    /// The default directory that partition tables are saved to before they are modified.
+    pub const SNAPSHOTS: &'static str = "/tmp/distinst-snapshots";
 
     /// Get a list of disks, skipping loopback devices
     ///
@@ -125,6 +193,7 @@ impl Installer {
     /// let disks = installer.disks().unwrap();
     /// ```
     pub fn disks(&self) -> io::Result<Disks> {
+        let _runner = external::exclusive_runner(self.runner.clone());
         info!("probing disks on system");
         Disks::probe_devices().with_context(|err| format!("disk probing error: {}", err))
     }
@@ -135,8 +204,10 @@ impl Installer {
     /// applied before installation. The `config` field provides configuration details that
     /// will be applied when configuring the new installation.
     ///
-    /// If `config.old_root` is set, then home at that location will be retained.
+    /// If `config.old_root` is set, then home at that location will be retained. Disk images
+    /// which were attached with `Disks::add_image` are detached once installation succeeds.
     pub fn install(&mut self, mut disks: Disks, config: &Config) -> io::Result<()> {
+        let _runner = external::exclusive_runner(self.runner.clone());
         let mut recovery_conf = if Path::new("/cdrom/recovery.conf").exists() {
             Some(RecoveryEnv::new()?)
         } else {
@@ -144,7 +215,11 @@ impl Installer {
         };
 
         disks.remove_untouched_disks();
+        // Images are detached once everything on them has been released. If the
+        // installation fails, they will be detached as they are dropped.
+        let images = disks.take_images();
         let steps = &mut InstallerState::new(self);
+        let mut backend = config.distribution.unwrap_or_else(Distribution::detect).backend();
 
         Self::backup(disks, config, steps, |mut disks, config, steps| {
             if !hostname::is_valid(&config.hostname) {
@@ -152,84 +227,149 @@ impl Installer {
             }
 
             let bootloader = Bootloader::detect();
-            disks
-                .verify_partitions(bootloader)
+            backend
+                .verify_partitions(&disks, bootloader, config)
                 .with_context(|err| format!("partition validation: {}", err))?;
 
-            /* let (squashfs, remove_pkgs) = steps.apply(Step::Init, "initializing", |steps| {
-                Installer::initialize(&mut disks, config, percent!(steps))
-            })?; */
+            steps.apply(Step::Init, "initializing", |steps| {
+                backend.prepare(&mut disks, config, &mut progress!(steps))
+            })?;
 
             steps.apply(Step::Partition, "partitioning", |steps| {
-                Installer::partition(&mut disks, percent!(steps))
+                let snapshot_dir = steps.installer.snapshot_dir.clone();
+                Installer::partition(&mut disks, &snapshot_dir, progress!(steps))?;
+                steps.checkpoint.record_partitions(&disks);
+                Ok(())
             })?;
 
-            // Mount the temporary directory, and all of our mount targets.
-            info!("mounting temporary chroot directory at {}", Self::CHROOT_ROOT);
+            Installer::install_system(
+                steps,
+                &mut *backend,
+                &disks,
+                config,
+                bootloader,
+                recovery_conf.as_mut(),
+            )
+        })?;
+
+        steps.clear_checkpoint();
+        steps.clear_snapshots();
+        let _ = deactivate_logical_devices();
+
+        for image in images {
+            image.detach().with_context(|err| format!("detaching disk image: {}", err))?;
+        }
+
+        if let Some(conf) = recovery_conf.as_mut() {
+            conf.remove("MODE");
+            conf.write()?;
+        }
+
+        Ok(())
+    }
 
-            let mount_dir = TempDir::new(Self::CHROOT_ROOT)
-                .with_context(|err| format!("chroot root temp mount: {}", err))?;
+    /// Installs the system described by a declarative install plan.
+    ///
+    /// The plan's disks are probed and configured, and its user accounts, if any are
+    /// defined, will take the place of the user callback.
+    pub fn install_plan(&mut self, plan: InstallPlan) -> io::Result<()> {
+        let _runner = external::exclusive_runner(self.runner.clone());
+        plan.validate()?;
 
-            info!("mounting all targets to the temporary chroot");
+        let InstallPlan { disks, config, users } = plan;
+        let disks = disks.build()?;
 
-            let mut mounts = disks
-                .mount_all_targets(mount_dir.path())
-                .with_context(|err| format!("mounting all targets: {}", err))?;
+        if !users.is_empty() {
+            self.set_users_callback(move || users.clone());
+        }
 
-            if PARTITIONING_TEST.load(Ordering::SeqCst) {
-                info!("PARTITION_TEST enabled: exiting before unsquashing");
-                return Ok(());
-            }
+        self.install(disks, &config)
+    }
 
-            /* let iso_os_release = steps.apply(Step::Extract, "extracting", |steps| {
-                Installer::extract(squashfs.as_path(), mount_dir.path(), percent!(steps))
-            })?; */
-
-            let timezone = steps.installer.timezone_cb.as_mut().map(|func| func());
-            let user = steps.installer.user_creation_cb.as_mut().map(|func| func());
-
-            steps.apply(Step::Configure, "installing nixOS", |steps| {
-                Installer::nixos(
-                    recovery_conf.as_mut(),
-                    bootloader,
-                    &disks,
-                    mount_dir.path(),
-                    &config,
-                    timezone.as_ref(),
-                    user.as_ref(),
-                    percent!(steps),
-                )
-            })?;
+    /// Sets where the checkpoint of an installation's progress will be written, and where
+    /// `Installer::resume` will read it from. Defaults to `Installer::CHECKPOINT`.
+    pub fn set_checkpoint_path<P: Into<PathBuf>>(&mut self, path: P) {
+        self.checkpoint_path = path.into();
+    }
 
-            /* steps.apply(Step::Configure, "configuring chroot", |steps| {
-                Installer::configure(
-                    recovery_conf.as_mut(),
-                    &disks,
-                    mount_dir.path(),
-                    &config,
-                    &iso_os_release,
-                    timezone.as_ref(),
-                    user.as_ref(),
-                    &remove_pkgs,
-                    percent!(steps),
-                )
-            })?;
+    /// Sets the directory that the partition table of each disk will be saved to before
+    /// the disks are modified. Defaults to `Installer::SNAPSHOTS`, and must not be stored
+    /// on any of the disks that will be modified.
+    pub fn set_snapshot_dir<P: Into<PathBuf>>(&mut self, path: P) {
+        self.snapshot_dir = path.into();
+    }
 
-            steps.apply(Step::Bootloader, "configuring bootloader", |steps| {
-                Installer::bootloader(
-                    &disks,
-                    mount_dir.path(),
-                    bootloader,
-                    &config,
-                    &iso_os_release,
-                    percent!(steps),
-                )
-            })?; */
-
-            mounts.unmount(false).with_context(|err| format!("chroot unmount: {}", err))?;
-            mount_dir.close().with_context(|err| format!("closing mount directory: {}", err))
-        })?;
+    /// Sets the runner which executes the external commands that the installer depends upon,
+    /// such as an `external::ScriptedRunner` which replays scripted outputs in tests.
+    ///
+    /// The runner is global to the process: while one of this installer's methods is running,
+    /// every command in the process is executed by it, including those of other threads.
+    /// Installers hold the runner exclusively while their methods run, so the methods of
+    /// another installer wait for them to return, rather than using this runner.
+    pub fn set_command_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.runner = Some(runner);
+    }
+
+    /// Restores the partition tables saved to the snapshot directory by an installation
+    /// which has failed.
+    ///
+    /// Partition tables are restored automatically if partitioning fails, so this is
+    /// only needed to revert disks after a later step has failed.
+    pub fn restore_snapshots(&self) -> io::Result<()> {
+        let _runner = external::exclusive_runner(self.runner.clone());
+        snapshot::restore_tables_from(&self.snapshot_dir)
+    }
+
+    /// Resumes an installation which failed after its partitions were committed.
+    ///
+    /// The disks are probed, and the LUKS partitions recorded in the checkpoint are reopened
+    /// with their keyfiles, or else the passwords of their physical volumes in `encryption`.
+    /// The disks are then validated against the partitions recorded in the checkpoint, which
+    /// will be mounted without being modified. Installation continues from the first step
+    /// which has yet to be completed.
+    pub fn resume(&mut self, config: &Config, encryption: &[LvmEncryption]) -> io::Result<()> {
+        let _runner = external::exclusive_runner(self.runner.clone());
+        if !hostname::is_valid(&config.hostname) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "hostname is not valid"));
+        }
+
+        if config.old_root.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "installs which retain the old home cannot be resumed",
+            ));
+        }
+
+        let mut recovery_conf = if Path::new("/cdrom/recovery.conf").exists() {
+            Some(RecoveryEnv::new()?)
+        } else {
+            None
+        };
+
+        let checkpoint = Checkpoint::from_file(&self.checkpoint_path)?;
+        let mut disks = self.disks()?;
+        checkpoint
+            .reopen_volumes(&mut disks, encryption)
+            .with_context(|err| format!("checkpoint volumes: {}", err))?;
+        checkpoint
+            .restore_targets(&mut disks)
+            .with_context(|err| format!("checkpoint validation: {}", err))?;
+
+        info!("resuming installation after {:?}", checkpoint.steps);
+        let bootloader = Bootloader::detect();
+        let mut backend = config.distribution.unwrap_or_else(Distribution::detect).backend();
+        let steps = &mut InstallerState::resume(self, checkpoint);
+        Installer::install_system(
+            steps,
+            &mut *backend,
+            &disks,
+            config,
+            bootloader,
+            recovery_conf.as_mut(),
+        )?;
 
+        steps.clear_checkpoint();
+        steps.clear_snapshots();
         let _ = deactivate_logical_devices();
 
         if let Some(conf) = recovery_conf.as_mut() {
@@ -240,6 +380,85 @@ impl Installer {
         Ok(())
     }
 
+    /// Mounts the committed partitions to a temporary chroot, and then installs the system
+    /// to it. Steps which have been completed in the checkpoint will be skipped.
+    fn install_system(
+        steps: &mut InstallerState,
+        backend: &mut dyn DistributionBackend,
+        disks: &Disks,
+        config: &Config,
+        bootloader: Bootloader,
+        mut recovery_conf: Option<&mut RecoveryEnv>,
+    ) -> io::Result<()> {
+        // Mount the temporary directory, and all of our mount targets.
+        info!("mounting temporary chroot directory at {}", Self::CHROOT_ROOT);
+
+        let mount_dir = TempDir::new(Self::CHROOT_ROOT)
+            .with_context(|err| format!("chroot root temp mount: {}", err))?;
+
+        info!("mounting all targets to the temporary chroot");
+
+        let mut mounts = disks
+            .mount_all_targets(mount_dir.path())
+            .with_context(|err| format!("mounting all targets: {}", err))?;
+
+        if PARTITIONING_TEST.load(Ordering::SeqCst) {
+            info!("PARTITION_TEST enabled: exiting before unsquashing");
+            return Ok(());
+        }
+
+        let timezone = steps.installer.timezone_cb.as_mut().map(|func| func());
+        let users = steps.installer.user_creation_cb.as_mut().map_or_else(Vec::new, |func| func());
+        let formatted = steps.checkpoint.formatted_targets();
+
+        let target = InstallTarget {
+            disks,
+            mount_dir: mount_dir.path(),
+            config,
+            bootloader,
+            region: timezone.as_ref(),
+            users: &users,
+            formatted: &formatted,
+        };
+
+        if !steps.checkpoint.is_complete(Step::Extract) {
+            steps.apply(Step::Extract, "extracting", |steps| {
+                backend.populate(&target, &mut progress!(steps))
+            })?;
+        }
+
+        // Configuration is generated again if any of its files are missing from the target.
+        let configured = steps.checkpoint.is_complete(Step::Configure)
+            && steps.checkpoint.configs.iter().all(|conf| mount_dir.path().join(conf).exists());
+
+        if !configured {
+            steps.apply(Step::Configure, "configuring chroot", |steps| {
+                let configs = backend.configure(
+                    &target,
+                    recovery_conf.as_mut().map(|conf| &mut **conf),
+                    &mut progress!(steps),
+                )?;
+                steps.checkpoint.configs = configs;
+                Ok(())
+            })?;
+        }
+
+        if !steps.checkpoint.is_complete(Step::Bootloader) {
+            steps.apply(Step::Bootloader, "configuring bootloader", |steps| {
+                backend.bootloader(&target, &mut progress!(steps))
+            })?;
+        }
+
+        if !config.hooks.is_empty() && !steps.checkpoint.is_complete(Step::Hooks) {
+            steps.apply(Step::Hooks, "running hooks", |steps| {
+                steps::hooks(mount_dir.path(), &config.hooks, progress!(steps))
+            })?;
+        }
+
+        mounts.unmount(false).with_context(|err| format!("chroot unmount: {}", err))?;
+        mount_dir.close().with_context(|err| format!("closing mount directory: {}", err))
+    }
+
     /// Create a backup of key data on the system, execute the given functi on, and then restore
     /// that backup. If a backup is not requested for the configuration, then it will just
     /// execute the given function.
@@ -353,6 +572,9 @@ impl Installer {
 
     /// Set the error callback
     ///
+    /// When an external command fails, the error describes its command line and the last lines
+    /// that it wrote to standard error, as given by an `external::CommandError`.
+    ///
     /// ```ignore,rust
     /// use distinst::Installer;
     /// let mut installer = Installer::new();
@@ -389,103 +611,64 @@ impl Installer {
         self.status_cb = Some(Box::new(callback));
     }
 
-    /// Set the timezone callback
-    pub fn set_timezone_callback<F: FnMut() -> Region + 'static>(&mut self, callback: F) {
-        self.timezone_cb = Some(Box::new(callback));
-    }
-
-    pub fn set_user_callback<F: FnMut() -> UserAccountCreate + 'static>(&mut self, callback: F) {
-        self.user_creation_cb = Some(Box::new(callback));
+    /// Send a progress event
+    pub fn emit_event(&mut self, event: &Event) {
+        if let Some(ref mut cb) = self.event_cb {
+            cb(event);
+        }
     }
 
-    fn initialize<F: FnMut(i32)>(
-        disks: &mut Disks,
-        config: &Config,
-        callback: F,
-    ) -> io::Result<(PathBuf, Vec<String>)> {
-        steps::initialize(disks, config, callback)
+    /// Set the event callback, which receives a detailed view of each step's progress.
+    ///
+    /// ```ignore,rust
+    /// use distinst::{Installer, Progress};
+    /// let mut installer = Installer::new();
+    /// installer.on_event(|event| if let Progress::SubStep(ref task) = event.progress {
+    ///     println!("{:?}: {}", event.step, task);
+    /// });
+    /// ```
+    pub fn on_event<F: FnMut(&Event) + 'static>(&mut self, callback: F) {
+        self.event_cb = Some(Box::new(callback));
     }
 
-    /// Apply all partitioning and formatting changes to the disks
-    /// configuration specified.
-    fn partition<F: FnMut(i32)>(disks: &mut Disks, callback: F) -> io::Result<()> {
-        steps::partition(disks, callback)
+    /// Delivers events to a channel, which replaces the event callback.
+    pub fn events(&mut self) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.on_event(move |event| {
+            let _ = tx.send(event.clone());
+        });
+        rx
     }
 
-    /// Extracts the squashfs image into the new install, and then gets the os-release data.
-    ///
-    /// We get the os-release data here because the host that is installing the image may differ
-    /// from the image that is being installed, and thus may be a completely different distro.
-    fn extract<P: AsRef<Path>, F: FnMut(i32)>(
-        squashfs: P,
-        mount_dir: P,
-        callback: F,
-    ) -> io::Result<OsRelease> {
-        info!("Extracting {}", squashfs.as_ref().display());
-        let mount_dir = mount_dir.as_ref();
-        squashfs::extract(squashfs, mount_dir, callback)?;
-        OsRelease::new_from(&mount_dir.join("etc/os-release")).with_context(|why| {
-            format!("failed to parse /etc/os-release from extracted image: {}", why)
-        })
+    /// Set the timezone callback
+    pub fn set_timezone_callback<F: FnMut() -> Region + 'static>(&mut self, callback: F) {
+        self.timezone_cb = Some(Box::new(callback));
     }
 
-    /// Configures the new install after it has been extracted.
-    fn configure<P: AsRef<Path>, S: AsRef<str>, F: FnMut(i32)>(
-        recovery_conf: Option<&mut RecoveryEnv>,
-        disks: &Disks,
-        mount_dir: P,
-        config: &Config,
-        iso_os_release: &OsRelease,
-        region: Option<&Region>,
-        user: Option<&UserAccountCreate>,
-        remove_pkgs: &[S],
-        callback: F,
-    ) -> io::Result<()> {
-        steps::configure(
-            recovery_conf,
-            disks,
-            mount_dir,
-            config,
-            iso_os_release,
-            region,
-            user,
-            remove_pkgs,
-            callback,
-        )
+    /// Set the callback which provides the user account to create.
+    pub fn set_user_callback<F: FnMut() -> UserAccountCreate + 'static>(
+        &mut self,
+        mut callback: F,
+    ) {
+        self.set_users_callback(move || vec![callback()]);
     }
 
-    fn nixos<P: AsRef<Path>, F: FnMut(i32)>(
-        recovery_conf: Option<&mut RecoveryEnv>,
-        bootloader: Bootloader,
-        disks: &Disks,
-        mount_dir: P,
-        config: &Config,
-        region: Option<&Region>,
-        user: Option<&UserAccountCreate>,
+    /// Set the callback which provides every user account to create.
+    pub fn set_users_callback<F: FnMut() -> Vec<UserAccountCreate> + 'static>(
+        &mut self,
         callback: F,
-    ) -> io::Result<()> {
-        steps::nixos(
-            recovery_conf,
-            bootloader,
-            disks,
-            mount_dir,
-            config,
-            region,
-            user,
-            callback,
-        )
+    ) {
+        self.user_creation_cb = Some(Box::new(callback));
     }
 
-    /// Installs and configures the boot loader after it has been configured.
-    fn bootloader<F: FnMut(i32)>(
-        disks: &Disks,
-        mount_dir: &Path,
-        bootloader: Bootloader,
-        config: &Config,
-        iso_os_release: &OsRelease,
+    /// Apply all partitioning and formatting changes to the disks
+    /// configuration specified.
+    fn partition<F: FnMut(Progress)>(
+        disks: &mut Disks,
+        snapshot_dir: &Path,
         callback: F,
     ) -> io::Result<()> {
-        steps::bootloader(disks, mount_dir, bootloader, config, iso_os_release, callback)
+        steps::partition(disks, snapshot_dir, callback)
     }
 }
 
@@ -494,3 +677,60 @@ impl From<ReinstallError> for io::Error {
         io::Error::new(io::ErrorKind::Other, format!("{}", why))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external::ScriptedRunner;
+    use std::fs;
+
+    pub(crate) fn config() -> Config {
+        Config {
+            hostname:         "pop-os".into(),
+            keyboard_layout:  "us".into(),
+            keyboard_model:   None,
+            keyboard_variant: None,
+            old_root:         None,
+            lang:             "en_US.UTF-8".into(),
+            remove:           String::new(),
+            squashfs:         String::new(),
+            flags:            0,
+            distribution:     Some(Distribution::Debian),
+            hooks:            Vec::new(),
+            boot_manager:     BootManager::default(),
+            nixos:            NixosConfig::default(),
+        }
+    }
+
+    /// Runners are compared by the address of their data, as their vtables may differ.
+    fn address(runner: &dyn CommandRunner) -> *const u8 {
+        runner as *const dyn CommandRunner as *const u8
+    }
+
+    #[test]
+    fn scripted_install() {
+        let dir = TempDir::new("distinst").unwrap();
+        let runner = Arc::new(ScriptedRunner::new());
+        let mut installer = Installer::default();
+        installer.set_checkpoint_path(dir.path().join("checkpoint.json"));
+        installer.set_snapshot_dir(dir.path().join("snapshots"));
+        installer.set_command_runner(runner.clone());
+
+        // Nothing is executed until the partitions have been validated.
+        let why = installer.install(Disks::default(), &config()).unwrap_err();
+        assert_eq!(why.to_string(), "partition validation: root partition was not defined");
+        assert!(runner.command_lines().is_empty());
+
+        let disk = dir.path().join("disk");
+        fs::write(&disk, vec![0; 1024 * 1024]).unwrap();
+        fs::create_dir(dir.path().join("snapshots")).unwrap();
+        let snapshot = TableSnapshot::capture(&disk).unwrap();
+        snapshot.write(dir.path().join("snapshots/disk.json")).unwrap();
+
+        installer.restore_snapshots().unwrap();
+        assert_eq!(runner.command_lines(), vec![format!("blockdev --rereadpt {}", disk.display())]);
+
+        // Other commands are not executed by the installer's runner once it has returned.
+        assert_ne!(address(&*external::runner()), address(&*runner));
+    }
+}
@@ -0,0 +1,72 @@
+//! Structured progress events, which describe what is happening within each step of an
+//! installation in more detail than the percentage reported by `Status`.
+
+use super::Step;
+use std::time::Duration;
+
+/// What is being counted by a `Counter`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CounterUnit {
+    Items,
+    Bytes,
+}
+
+/// The amount of work that has been completed within a step.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Counter {
+    pub unit:     CounterUnit,
+    pub done:     u64,
+    /// The total amount of work, if it is known.
+    pub expected: Option<u64>,
+}
+
+/// Progress that has been made by a step.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Progress {
+    /// The step has started, with a description of what the step does.
+    Started(String),
+    /// The percentage of the step which has been completed.
+    Percent(i32),
+    /// Describes the task which the step is now performing.
+    SubStep(String),
+    /// Counts the items or bytes which have been processed.
+    Counter(Counter),
+    /// A line of output from a command that the step is executing.
+    Log(String),
+    /// The step has completed successfully.
+    Finished,
+}
+
+/// An installer event, which is delivered to the callback set by `Installer::on_event`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    pub step:      Step,
+    pub progress:  Progress,
+    /// The estimated time remaining for the current step, once it has made some progress.
+    pub remaining: Option<Duration>,
+}
+
+/// Estimates the time remaining for a step, assuming that it progresses at a steady rate.
+pub(crate) fn estimate_remaining(elapsed: Duration, percent: i32) -> Option<Duration> {
+    if percent <= 0 || percent >= 100 {
+        return None;
+    }
+
+    let elapsed = elapsed.as_millis() as u64;
+    let percent = percent as u64;
+    Some(Duration::from_millis(elapsed * (100 - percent) / percent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_time() {
+        let elapsed = Duration::from_secs(30);
+        assert_eq!(estimate_remaining(elapsed, 0), None);
+        assert_eq!(estimate_remaining(elapsed, 25), Some(Duration::from_secs(90)));
+        assert_eq!(estimate_remaining(elapsed, 50), Some(Duration::from_secs(30)));
+        assert_eq!(estimate_remaining(elapsed, 100), None);
+    }
+}
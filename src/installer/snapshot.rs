@@ -0,0 +1,459 @@
+//! Snapshots of the partition tables on disks which are about to be modified, so that a
+//! partitioning step which fails part way through may be reverted.
+
+use crate::{
+    disks::{Disks, PartitionTable},
+    errors::IoContext,
+    external::blockdev,
+};
+use disk_types::BlockDeviceExt;
+use json::object;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// The version of the snapshot format that is read and written by this library.
+const SNAPSHOT_VERSION: u64 = 1;
+
+const SECTOR: u64 = 512;
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+/// The size of the partition entry array of a default GPT.
+const GPT_ENTRIES: u64 = 128 * 128;
+/// Guards against corrupted headers requesting absurdly large entry arrays.
+const GPT_ENTRIES_MAX: u64 = 1024 * 1024;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+const MBR_LOGICAL_MAX: usize = 128;
+
+/// The regions of a disk which contain its partition table, as they were on disk.
+///
+/// Regions which a new GPT would occupy are always included, so that replacing the
+/// table on the disk, or creating one where there was none before, can be reverted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableSnapshot {
+    pub device: PathBuf,
+    /// The size of the device, in bytes.
+    pub size:   u64,
+    pub table:  Option<PartitionTable>,
+    regions:    Vec<(u64, Vec<u8>)>,
+}
+
+impl TableSnapshot {
+    /// Reads the partition table of the given block device.
+    pub fn capture<P: AsRef<Path>>(device: P) -> io::Result<TableSnapshot> {
+        let device = device.as_ref();
+        let mut file = File::open(device)
+            .with_context(|why| format!("failed to open {:?} for snapshot: {}", device, why))?;
+        TableSnapshot::capture_from(device, &mut file)
+    }
+
+    fn capture_from<F: Read + Seek>(device: &Path, file: &mut F) -> io::Result<TableSnapshot> {
+        let size = file.seek(SeekFrom::End(0))?;
+        let (table, mut ranges) = table_ranges(file, size)?;
+
+        ranges.push((0, 2 * SECTOR + GPT_ENTRIES));
+        ranges.push((size.saturating_sub(SECTOR + GPT_ENTRIES), SECTOR + GPT_ENTRIES));
+
+        let regions = merge_ranges(ranges, size)
+            .into_iter()
+            .map(|(offset, length)| read_at(file, offset, length).map(|data| (offset, data)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(TableSnapshot { device: device.to_path_buf(), size, table, regions })
+    }
+
+    /// Reads the snapshot stored at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<TableSnapshot> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|why| format!("failed to read snapshot at {:?}: {}", path, why))?;
+        TableSnapshot::parse(&data)
+    }
+
+    /// Parses a snapshot from its JSON representation.
+    pub fn parse(data: &str) -> io::Result<TableSnapshot> {
+        let value = json::parse(data).map_err(|why| invalid(&why.to_string()))?;
+
+        if value["version"].as_u64() != Some(SNAPSHOT_VERSION) {
+            return Err(invalid("unsupported snapshot version"));
+        }
+
+        let device = value["device"].as_str().ok_or_else(|| invalid("missing device"))?;
+        let size = value["size"].as_u64().ok_or_else(|| invalid("missing size"))?;
+        let table = match value["table"].as_str() {
+            Some("gpt") => Some(PartitionTable::Gpt),
+            Some("msdos") => Some(PartitionTable::Msdos),
+            Some(_) => return Err(invalid("unknown partition table")),
+            None => None,
+        };
+
+        let regions = value["regions"]
+            .members()
+            .map(|region| {
+                let offset = region["offset"].as_u64();
+                let data = region["data"].as_str().and_then(decode_hex);
+                match (offset, data) {
+                    (Some(offset), Some(data)) => Ok((offset, data)),
+                    _ => Err(invalid("invalid region")),
+                }
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(TableSnapshot { device: PathBuf::from(device), size, table, regions })
+    }
+
+    /// Serializes the snapshot into its JSON representation.
+    pub fn to_json(&self) -> String {
+        let regions = self
+            .regions
+            .iter()
+            .map(|&(offset, ref data)| object! { "offset" => offset, "data" => encode_hex(data) })
+            .collect::<Vec<_>>();
+
+        object! {
+            "version" => SNAPSHOT_VERSION,
+            "device" => self.device.to_string_lossy().into_owned(),
+            "size" => self.size,
+            "table" => self.table.map(|table| match table {
+                PartitionTable::Gpt => "gpt",
+                PartitionTable::Msdos => "msdos",
+            }),
+            "regions" => regions
+        }
+        .pretty(4)
+    }
+
+    /// Writes the snapshot to the given path.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json())
+            .with_context(|why| format!("failed to write snapshot to {:?}: {}", path, why))
+    }
+
+    /// Restores the partition table to the device that it was captured from.
+    pub fn restore(&self) -> io::Result<()> { self.restore_to(&self.device) }
+
+    /// Restores the partition table to the given device, which must be the same size as
+    /// the device that the snapshot was captured from.
+    pub fn restore_to<P: AsRef<Path>>(&self, device: P) -> io::Result<()> {
+        let device = device.as_ref();
+        info!("restoring partition table of {:?} to {:?}", self.device, device);
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(device)
+            .with_context(|why| format!("failed to open {:?} for restore: {}", device, why))?;
+
+        self.write_regions(&mut file)?;
+        file.sync_all()?;
+
+        if let Err(why) = blockdev(device, &["--rereadpt"]) {
+            warn!("failed to reread partition table of {:?}: {}", device, why);
+        }
+
+        Ok(())
+    }
+
+    fn write_regions<F: Write + Seek>(&self, file: &mut F) -> io::Result<()> {
+        let size = file.seek(SeekFrom::End(0))?;
+        if size != self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "snapshot of {:?} is for a device of {} bytes, but the device has {}",
+                    self.device, self.size, size
+                ),
+            ));
+        }
+
+        for &(offset, ref data) in &self.regions {
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(data)?;
+        }
+
+        file.flush()
+    }
+}
+
+/// Captures the partition table of every physical disk, and writes each snapshot to the
+/// given directory, which must not be stored on any of the disks.
+pub(crate) fn snapshot_tables(disks: &Disks, dir: &Path) -> io::Result<Vec<TableSnapshot>> {
+    fs::create_dir_all(dir)
+        .with_context(|why| format!("failed to create snapshot directory {:?}: {}", dir, why))?;
+    remove_tables(dir)?;
+
+    let dir_device = fs::metadata(dir)?.dev();
+    let on_disks = disks.get_physical_devices().iter().any(|disk| {
+        let mut devices = disk.partitions.iter().map(|part| part.get_device_path());
+        devices.any(|path| fs::metadata(path).ok().map_or(false, |meta| meta.rdev() == dir_device))
+    });
+
+    if on_disks {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("snapshot directory {:?} is stored on a disk that will be modified", dir),
+        ));
+    }
+
+    disks
+        .get_physical_devices()
+        .iter()
+        .map(|disk| {
+            let device = disk.get_device_path();
+            let snapshot = TableSnapshot::capture(device)?;
+            snapshot.write(snapshot_path(dir, device))?;
+            Ok(snapshot)
+        })
+        .collect()
+}
+
+/// Restores every snapshot, attempting each even if another could not be restored.
+pub(crate) fn restore_tables(snapshots: &[TableSnapshot]) -> io::Result<()> {
+    let mut result = Ok(());
+    for snapshot in snapshots {
+        if let Err(why) = snapshot.restore() {
+            error!("failed to restore partition table of {:?}: {}", snapshot.device, why);
+            result = Err(why);
+        }
+    }
+
+    result
+}
+
+/// Reads and restores every snapshot stored in the given directory.
+pub(crate) fn restore_tables_from(dir: &Path) -> io::Result<()> {
+    let snapshots = read_tables(dir)?.into_iter().map(|(_, snapshot)| snapshot).collect::<Vec<_>>();
+    if snapshots.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no partition table snapshots were found in {:?}", dir),
+        ));
+    }
+
+    restore_tables(&snapshots)
+}
+
+/// Removes the snapshots stored in the given directory, leaving any other files intact.
+pub(crate) fn remove_tables(dir: &Path) -> io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for (path, _) in read_tables(dir)? {
+        fs::remove_file(&path)
+            .with_context(|why| format!("failed to remove snapshot at {:?}: {}", path, why))?;
+    }
+
+    Ok(())
+}
+
+fn read_tables(dir: &Path) -> io::Result<Vec<(PathBuf, TableSnapshot)>> {
+    let entries = fs::read_dir(dir)
+        .with_context(|why| format!("failed to read snapshot directory {:?}: {}", dir, why))?;
+
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map_or(false, |ext| ext == "json") {
+            if let Ok(snapshot) = TableSnapshot::from_file(&path) {
+                snapshots.push((path, snapshot));
+            }
+        }
+    }
+
+    Ok(snapshots)
+}
+
+fn snapshot_path(dir: &Path, device: &Path) -> PathBuf {
+    let name = device.to_string_lossy().trim_start_matches('/').replace('/', "-");
+    dir.join([&name, ".json"].concat())
+}
+
+/// Locates the regions of the disk which store its partition table, if it has one.
+fn table_ranges<F: Read + Seek>(
+    file: &mut F,
+    size: u64,
+) -> io::Result<(Option<PartitionTable>, Vec<(u64, u64)>)> {
+    for &sector in &[SECTOR, 4096] {
+        if sector * 2 > size {
+            break;
+        }
+
+        let header = read_at(file, sector, 92)?;
+        if &header[..8] == GPT_SIGNATURE {
+            let alternate = le_u64(&header[32..40]);
+            let entries_lba = le_u64(&header[72..80]);
+            let entries = le_u32(&header[80..84]) as u64 * le_u32(&header[84..88]) as u64;
+            let entries = round_up(entries.min(GPT_ENTRIES_MAX), sector);
+
+            let mut ranges = vec![(0, entries_lba * sector + entries)];
+            if (alternate + 1) * sector <= size {
+                let end = (alternate + 1) * sector;
+                let start = (alternate * sector).saturating_sub(entries);
+                ranges.push((start, end - start));
+            }
+
+            return Ok((Some(PartitionTable::Gpt), ranges));
+        }
+    }
+
+    if size < SECTOR {
+        return Ok((None, Vec::new()));
+    }
+
+    let mbr = read_at(file, 0, SECTOR)?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok((None, Vec::new()));
+    }
+
+    let mut ranges = vec![(0, SECTOR)];
+    for entry in mbr[446..510].chunks(16) {
+        if !MBR_EXTENDED.contains(&entry[4]) {
+            continue;
+        }
+
+        // Logical partitions are described by a chain of boot records within the
+        // extended partition, each of which is relative to the extended partition.
+        let extended = le_u32(&entry[8..12]) as u64;
+        let mut record = extended;
+        for _ in 0..MBR_LOGICAL_MAX {
+            if (record + 1) * SECTOR > size {
+                break;
+            }
+
+            ranges.push((record * SECTOR, SECTOR));
+            let ebr = read_at(file, record * SECTOR, SECTOR)?;
+            let next = &ebr[462..478];
+            if ebr[510..512] != MBR_SIGNATURE || !MBR_EXTENDED.contains(&next[4]) {
+                break;
+            }
+
+            record = extended + le_u32(&next[8..12]) as u64;
+        }
+    }
+
+    Ok((Some(PartitionTable::Msdos), ranges))
+}
+
+/// Sorts the ranges, clamps them to the device, and combines those that overlap.
+fn merge_ranges(mut ranges: Vec<(u64, u64)>, size: u64) -> Vec<(u64, u64)> {
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, length) in ranges {
+        let end = (start + length).min(size);
+        if start >= end {
+            continue;
+        }
+
+        match merged.last_mut() {
+            Some(last) if start <= last.0 + last.1 => {
+                last.1 = last.1.max(end - last.0);
+            }
+            _ => merged.push((start, end - start)),
+        }
+    }
+
+    merged
+}
+
+fn read_at<F: Read + Seek>(file: &mut F, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; length as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn le_u32(bytes: &[u8]) -> u32 { bytes.iter().rev().fold(0, |acc, &byte| (acc << 8) | byte as u32) }
+
+fn le_u64(bytes: &[u8]) -> u64 { bytes.iter().rev().fold(0, |acc, &byte| (acc << 8) | byte as u64) }
+
+fn round_up(value: u64, multiple: u64) -> u64 { (value + multiple - 1) / multiple * multiple }
+
+fn encode_hex(data: &[u8]) -> String { data.iter().map(|byte| format!("{:02x}", byte)).collect() }
+
+fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..data.len())
+        .step_by(2)
+        .map(|index| data.get(index..index + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+        .collect()
+}
+
+fn invalid(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("snapshot is not valid: {}", why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const DISK_SIZE: u64 = 8 * 1024 * 1024;
+
+    fn put(disk: &mut [u8], offset: u64, bytes: &[u8]) {
+        disk[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn gpt_disk() -> Vec<u8> {
+        let mut disk = vec![0xAB; DISK_SIZE as usize];
+        let alternate = DISK_SIZE / SECTOR - 1;
+        for &(lba, backup) in &[(1, alternate), (alternate, 1)] {
+            let header = lba * SECTOR;
+            put(&mut disk, header, GPT_SIGNATURE);
+            put(&mut disk, header + 32, &backup.to_le_bytes());
+            let entries = if lba == 1 { 2 } else { alternate - 32 };
+            put(&mut disk, header + 72, &entries.to_le_bytes());
+            put(&mut disk, header + 80, &128u32.to_le_bytes());
+            put(&mut disk, header + 84, &128u32.to_le_bytes());
+        }
+
+        disk
+    }
+
+    #[test]
+    fn gpt_snapshot_restores() {
+        let original = gpt_disk();
+        let mut disk = Cursor::new(original.clone());
+        let snapshot = TableSnapshot::capture_from(Path::new("/dev/sdz"), &mut disk).unwrap();
+        assert_eq!(snapshot.table, Some(PartitionTable::Gpt));
+
+        let snapshot = TableSnapshot::parse(&snapshot.to_json()).unwrap();
+        let mut modified = original.clone();
+        put(&mut modified, 0, &[0; 34 * 512]);
+        put(&mut modified, DISK_SIZE - 33 * 512, &[0; 33 * 512]);
+
+        let mut disk = Cursor::new(modified);
+        snapshot.write_regions(&mut disk).unwrap();
+        assert!(disk.into_inner() == original);
+
+        let mut smaller = Cursor::new(vec![0; DISK_SIZE as usize / 2]);
+        assert!(snapshot.write_regions(&mut smaller).is_err());
+    }
+
+    #[test]
+    fn mbr_snapshot_follows_logical_partitions() {
+        let mut disk = vec![0; DISK_SIZE as usize];
+        put(&mut disk, 510, &MBR_SIGNATURE);
+        put(&mut disk, 446 + 4, &[0x05]);
+        put(&mut disk, 446 + 8, &2048u32.to_le_bytes());
+
+        for &(ebr, next) in &[(2048u64, 4096u32), (6144, 0)] {
+            put(&mut disk, ebr * SECTOR + 510, &MBR_SIGNATURE);
+            put(&mut disk, ebr * SECTOR + 446 + 4, &[0x83]);
+            if next != 0 {
+                put(&mut disk, ebr * SECTOR + 462 + 4, &[0x05]);
+                put(&mut disk, ebr * SECTOR + 462 + 8, &next.to_le_bytes());
+            }
+        }
+
+        let (table, ranges) = table_ranges(&mut Cursor::new(disk), DISK_SIZE).unwrap();
+        assert_eq!(table, Some(PartitionTable::Msdos));
+        assert_eq!(ranges, vec![(0, 512), (2048 * 512, 512), (6144 * 512, 512)]);
+    }
+}
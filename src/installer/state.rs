@@ -1,16 +1,37 @@
-use super::{Error, Installer, Status, Step};
+use super::{
+    progress::estimate_remaining, snapshot, Checkpoint, Error, Event, Installer, Progress, Status,
+    Step,
+};
 use libc;
-use std::{io, sync::atomic::Ordering};
+use std::{
+    fs, io,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
 use crate::KILL_SWITCH;
 
 pub struct InstallerState<'a> {
-    pub installer: &'a mut Installer,
-    pub status:    Status,
+    pub installer:  &'a mut Installer,
+    pub status:     Status,
+    pub checkpoint: Checkpoint,
+    started:        Instant,
+    remaining:      Option<Duration>,
 }
 
 impl<'a> InstallerState<'a> {
     pub fn new(installer: &'a mut Installer) -> Self {
-        Self { installer, status: Status { step: Step::Init, percent: 0 } }
+        Self::resume(installer, Checkpoint::default())
+    }
+
+    /// Continues from a checkpoint that was recorded by a previous installation.
+    pub fn resume(installer: &'a mut Installer, checkpoint: Checkpoint) -> Self {
+        Self {
+            installer,
+            status: Status { step: Step::Init, percent: 0 },
+            checkpoint,
+            started: Instant::now(),
+            remaining: None,
+        }
     }
 
     pub fn apply<T, F>(&mut self, step: Step, msg: &str, mut action: F) -> io::Result<T>
@@ -27,12 +48,20 @@ impl<'a> InstallerState<'a> {
 
         self.status.step = step;
         self.status.percent = 0;
+        self.started = Instant::now();
+        self.remaining = None;
         let status = self.status;
         self.emit_status(status);
+        self.emit_event(Progress::Started(msg.into()));
 
         info!("starting {} step", msg);
         match action(self) {
-            Ok(value) => Ok(value),
+            Ok(value) => {
+                self.emit_event(Progress::Finished);
+                self.checkpoint.complete(step);
+                self.save_checkpoint();
+                Ok(value)
+            }
             Err(err) => {
                 error!("{} error: {}", msg, err);
                 let error = Error { step: self.status.step, err };
@@ -42,6 +71,49 @@ impl<'a> InstallerState<'a> {
         }
     }
 
+    /// Writes the checkpoint to the installer's checkpoint path. Failing to do so only
+    /// prevents the installation from being resumed, so it is not treated as an error.
+    pub fn save_checkpoint(&self) {
+        if let Err(why) = self.checkpoint.write(&self.installer.checkpoint_path) {
+            warn!("{}", why);
+        }
+    }
+
+    /// Removes the checkpoint once the installation has completed.
+    pub fn clear_checkpoint(&self) {
+        let path = &self.installer.checkpoint_path;
+        if path.exists() {
+            if let Err(why) = fs::remove_file(path) {
+                warn!("failed to remove checkpoint at {:?}: {}", path, why);
+            }
+        }
+    }
+
+    /// Reports progress made by the current step. Percentages are also delivered to the
+    /// status callback, and are used to estimate the time remaining for the step.
+    pub fn emit_progress(&mut self, progress: Progress) {
+        if let Progress::Percent(percent) = progress {
+            self.status.percent = percent;
+            self.remaining = estimate_remaining(self.started.elapsed(), percent);
+            let status = self.status;
+            self.emit_status(status);
+        }
+
+        self.emit_event(progress);
+    }
+
+    fn emit_event(&mut self, progress: Progress) {
+        let event = Event { step: self.status.step, progress, remaining: self.remaining };
+        self.installer.emit_event(&event);
+    }
+
+    /// Removes the partition table snapshots once the installation has completed.
+    pub fn clear_snapshots(&self) {
+        if let Err(why) = snapshot::remove_tables(&self.installer.snapshot_dir) {
+            warn!("{}", why);
+        }
+    }
+
     pub fn emit_status(&mut self, status: Status) { self.installer.emit_status(status); }
 
     pub fn emit_error(&mut self, error: &Error) { self.installer.emit_error(&error); }
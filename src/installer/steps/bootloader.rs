@@ -9,12 +9,13 @@ use std::{
     os::unix::ffi::{OsStrExt, OsStringExt},
     path::{Path, PathBuf},
 };
+use crate::installer::Progress;
 use crate::Config;
 use crate::MODIFY_BOOT_ORDER;
 
 use super::mount_efivars;
 
-pub fn bootloader<F: FnMut(i32)>(
+pub fn bootloader<F: FnMut(Progress)>(
     disks: &Disks,
     mount_dir: &Path,
     bootloader: Bootloader,
@@ -33,6 +34,11 @@ pub fn bootloader<F: FnMut(i32)>(
     });
 
     info!("{}: installing bootloader for {:?}", bootloader_dev.display(), bootloader);
+    callback(Progress::SubStep(format!(
+        "installing {:?} boot loader to {}",
+        bootloader,
+        bootloader_dev.display()
+    )));
 
     {
         let efi_path = {
@@ -164,7 +170,7 @@ pub fn bootloader<F: FnMut(i32)>(
         }
     }
 
-    callback(99);
+    callback(Progress::Percent(99));
 
     Ok(())
 }
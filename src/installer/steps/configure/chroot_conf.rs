@@ -1,17 +1,19 @@
-use crate::chroot::{Chroot, Command};
+use crate::chroot::Chroot;
 use crate::errors::IoContext;
+use crate::external::Invocation;
 use crate::misc;
 use partition_identity::PartitionID;
 use proc_mounts::MountList;
 use std::{
     fs,
     io::{self, Write},
+    os::unix::fs::PermissionsExt,
     path::Path,
     process::Stdio,
 };
 use sys_mount::*;
 use crate::timezones::Region;
-use crate::Config;
+use crate::{Config, UserAccountCreate};
 
 const APT_OPTIONS: &[&str] = &[
     "-o",
@@ -130,29 +132,71 @@ impl<'a> ChrootConfigurator<'a> {
     }
 
     /// Create a new user account.
-    pub fn create_user(
-        &self,
-        user: &str,
-        pass: Option<&str>,
-        fullname: Option<&str>,
-    ) -> io::Result<()> {
-        let mut command = self.chroot.command("useradd", &["-m", "-G", "adm,sudo"]);
-        if let Some(name) = fullname {
-            command.args(&["-c", name, user]);
-        } else {
-            command.arg(user);
-        };
+    /// Create a user account, along with its primary group and authorized SSH keys.
+    pub fn create_user(&self, user: &UserAccountCreate) -> io::Result<()> {
+        let name = user.username.as_str();
+        let uid = user.uid.map(|uid| uid.to_string());
+        let gid = user.gid.map(|gid| gid.to_string());
+        let groups =
+            if user.groups.is_empty() { "adm,sudo".to_owned() } else { user.groups.join(",") };
+
+        if let Some(ref gid) = gid {
+            self.chroot.command("groupadd", &["-g", gid, name]).run()?;
+        }
+
+        let mut command = self.chroot.command("useradd", &["-m", "-G", &groups]);
+        if let Some(ref uid) = uid {
+            command.args(&["-u", uid]);
+        }
+
+        if let Some(ref gid) = gid {
+            command.args(&["-g", gid]);
+        }
 
+        if let Some(ref shell) = user.shell {
+            command.args(&["-s", shell]);
+        }
+
+        if let Some(ref fullname) = user.realname {
+            command.args(&["-c", fullname]);
+        }
+
+        command.arg(name);
         command.run()?;
 
-        if let Some(pass) = pass {
+        if let Some(pass) = user.password.as_deref() {
             let pass = [pass, "\n", pass, "\n"].concat();
-            self.chroot.command("passwd", &[user]).stdin_input(&pass).run()?;
+            self.chroot.command("passwd", &[name]).stdin_input(&pass).run()?;
+        }
+
+        if !user.ssh_keys.is_empty() {
+            self.authorize_keys(name, &user.ssh_keys)?;
         }
 
         Ok(())
     }
 
+    /// Writes the keys to the user's `~/.ssh/authorized_keys`, which sshd requires to be
+    /// owned by the user, and not writable by others.
+    fn authorize_keys(&self, user: &str, keys: &[String]) -> io::Result<()> {
+        let ssh_dir = ["/home/", user, "/.ssh"].concat();
+        let target_dir = self.chroot.path.join(&ssh_dir[1..]);
+        let authorized_keys = target_dir.join("authorized_keys");
+
+        info!("authorizing {} SSH keys for {}", keys.len(), user);
+        fs::create_dir_all(&target_dir)
+            .and_then(|_| fs::set_permissions(&target_dir, fs::Permissions::from_mode(0o700)))
+            .with_context(|why| format!("failed to create {}: {}", ssh_dir, why))?;
+
+        let mut contents = keys.join("\n");
+        contents.push('\n');
+        fs::write(&authorized_keys, contents)
+            .and_then(|_| fs::set_permissions(&authorized_keys, fs::Permissions::from_mode(0o600)))
+            .with_context(|why| format!("failed to write {}/authorized_keys: {}", ssh_dir, why))?;
+
+        self.chroot.command("chown", &["-R", &[user, ":"].concat(), &ssh_dir]).run()
+    }
+
     /// Disable the nvidia fallback service.
     pub fn disable_nvidia_fallback(&self) {
         info!("attempting to disable nvidia-fallback.service");
@@ -323,8 +367,11 @@ impl<'a> ChrootConfigurator<'a> {
             .or_else(|| PartitionID::get_uuid(&efi_mount.source))
             .expect("/recovery does not have a UUID");
 
-        let cdrom_uuid =
-            Command::new("findmnt").args(&["-n", "-o", "UUID", "/cdrom"]).run_with_stdout()?;
+        let findmnt = Invocation::new("findmnt")
+            .args(&["-n", "-o", "UUID", "/cdrom"])
+            .run()
+            .with_context(|why| format!("failed to execute findmnt: {}", why))?;
+        let cdrom_uuid = String::from_utf8_lossy(&findmnt.stdout);
         let cdrom_uuid = cdrom_uuid.trim();
 
         // If we are installing from the recovery partition, then we can skip this step.
@@ -2,7 +2,7 @@ use crate::bootloader::Bootloader;
 mod chroot_conf;
 use self::chroot_conf::ChrootConfigurator;
 use super::{mount_cdrom, mount_efivars};
-use crate::installer::{conf::RecoveryEnv, steps::normalize_os_release_name};
+use crate::installer::{conf::RecoveryEnv, steps::normalize_os_release_name, Progress};
 use crate::chroot::Chroot;
 use crate::distribution;
 use crate::errors::*;
@@ -63,14 +63,14 @@ macro_rules! map_errors {
     }
 }
 
-pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i32)>(
+pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(Progress)>(
     recovery_conf: Option<&mut RecoveryEnv>,
     disks: &D,
     mount_dir: P,
     config: &Config,
     iso_os_release: &OsRelease,
     region: Option<&Region>,
-    user: Option<&UserAccountCreate>,
+    users: &[UserAccountCreate],
     remove_pkgs: &[S],
     mut callback: F,
 ) -> io::Result<()> {
@@ -85,7 +85,8 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         ..extend_from_slice(distribution::debian::get_bootloader_packages(&iso_os_release));
     };
 
-    callback(5);
+    callback(Progress::Percent(5));
+    callback(Progress::SubStep("generating fstab and crypttab".into()));
 
     let lvm_autodetection = || {
         // Ubuntu's LVM auto-detection doesn't seem to work for activating root volumes.
@@ -132,7 +133,7 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             });
         });
 
-        callback(10);
+        callback(Progress::Percent(10));
         map_errors! {
             b => "lvm autodetection error";
             c => "failed to generate fstab / crypttab"
@@ -156,12 +157,12 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         let efivars_mount = mount_efivars(&mount_dir)?;
         let cdrom_mount = mount_cdrom(&mount_dir)?;
 
-        callback(15);
+        callback(Progress::Percent(15));
 
         let root_entry = disks.get_block_info_of("/")?;
         let _recovery_entry = disks.get_block_info_of("/recovery");
 
-        callback(20);
+        callback(Progress::Percent(20));
 
         let luks_uuid = root_entry
             .uid
@@ -172,7 +173,7 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             .and_then(PartitionID::get_uuid)
             .and_then(|uuid| if uuid == root_entry.uid { None } else { Some(uuid) });
 
-        callback(25);
+        callback(Progress::Percent(25));
 
         let root_uuid = &root_entry.uid;
         if let Some(conf) = recovery_conf {
@@ -184,7 +185,7 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             )?;
         }
 
-        callback(30);
+        callback(Progress::Percent(30));
 
         let (retain, lang_output) = rayon::join(
             // Get packages required by this disk configuration.
@@ -239,7 +240,8 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             Bootloader::Efi => (),
         }
 
-        callback(35);
+        callback(Progress::Percent(35));
+        callback(Progress::SubStep(format!("installing {} packages", install_pkgs.len())));
 
         // TODO: use a macro to make this more manageable.
         let chroot = ChrootConfigurator::new(chroot);
@@ -257,15 +259,8 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             Ok(())
         };
 
-        let useradd = if let Some(ref user) = user {
-            chroot.create_user(
-                &user.username,
-                user.password.as_deref(),
-                user.realname.as_deref(),
-            )
-        } else {
-            Ok(())
-        };
+        let useradd =
+            users.iter().map(|user| chroot.create_user(user)).collect::<io::Result<()>>();
 
         let apt_install = chroot
             .cdrom_add()
@@ -281,10 +276,11 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             apt_install => "error installing packages";
             kernel_copy => "error copying kernel from casper to chroot";
             timezone => "error setting timezone";
-            useradd => "error creating user account"
+            useradd => "error creating user accounts"
         }
 
-        callback(70);
+        callback(Progress::Percent(70));
+        callback(Progress::SubStep(format!("removing {} packages", remove.len())));
 
         let apt_remove = chroot.apt_remove(&remove);
         let recovery = chroot.recovery(
@@ -299,11 +295,12 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             recovery => "error creating recovery partition"
         }
 
-        callback(75);
+        callback(Progress::Percent(75));
+        callback(Progress::SubStep("installing boot loader packages".into()));
 
         chroot.bootloader().with_context(|why| format!("error installing bootloader: {}", why))?;
 
-        callback(80);
+        callback(Progress::Percent(80));
 
         if configure_graphics {
             chroot.disable_nvidia_fallback();
@@ -312,12 +309,13 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         chroot
             .keyboard_layout(config)
             .with_context(|why| format!("error setting keyboard layout: {}", why))?;
-        callback(85);
+        callback(Progress::Percent(85));
+        callback(Progress::SubStep("updating initramfs".into()));
 
         chroot
             .update_initramfs()
             .with_context(|why| format!("error updating initramfs: {}", why))?;
-        callback(90);
+        callback(Progress::Percent(90));
 
         // Sync to the disk before unmounting
         unsafe {
@@ -331,11 +329,11 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         }
 
         drop(efivars_mount);
-        callback(95);
+        callback(Progress::Percent(95));
     }
 
     configure_dir.close()?;
-    callback(100);
+    callback(Progress::Percent(100));
 
     Ok(())
 }
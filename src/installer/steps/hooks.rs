@@ -0,0 +1,106 @@
+use crate::{chroot::Chroot, errors::IoContext, installer::Progress};
+use std::{
+    cell::RefCell,
+    fs, io, iter,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// The directory within the target that hooks are copied to before they are executed.
+const HOOK_DIR: &str = "tmp/distinst-hooks";
+
+/// Executes each hook script within the target, in order. Directories of hooks are expanded
+/// to the executable files within them, sorted by name.
+pub fn hooks<F: FnMut(Progress)>(
+    mount_dir: &Path,
+    hooks: &[PathBuf],
+    mut callback: F,
+) -> io::Result<()> {
+    let scripts = collect_hooks(hooks)?;
+    let hook_dir = mount_dir.join(HOOK_DIR);
+    fs::create_dir_all(&hook_dir)
+        .with_context(|why| format!("failed to create {}: {}", hook_dir.display(), why))?;
+
+    let result = run_hooks(mount_dir, &hook_dir, &scripts, &mut callback);
+
+    if let Err(why) = fs::remove_dir_all(&hook_dir) {
+        warn!("failed to remove {}: {}", hook_dir.display(), why);
+    }
+
+    result
+}
+
+fn run_hooks<F: FnMut(Progress)>(
+    mount_dir: &Path,
+    hook_dir: &Path,
+    scripts: &[PathBuf],
+    callback: &mut F,
+) -> io::Result<()> {
+    let chroot = Chroot::new(mount_dir)
+        .with_context(|why| format!("failed to create chroot for hooks: {}", why))?;
+
+    for (id, script) in scripts.iter().enumerate() {
+        let name = script.file_name().map_or_else(|| "hook".into(), |name| name.to_string_lossy());
+        let target_name = format!("{:02}-{}", id, name);
+        let target = hook_dir.join(&target_name);
+
+        info!("running hook {}", script.display());
+        callback(Progress::SubStep(format!("running hook {}", script.display())));
+
+        fs::copy(script, &target)
+            .and_then(|_| fs::set_permissions(&target, fs::Permissions::from_mode(0o755)))
+            .with_context(|why| format!("failed to copy hook {}: {}", script.display(), why))?;
+
+        let output = RefCell::new(&mut *callback);
+        let log = |line: &str| {
+            info!("{}: {}", name, line);
+            (*output.borrow_mut())(Progress::Log(line.into()));
+        };
+
+        chroot
+            .command(["/", HOOK_DIR, "/", &target_name].concat(), iter::empty::<&str>())
+            .run_with_callbacks(&log, &log)
+            .with_context(|why| format!("hook {} failed: {}", script.display(), why))?;
+
+        callback(Progress::Percent(((id + 1) * 100 / scripts.len()) as i32));
+    }
+
+    Ok(())
+}
+
+fn collect_hooks(hooks: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut scripts = Vec::new();
+
+    for hook in hooks {
+        let metadata = fs::metadata(hook)
+            .with_context(|why| format!("hook {} is not accessible: {}", hook.display(), why))?;
+
+        if !metadata.is_dir() {
+            scripts.push(hook.clone());
+            continue;
+        }
+
+        let mut entries = fs::read_dir(hook)
+            .and_then(|entries| {
+                entries.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()
+            })
+            .with_context(|why| format!("failed to read hooks in {}: {}", hook.display(), why))?;
+
+        entries.sort();
+        for entry in entries {
+            if is_executable(&entry) {
+                scripts.push(entry);
+            } else {
+                info!("skipping {}, which is not an executable file", entry.display());
+            }
+        }
+    }
+
+    Ok(scripts)
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
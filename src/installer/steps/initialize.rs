@@ -5,14 +5,16 @@ use std::{
     io::{self, BufRead},
     path::{Path, PathBuf},
 };
+use crate::installer::Progress;
 use crate::Config;
 
-pub fn initialize<F: FnMut(i32)>(
+pub fn initialize<F: FnMut(Progress)>(
     disks: &mut Disks,
     config: &Config,
     mut callback: F,
 ) -> io::Result<(PathBuf, Vec<String>)> {
     info!("Initializing");
+    callback(Progress::SubStep("verifying configuration and unmounting devices".into()));
 
     let fetch_squashfs = || match Path::new(&config.squashfs).canonicalize() {
         Ok(squashfs) => {
@@ -30,32 +32,7 @@ pub fn initialize<F: FnMut(i32)>(
         }
     };
 
-    let fetch_packages = || {
-        let mut remove_pkgs = Vec::new();
-        {
-            let file = match misc::open(&config.remove) {
-                Ok(file) => file,
-                Err(err) => {
-                    error!("config.remove: {}", err);
-                    return Err(err);
-                }
-            };
-
-            // Collects the packages that are to be removed from the install.
-            for line_res in io::BufReader::new(file).lines() {
-                match line_res {
-                    // Only add package if it is not contained within lang_packs.
-                    Ok(line) => remove_pkgs.push(line),
-                    Err(err) => {
-                        error!("config.remove: {}", err);
-                        return Err(err);
-                    }
-                }
-            }
-        }
-
-        Ok(remove_pkgs)
-    };
+    let fetch_packages = || remove_packages(config);
 
     let verify_disks = |disks: &Disks| {
         disks.verify_keyfile_paths()?;
@@ -121,7 +98,33 @@ pub fn initialize<F: FnMut(i32)>(
         })
         .collect::<io::Result<()>>()?;
 
-    callback(100);
+    callback(Progress::Percent(100));
 
     Ok((squashfs, remove_pkgs))
 }
+
+/// Reads the manifest of packages which are to be removed from the install.
+pub fn remove_packages(config: &Config) -> io::Result<Vec<String>> {
+    let file = match misc::open(&config.remove) {
+        Ok(file) => file,
+        Err(err) => {
+            error!("config.remove: {}", err);
+            return Err(err);
+        }
+    };
+
+    // Collects the packages that are to be removed from the install.
+    let mut remove_pkgs = Vec::new();
+    for line_res in io::BufReader::new(file).lines() {
+        match line_res {
+            // Only add package if it is not contained within lang_packs.
+            Ok(line) => remove_pkgs.push(line),
+            Err(err) => {
+                error!("config.remove: {}", err);
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(remove_pkgs)
+}
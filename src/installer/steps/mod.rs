@@ -1,10 +1,13 @@
 mod bootloader;
 mod configure;
+mod hooks;
 mod initialize;
 mod partition;
+mod nix;
 mod nixos;
+mod nixos_progress;
 
-pub use self::{bootloader::*, configure::*, initialize::*, partition::*, nixos::*};
+pub use self::{bootloader::*, configure::*, hooks::*, initialize::*, partition::*, nixos::*};
 
 use std::{
     borrow::Cow,
@@ -25,6 +28,7 @@ pub enum Step {
     Extract,
     Configure,
     Bootloader,
+    Hooks,
 }
 
 fn mount_cdrom(mount_dir: &Path) -> io::Result<Option<(UnmountDrop<Mount>, PathBuf)>> {
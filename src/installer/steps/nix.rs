@@ -0,0 +1,348 @@
+//! A small representation of Nix expressions, from which the NixOS configuration files are
+//! generated, so that values are always escaped and the output is formatted deterministically.
+
+use std::{
+    fmt::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const KEYWORDS: &[&str] =
+    &["assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with"];
+
+/// A Nix expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Path(PathBuf),
+    List(Vec<Expr>),
+    AttrSet(AttrSet),
+    /// A function which takes an attribute set containing the given arguments, such as the
+    /// `{ config, pkgs, lib, ... }:` header of a NixOS module.
+    Function(Vec<String>, Box<Expr>),
+    /// A variable, or an attribute selected from one, such as `nixpkgs.lib.nixosSystem`.
+    Var(String),
+    /// A function applied to an argument.
+    Apply(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// A NixOS module, which takes the given arguments and evaluates to `body`.
+    pub fn module(args: &[&str], body: AttrSet) -> Expr {
+        Expr::Function(args.iter().map(|&arg| arg.into()).collect(), Box::new(body.into()))
+    }
+
+    pub fn var(name: &str) -> Expr { Expr::Var(name.into()) }
+
+    /// Applies the function named `function` to `arg`.
+    pub fn apply<A: Into<Expr>>(function: &str, arg: A) -> Expr {
+        Expr::Apply(Box::new(Expr::var(function)), Box::new(arg.into()))
+    }
+
+    fn is_scalar(&self) -> bool {
+        match *self {
+            Expr::Bool(_) | Expr::Int(_) | Expr::String(_) | Expr::Path(_) | Expr::Var(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// An attribute set, whose attributes are written in the order that they were assigned.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AttrSet(Vec<(Vec<String>, Expr)>);
+
+impl AttrSet {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Assigns a value to a dotted attribute path, such as `boot.loader.grub.enable`,
+    /// replacing any value that was previously assigned to the same path.
+    pub fn set<V: Into<Expr>>(&mut self, path: &str, value: V) -> &mut Self {
+        self.set_path(&path.split('.').collect::<Vec<_>>(), value)
+    }
+
+    /// Assigns a value to an attribute path whose components may themselves contain dots.
+    pub fn set_path<V: Into<Expr>>(&mut self, path: &[&str], value: V) -> &mut Self {
+        let value = value.into();
+        match self.0.iter().position(|(existing, _)| existing.iter().eq(path)) {
+            Some(id) => self.0[id].1 = value,
+            None => self.0.push((path.iter().map(|&name| name.into()).collect(), value)),
+        }
+
+        self
+    }
+
+    pub fn with<V: Into<Expr>>(mut self, path: &str, value: V) -> Self {
+        self.set(path, value);
+        self
+    }
+}
+
+impl From<bool> for Expr {
+    fn from(value: bool) -> Self { Expr::Bool(value) }
+}
+
+impl From<i64> for Expr {
+    fn from(value: i64) -> Self { Expr::Int(value) }
+}
+
+impl From<u32> for Expr {
+    fn from(value: u32) -> Self { Expr::Int(i64::from(value)) }
+}
+
+impl<'a> From<&'a str> for Expr {
+    fn from(value: &'a str) -> Self { Expr::String(value.into()) }
+}
+
+impl From<String> for Expr {
+    fn from(value: String) -> Self { Expr::String(value) }
+}
+
+impl<'a> From<&'a Path> for Expr {
+    fn from(value: &'a Path) -> Self { Expr::Path(value.into()) }
+}
+
+impl From<PathBuf> for Expr {
+    fn from(value: PathBuf) -> Self { Expr::Path(value) }
+}
+
+impl From<AttrSet> for Expr {
+    fn from(value: AttrSet) -> Self { Expr::AttrSet(value) }
+}
+
+impl<T: Into<Expr>> From<Vec<T>> for Expr {
+    fn from(value: Vec<T>) -> Self { Expr::List(value.into_iter().map(Into::into).collect()) }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write_expr(f, self, 0) }
+}
+
+/// Renders an expression as the contents of a file, preceded by a comment.
+pub fn file(comment: &str, expr: &Expr) -> String {
+    let mut output = String::new();
+    for line in comment.lines() {
+        output.push('#');
+        if !line.is_empty() {
+            output.push(' ');
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
+
+    let _ = writeln!(output, "{}", expr);
+    output
+}
+
+fn write_expr<W: Write>(f: &mut W, expr: &Expr, level: usize) -> fmt::Result {
+    match *expr {
+        Expr::Bool(value) => write!(f, "{}", value),
+        Expr::Int(value) => write!(f, "{}", value),
+        Expr::String(ref value) => write_string(f, value),
+        Expr::Path(ref path) => write_path(f, path),
+        Expr::List(ref items) if items.is_empty() => f.write_str("[ ]"),
+        Expr::List(ref items) if items.iter().all(Expr::is_scalar) => {
+            f.write_char('[')?;
+            for item in items {
+                f.write_char(' ')?;
+                write_expr(f, item, level)?;
+            }
+            f.write_str(" ]")
+        }
+        Expr::List(ref items) => {
+            f.write_str("[\n")?;
+            for item in items {
+                write_indent(f, level + 1)?;
+                write_expr(f, item, level + 1)?;
+                f.write_char('\n')?;
+            }
+            write_indent(f, level)?;
+            f.write_char(']')
+        }
+        Expr::AttrSet(ref set) if set.is_empty() => f.write_str("{ }"),
+        Expr::AttrSet(ref set) => {
+            f.write_str("{\n")?;
+            for (path, value) in &set.0 {
+                write_indent(f, level + 1)?;
+                for (id, name) in path.iter().enumerate() {
+                    if id != 0 {
+                        f.write_char('.')?;
+                    }
+
+                    if is_identifier(name) {
+                        f.write_str(name)?;
+                    } else {
+                        write_string(f, name)?;
+                    }
+                }
+                f.write_str(" = ")?;
+                write_expr(f, value, level + 1)?;
+                f.write_str(";\n")?;
+            }
+            write_indent(f, level)?;
+            f.write_char('}')
+        }
+        Expr::Function(ref args, ref body) => {
+            f.write_str("{ ")?;
+            for arg in args {
+                f.write_str(arg)?;
+                f.write_str(", ")?;
+            }
+            // Only a function which makes up the whole file is separated from its body.
+            f.write_str(if level == 0 { "... }:\n\n" } else { "... }: " })?;
+            write_expr(f, body, level)
+        }
+        Expr::Var(ref name) => f.write_str(name),
+        Expr::Apply(ref function, ref arg) => {
+            write_expr(f, function, level)?;
+            f.write_char(' ')?;
+            match **arg {
+                Expr::Function(..) | Expr::Apply(..) => {
+                    f.write_char('(')?;
+                    write_expr(f, arg, level)?;
+                    f.write_char(')')
+                }
+                _ => write_expr(f, arg, level),
+            }
+        }
+    }
+}
+
+fn write_indent<W: Write>(f: &mut W, level: usize) -> fmt::Result {
+    for _ in 0..level {
+        f.write_str("  ")?;
+    }
+
+    Ok(())
+}
+
+/// Writes a double-quoted string, escaping anything that Nix would otherwise interpret.
+fn write_string<W: Write>(f: &mut W, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    let mut chars = value.chars().peekable();
+    while let Some(character) = chars.next() {
+        match character {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '$' if chars.peek() == Some(&'{') => f.write_str("\\$")?,
+            _ => f.write_char(character)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Writes a path literal, or an expression which evaluates to the path when it contains
+/// characters that a literal may not.
+fn write_path<W: Write>(f: &mut W, path: &Path) -> fmt::Result {
+    let path = path.to_string_lossy();
+    let literal = if path.starts_with('/') || path.starts_with("./") || path.starts_with("../") {
+        path.into_owned()
+    } else {
+        ["./", &path].concat()
+    };
+
+    let is_literal = !literal.ends_with('/')
+        && literal.chars().all(|c| c.is_ascii_alphanumeric() || "/._+-".contains(c));
+
+    if is_literal {
+        f.write_str(&literal)
+    } else if literal.starts_with('/') {
+        f.write_str("(/. + ")?;
+        write_string(f, &literal)?;
+        f.write_char(')')
+    } else {
+        f.write_str("(./. + ")?;
+        write_string(f, &["/", &literal].concat())?;
+        f.write_char(')')
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '\'' || c == '-')
+        && !KEYWORDS.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        let expr = Expr::from("say \"hi\" to ${user}\\$HOME\n");
+        assert_eq!(expr.to_string(), r#""say \"hi\" to \${user}\\$HOME\n""#);
+
+        assert_eq!(Expr::from(Path::new("/etc/nixos")).to_string(), "/etc/nixos");
+        assert_eq!(Expr::from(Path::new("boot.nix")).to_string(), "./boot.nix");
+        assert_eq!(Expr::from(Path::new("/mnt/my disk")).to_string(), r#"(/. + "/mnt/my disk")"#);
+
+        let mut set = AttrSet::new();
+        set.set("users.users.root.isNormalUser", false)
+            .set_path(&["boot", "initrd", "secrets", "/etc/key.bin"], "/etc/key.bin")
+            .set("let.in", true);
+        assert_eq!(
+            Expr::from(set).to_string(),
+            [
+                "{\n",
+                "  users.users.root.isNormalUser = false;\n",
+                "  boot.initrd.secrets.\"/etc/key.bin\" = \"/etc/key.bin\";\n",
+                "  \"let\".\"in\" = true;\n",
+                "}",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn module() {
+        let mut body = AttrSet::new();
+        body.set("imports", vec![PathBuf::from("hardware-configuration.nix")])
+            .set("boot.loader.grub.enable", true)
+            .set("boot.loader.grub.devices", vec!["nodev"])
+            .set("networking.hostName", "old")
+            .set("networking.hostName", "distinst")
+            .set("users.groups", AttrSet::new())
+            .set(
+                "swapDevices",
+                Expr::List(vec![AttrSet::new()
+                    .with("device", "/dev/sda2")
+                    .with("randomEncryption.enable", true)
+                    .into()]),
+            )
+            .set("users.users.user.uid", 1000u32);
+
+        let expected = [
+            "# Generated by distinst\n",
+            "#\n",
+            "# Do not edit\n",
+            "{ config, pkgs, ... }:\n",
+            "\n",
+            "{\n",
+            "  imports = [ ./hardware-configuration.nix ];\n",
+            "  boot.loader.grub.enable = true;\n",
+            "  boot.loader.grub.devices = [ \"nodev\" ];\n",
+            "  networking.hostName = \"distinst\";\n",
+            "  users.groups = { };\n",
+            "  swapDevices = [\n",
+            "    {\n",
+            "      device = \"/dev/sda2\";\n",
+            "      randomEncryption.enable = true;\n",
+            "    }\n",
+            "  ];\n",
+            "  users.users.user.uid = 1000;\n",
+            "}\n",
+        ]
+        .concat();
+
+        assert_eq!(
+            file("Generated by distinst\n\nDo not edit", &Expr::module(&["config", "pkgs"], body)),
+            expected
+        );
+    }
+}
@@ -1,32 +1,29 @@
 extern crate json;
 use self::json::object;
-use self::json::array;
 use self::json::JsonValue;
-use self::json::parse;
 
-use crate::installer::{conf::RecoveryEnv};
+use crate::installer::{conf::RecoveryEnv, Progress};
 use crate::errors::*;
-use disks::{Bootloader, Disks};
-use crate::installer::traits::InstallerDiskOps;
+use crate::external::Invocation;
+use disks::{Bootloader, Diagnostic, Disks, PartitionFlag, PartitionInfo};
+use disk_types::{FileSystem, SectorExt};
+use crate::installer::traits::{CryptKey, InstallerDiskOps};
+use super::nix::{self, AttrSet, Expr};
+use super::nixos_progress::InstallProgress;
+use err_derive::Error;
 use std::{
-    path::Path,
+    fmt,
+    path::{Path, PathBuf},
     path::Component,
-    process::{Command, Stdio},
-    fs,
-    io::{self, BufReader, BufRead},
+    fs::{self, OpenOptions},
+    io::{self, Write},
     sync::atomic::Ordering,
-    os::unix::fs::PermissionsExt,
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
 };
 use timezones::Region;
 use crate::Config;
-use crate::UserAccountCreate;
-
-const USE_STATUS: [(&str, f64); 3] = [
-    /* status, weight*/
-    ("builds", 1.0),
-    ("copyPath", 0.1),
-    ("fileTransfer", 0.3)
-];
+use crate::{BootManager, UserAccountCreate, NO_EFI_VARIABLES, OFFLINE};
+use partition_identity::PartitionID;
 
 #[macro_export]
 macro_rules! str {
@@ -35,104 +32,386 @@ macro_rules! str {
     }
 }
 
+/// The prefix of the file that the log of a failed install is preserved in, as the target may
+/// be wiped. Each failed install preserves its log in a new file.
+pub const NIXOS_INSTALL_LOG: &str = "/tmp/distinst-nixos-install";
+
+/// The options which prevent Nix from substituting paths from the remote caches. They are
+/// given to both the offline preflight and the installer, so that both see the same store.
+const OFFLINE_OPTIONS: &[&str] = &["--option", "substituters", ""];
+
+/// The installers which pass their options on to `nixos-install` unchanged, and so will build
+/// the system that the offline preflight evaluates.
+const OFFLINE_INSTALLERS: &[&str] = &["nixos-install", "nixos-install-wrapped"];
+
+/// The phases of a NixOS install, one of which is reported when the install fails.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NixosPhase {
+    /// Generating the configuration files in `/etc/nixos`.
+    Configure,
+    /// Initializing the configuration from the conf-tool template.
+    Init,
+    /// Checking that the system can be installed without the network.
+    Preflight,
+    /// Running `nixos-install`, with the stage that it last announced.
+    Install(Option<&'static str>),
+}
+
+impl fmt::Display for NixosPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NixosPhase::Configure => f.write_str("generating the configuration"),
+            NixosPhase::Init => f.write_str("initializing the configuration"),
+            NixosPhase::Preflight => f.write_str("checking the system can be installed offline"),
+            NixosPhase::Install(None) => f.write_str("running nixos-install"),
+            NixosPhase::Install(Some(stage)) => write!(f, "running nixos-install ({})", stage),
+        }
+    }
+}
+
+/// A NixOS install which failed, and the phase that it failed in.
+#[derive(Debug, Error)]
+#[error(display = "NixOS install failed while {}: {}", phase, why)]
+pub struct NixosInstallError {
+    pub phase: NixosPhase,
+    /// The copy of the install log which was preserved outside of the target, if there was one.
+    pub log:   Option<PathBuf>,
+    #[error(source)]
+    pub why:   io::Error,
+}
 
-pub fn nixos<P: AsRef<Path>, F: FnMut(i32)>(
+/// Generates the NixOS configuration of the target, and builds the system into it.
+///
+/// When the install fails, the returned error contains a `NixosInstallError`, and the target
+/// is wiped if the configuration asks for it. Only the partitions mounted at the `formatted`
+/// targets are wiped.
+pub fn nixos<P: AsRef<Path>, F: FnMut(Progress)>(
     recovery_conf: Option<&mut RecoveryEnv>,
     bootloader: Bootloader,
     disks: &Disks,
     mount_dir: P,
     config: &Config,
     region: Option<&Region>,
-    user: Option<&UserAccountCreate>,
+    users: &[UserAccountCreate],
+    formatted: &[PathBuf],
     mut callback: F,
-) -> io::Result<()> {
-    let mount_dir = mount_dir.as_ref().canonicalize().unwrap();
+) -> io::Result<Vec<PathBuf>> {
+    let mount_dir = mount_dir.as_ref().canonicalize()
+        .with_context(|why| format!("failed to resolve the target: {}", why))?;
+
+    let mut phase = NixosPhase::Configure;
+    install(
+        &mut phase,
+        recovery_conf,
+        bootloader,
+        disks,
+        &mount_dir,
+        config,
+        region,
+        users,
+        &mut callback
+    ).map_err(|why| install_failed(&mount_dir, formatted, config, phase, why))
+}
+
+/// Preserves the install log, and wipes the target if requested, before reporting the phase
+/// that the install failed in.
+fn install_failed(
+    mount_dir: &Path,
+    formatted: &[PathBuf],
+    config: &Config,
+    phase: NixosPhase,
+    why: io::Error,
+) -> io::Error {
+    let install_log = mount_dir.join("install.log");
+    let log = if install_log.exists() {
+        match preserve_log(&install_log) {
+            Ok(path) => {
+                info!("the install log was preserved at {}", path.display());
+                Some(path)
+            }
+            Err(why) => {
+                warn!("failed to preserve the install log: {}", why);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if config.nixos.wipe {
+        let formatted = formatted_targets(formatted, mount_dir);
+        if formatted.is_empty() {
+            warn!("not wiping the target, as none of its partitions were formatted by the install");
+        } else {
+            info!("wiping the partially-installed target");
+            if let Err(why) = wipe_target(mount_dir, &formatted) {
+                warn!("failed to wipe {}: {}", mount_dir.display(), why);
+            }
+        }
+    }
+
+    io::Error::new(why.kind(), NixosInstallError { phase, log, why })
+}
+
+/// Copies the install log into a new file which only root may read. The file is created
+/// exclusively, so that an existing file or symlink in its place is never written through.
+fn preserve_log(install_log: &Path) -> io::Result<PathBuf> {
+    let mut source = fs::File::open(install_log)?;
+    let mut attempt = 0;
+    loop {
+        let path = PathBuf::from(format!(
+            "{}-{}-{}.log",
+            NIXOS_INSTALL_LOG,
+            std::process::id(),
+            attempt
+        ));
+
+        match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path) {
+            Ok(mut file) => {
+                io::copy(&mut source, &mut file)?;
+                return Ok(path);
+            }
+            Err(ref why) if why.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(why) => return Err(why),
+        }
+    }
+}
+
+/// Where each partition that this install formatted is mounted within the target.
+fn formatted_targets(formatted: &[PathBuf], mount_dir: &Path) -> Vec<PathBuf> {
+    formatted
+        .iter()
+        .map(|target| mount_dir.join(target.strip_prefix("/").unwrap_or(target)))
+        .collect()
+}
+
+/// Removes everything within the target that is stored on a partition which this install
+/// formatted, which are given by `formatted`. Partitions which were not formatted, such as
+/// an existing `/home`, or an EFI partition shared with other systems, are never entered.
+/// The directories that other partitions are mounted on are kept, along with `lost+found`.
+fn wipe_target(dir: &Path, formatted: &[PathBuf]) -> io::Result<()> {
+    if !formatted.iter().any(|target| target == dir) {
+        return Ok(());
+    }
+
+    wipe_dir(dir, formatted)
+}
+
+fn wipe_dir(dir: &Path, formatted: &[PathBuf]) -> io::Result<()> {
+    let device = fs::metadata(dir)?.dev();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type()?.is_dir() {
+            fs::remove_file(&path)?;
+            continue;
+        }
+
+        if fs::metadata(&path)?.dev() != device {
+            wipe_target(&path, formatted)?;
+            continue;
+        }
+
+        wipe_dir(&path, formatted)?;
+
+        let is_empty = fs::read_dir(&path)?.next().is_none();
+        if is_empty && entry.file_name() != "lost+found" {
+            fs::remove_dir(&path)?;
+        }
+    }
+
+    Ok(())
+}
 
+fn install(
+    phase: &mut NixosPhase,
+    recovery_conf: Option<&mut RecoveryEnv>,
+    bootloader: Bootloader,
+    disks: &Disks,
+    mount_dir: &Path,
+    config: &Config,
+    region: Option<&Region>,
+    users: &[UserAccountCreate],
+    callback: &mut dyn FnMut(Progress),
+) -> io::Result<Vec<PathBuf>> {
     info!("writing config");
+    callback(Progress::SubStep("generating NixOS configuration".into()));
 
-    let seed = Path::new("/etc/conf-tool-seed.json");
+    let seed = &config.nixos.seed;
     let mut extra_config: Option<JsonValue> = None;
     if seed.exists() {
-        extra_config = Some(
-            json::parse(
-                &fs::read_to_string(seed).expect("failed to read conf-tool seed")
-            ).expect("failed to parse conf-tool seed")
-        );
+        let contents = fs::read_to_string(seed)
+            .with_context(|why| format!("failed to read conf-tool seed: {}", why))?;
+        extra_config = Some(json::parse(&contents).map_err(|why| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse conf-tool seed at {}: {}", seed.display(), why)
+        ))?);
     }
 
-    let json = generate_conftool_json(
+    let mut conf = generate_conftool_json(
         recovery_conf,
         disks,
         config,
         region,
-        user,
+        users,
         extra_config
-    );
+    )?;
+
+    if let Some(ref extra_json) = config.nixos.extra_json {
+        let overlay = json::parse(extra_json)
+            .ok()
+            .filter(JsonValue::is_object)
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "extra conf-tool configuration must be a JSON object"
+            ))?;
+
+        merge_json(&mut conf, overlay);
+    }
+
+    let json = json::stringify(conf);
 
     let boot = generate_boot_config(
         disks,
-        bootloader
+        bootloader,
+        config.boot_manager
     );
 
     let nix_conf_folder = mount_dir.join("etc/nixos");
 
     let target = mount_dir.to_str().unwrap();
 
-    fs::create_dir_all(&nix_conf_folder).expect("failed to mkdir /etc/nixos on target");
-    fs::write(nix_conf_folder.join("conf-tool.json"), json).expect("failed to write /etc/nixos/conf-tool.json");
-    fs::write(nix_conf_folder.join("boot.nix"), boot).expect("failed to write /etc/nixos/boot.nix");
+    fs::create_dir_all(&nix_conf_folder)
+        .with_context(|why| format!("failed to create /etc/nixos on the target: {}", why))?;
+    write_private(&nix_conf_folder.join("conf-tool.json"), &json)
+        .with_context(|why| format!("failed to write /etc/nixos/conf-tool.json: {}", why))?;
+    fs::write(nix_conf_folder.join("boot.nix"), boot)
+        .with_context(|why| format!("failed to write /etc/nixos/boot.nix: {}", why))?;
+
+    let mut configs = vec![
+        PathBuf::from("etc/nixos/conf-tool.json"),
+        PathBuf::from("etc/nixos/boot.nix"),
+        PathBuf::from("etc/nixos/hardware.nix"),
+    ];
+
+    // The disks are described by the layout that was just committed, so only the rest of the
+    // hardware is detected from the running system.
+    let hardware_scan = match Invocation::new("nixos-generate-config")
+        .args(&["--no-filesystems", "--show-hardware-config", "--root"])
+        .arg(target)
+        .run()
+    {
+        Ok(ref output) if output.success() => {
+            fs::write(nix_conf_folder.join("hardware-scan.nix"), &output.stdout)
+                .with_context(|why| format!("failed to write /etc/nixos/hardware-scan.nix: {}", why))?;
+            configs.push(PathBuf::from("etc/nixos/hardware-scan.nix"));
+            true
+        }
+        Ok(output) => {
+            warn!("hardware scan failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            false
+        }
+        Err(why) => {
+            warn!("failed to execute nixos-generate-config: {}", why);
+            false
+        }
+    };
+
+    let hardware = nix::file(
+        "Generated by distinst from the partitioned disks",
+        &hardware_module(disks, hardware_scan)
+    );
+    fs::write(nix_conf_folder.join("hardware.nix"), hardware)
+        .with_context(|why| format!("failed to write /etc/nixos/hardware.nix: {}", why))?;
+
+    let flake = if config.nixos.flake {
+        let nixpkgs = live_nixpkgs()?;
+        info!("pinning the flake to {}", nixpkgs.display());
+
+        let system = [std::env::consts::ARCH, "-linux"].concat();
+        let contents = nix::file(
+            "Generated by distinst, from the nixpkgs of the live image",
+            &flake_expr(&config.hostname, &system, &nixpkgs)
+        );
+        fs::write(nix_conf_folder.join("flake.nix"), contents)
+            .with_context(|why| format!("failed to write /etc/nixos/flake.nix: {}", why))?;
+        configs.push(PathBuf::from("etc/nixos/flake.nix"));
+
+        Some([target, "/etc/nixos"].concat())
+    } else {
+        None
+    };
 
     info!("setting up");
+    *phase = NixosPhase::Init;
 
-    let init = Command::new("conf")
+    let init = Invocation::new("conf")
             .arg("init")
             .arg("--root")
             .arg(target)
             .arg("--template")
-            .arg("solaros")
-            .arg("--hwScan")
-            .status()
-            .expect("failed to execute init command");
+            .arg(&config.nixos.template);
+
+    let output = init.run().with_context(|why| format!("failed to execute conf: {}", why))?;
+    if !output.success() {
+        return Err(init.failed(&output));
+    }
+
+    let offline = config.flags & OFFLINE != 0;
+    let closure = config.nixos.closure.as_deref();
 
-    if !init.success() {
-        io::Error::new(io::ErrorKind::Other, "failed to init");
+    if offline {
+        validate_offline(config)?;
+        *phase = NixosPhase::Preflight;
+        info!("checking that the system is in the local store");
+        callback(Progress::SubStep("checking that the system can be installed offline".into()));
+        let system = flake.as_ref().map(|flake| {
+            [flake.as_str(), "#nixosConfigurations.", &config.hostname].concat()
+        });
+        offline_preflight(mount_dir, closure, system.as_deref())?;
     }
 
     info!("running nixos-install");
+    *phase = NixosPhase::Install(None);
+    callback(Progress::SubStep("building the system with nixos-install".into()));
 
-    let mut install = Command::new("nixos-install-wrapped")
+    let mut command = Invocation::new(config.nixos.installer.as_str())
             .arg("--root")
             .arg(target)
             .arg("-v")
             .arg("--show-trace")
-            .env("LOGFILE", mount_dir.join("install.log").to_str().unwrap())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("failed to execute install command");
-
-    if let Some(ref mut stderr) = install.stderr {
-        for line in BufReader::new(stderr).lines() {
-            match progress(line.unwrap()) {
-                Some(p) => {
-                    callback((p * 100.0) as i32);
-                },
-                _ => ()
-            }
-        }
+            .env("LOGFILE", mount_dir.join("install.log"));
+
+    if let Some(closure) = closure {
+        warn!(
+            "installing the prebuilt system {}, which ignores the generated users, hostname \
+             and hardware-configuration.nix",
+            closure.display()
+        );
+        command = command.arg("--system").arg(closure);
+    } else if let Some(ref flake) = flake {
+        command = command.arg("--flake").arg([flake.as_str(), "#", &config.hostname].concat());
     }
 
-    let exit_status = match install.try_wait() {
-        Ok(Some(s)) => s,
-        Ok(None) => install.wait().unwrap(),
-        Err(e) => return Err(io::Error::new(io::ErrorKind::Other, "failed to install")),
-    };
+    // The live image's store is always used, so that only leaves the remote caches.
+    if offline {
+        command = command.args(OFFLINE_OPTIONS);
+    }
+
+    let mut progress = InstallProgress::default();
+    let install = command
+            .run_streamed(&mut |line| progress.parse(&String::from_utf8_lossy(line), callback))
+            .with_context(|why| format!("failed to execute {}: {}", config.nixos.installer, why))?;
 
-    if !exit_status.success() {
-        return Err(io::Error::new(io::ErrorKind::Other, "failed to install"));
+    *phase = NixosPhase::Install(progress.stage());
+
+    if !install.success() {
+        return Err(command.failed(&install));
     }
 
-    Ok(())
+    Ok(configs)
 }
 
 fn generate_conftool_json<D: InstallerDiskOps>(
@@ -140,23 +419,12 @@ fn generate_conftool_json<D: InstallerDiskOps>(
     disks: &D,
     config: &Config,
     region: Option<&Region>,
-    user: Option<&UserAccountCreate>,
+    users: &[UserAccountCreate],
     extra_config: Option<JsonValue>
-) -> String {
+) -> io::Result<JsonValue> {
     let mut j = if extra_config.is_some() { extra_config.clone().unwrap() } else { object!{} };
 
-    if user.is_some() {
-        let u = user.unwrap();
-        j["keys"]["users"] = array![u.username.clone()];
-
-        if u.password.is_some() {
-            j["keys"]["users"]["users"][u.username.clone()]["initialPassword"] = str!(u.password.clone().unwrap());
-        }
-
-        if u.realname.is_some() {
-            j["keys"]["users"]["users"][u.username.clone()]["name"] = str!(u.realname.clone().unwrap());
-        }
-    }
+    user_accounts(&mut j, users)?;
 
     if region.is_some() && region.unwrap().path().to_str().is_some() {
         let mut comp = region.unwrap().path().components();
@@ -180,113 +448,848 @@ fn generate_conftool_json<D: InstallerDiskOps>(
         j["keys"]["services"]["xserver"]["xkbVariant"] = str!(config.keyboard_variant.clone().unwrap());
     }
 
-    // TODO: crypttab? flags?
+    // TODO: flags?
+
+    return Ok(j);
+}
+
+/// Adds each user account to the configuration, with its password hashed.
+fn user_accounts(j: &mut JsonValue, users: &[UserAccountCreate]) -> io::Result<()> {
+    for u in users {
+        {
+            let account = &mut j["keys"]["users"]["users"][u.username.as_str()];
+            account["isNormalUser"] = JsonValue::Boolean(true);
+
+            if let Some(ref password) = u.password {
+                info!("hashing the password of {}", u.username);
+                account["initialHashedPassword"] = str!(hash_password(password)?);
+            }
+
+            if let Some(ref realname) = u.realname {
+                account["description"] = str!(realname.clone());
+            }
+
+            if !u.groups.is_empty() {
+                account["extraGroups"] = u.groups.clone().into();
+            }
+
+            if let Some(ref shell) = u.shell {
+                account["shell"] = str!(shell.clone());
+            }
+
+            if let Some(uid) = u.uid {
+                account["uid"] = uid.into();
+            }
+
+            if u.gid.is_some() {
+                account["group"] = str!(u.username.clone());
+            }
+
+            if !u.ssh_keys.is_empty() {
+                account["openssh"]["authorizedKeys"]["keys"] = u.ssh_keys.clone().into();
+            }
+        }
+
+        if let Some(gid) = u.gid {
+            j["keys"]["users"]["groups"][u.username.as_str()]["gid"] = gid.into();
+        }
+    }
+
+    Ok(())
+}
+
+/// Hashes a password with SHA-512 crypt, so that it is never written to the target in
+/// plain text.
+fn hash_password(password: &str) -> io::Result<String> {
+    pwhash::sha512_crypt::hash(password).map_err(|why| io::Error::new(
+        io::ErrorKind::Other,
+        format!("failed to hash password: {}", why)
+    ))
+}
+
+/// Writes a file which only root may read, as it contains password hashes.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // The mode is only applied to files that did not already exist.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+/// Ensures that installing the system will not need to download anything which is not
+/// already in the live image's store. The system is evaluated from `flake`, which refers
+/// to the flake's NixOS configuration, when the configuration is a flake.
+fn offline_preflight(
+    mount_dir: &Path,
+    closure: Option<&Path>,
+    flake: Option<&str>
+) -> io::Result<()> {
+    if let Some(closure) = closure {
+        // The references of a valid store path are always valid as well.
+        let valid = Invocation::new("nix-store")
+            .arg("--check-validity")
+            .arg(closure)
+            .run()
+            .with_context(|why| format!("failed to execute nix-store: {}", why))?;
+
+        if !valid.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("system closure {} is not in the local Nix store", closure.display())
+            ));
+        }
+
+        return Ok(());
+    }
+
+    let command = match flake {
+        Some(flake) => Invocation::new("nix")
+            .args(&["build", "--extra-experimental-features", "nix-command flakes"])
+            .arg([flake, ".config.system.build.toplevel"].concat()),
+        None => {
+            let nixos_config = mount_dir.join("etc/nixos/configuration.nix");
+            Invocation::new("nix-build")
+                .args(&["<nixpkgs/nixos>", "-A", "system"])
+                .arg("-I")
+                .arg(["nixos-config=", &nixos_config.to_string_lossy()].concat())
+        }
+    };
+
+    let command = command.arg("--dry-run").args(OFFLINE_OPTIONS);
+    let output = command
+        .run()
+        .with_context(|why| format!("failed to evaluate the NixOS configuration: {}", why))?;
+
+    if !output.success() {
+        return Err(command.failed(&output));
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // The system's own derivations, such as `etc` and `nixos-system`, are always built, and
+    // that is possible offline as long as none of them has to download its source.
+    let (builds, fetches) = dry_run_paths(&stderr);
+    let downloads = if builds.is_empty() {
+        Vec::new()
+    } else {
+        let command = Invocation::new("nix")
+            .args(&["show-derivation", "--extra-experimental-features", "nix-command"])
+            .args(&builds);
+
+        let output = command
+            .run()
+            .with_context(|why| format!("failed to execute nix show-derivation: {}", why))?;
+
+        if !output.success() {
+            return Err(command.failed(&output));
+        }
+
+        let derivations = json::parse(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|why| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to parse the output of nix show-derivation: {}", why)
+            ))?;
+
+        fixed_outputs(&derivations, &builds)
+    };
+
+    match fetches.first().or_else(|| downloads.first()) {
+        Some(example) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "cannot install offline: {} paths would be downloaded and {} sources are \
+                 missing from the store, such as {}",
+                fetches.len(),
+                downloads.len(),
+                example
+            )
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The fixed-output derivations among `builds`, as described by `nix show-derivation`. These
+/// download their outputs, so they cannot be built offline.
+fn fixed_outputs<'a>(derivations: &JsonValue, builds: &[&'a str]) -> Vec<&'a str> {
+    builds
+        .iter()
+        .copied()
+        .filter(|build| {
+            derivations[*build]["outputs"].entries().any(|(_, output)| !output["hash"].is_null())
+        })
+        .collect()
+}
+
+/// The nixpkgs which the live image was built from, preferring that of its flake registry
+/// to that of its channels.
+fn live_nixpkgs() -> io::Result<PathBuf> {
+    let metadata = Invocation::new("nix")
+        .args(&["flake", "metadata", "nixpkgs", "--json"])
+        .args(&["--extra-experimental-features", "nix-command flakes"])
+        .run();
+
+    if let Ok(ref output) = metadata {
+        let path = json::parse(&String::from_utf8_lossy(&output.stdout))
+            .ok()
+            .filter(|_| output.success())
+            .and_then(|metadata| metadata["path"].as_str().map(PathBuf::from));
+
+        if let Some(path) = path {
+            return Ok(path);
+        }
+    }
+
+    let output = Invocation::new("nix-instantiate")
+        .args(&["--find-file", "nixpkgs"])
+        .run()
+        .with_context(|why| format!("failed to execute nix-instantiate: {}", why))?;
+
+    if !output.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "nixpkgs is not available on the live image, so the flake cannot be pinned to it"
+        ));
+    }
+
+    // The channel is a link into the store, which is where the flake must refer to.
+    Path::new(String::from_utf8_lossy(&output.stdout).trim())
+        .canonicalize()
+        .with_context(|why| format!("failed to resolve the live image's nixpkgs: {}", why))
+}
+
+/// A flake which builds the generated configuration with `nixpkgs`, and which the installed
+/// system's registry is pinned to, so that it is managed as a flake from the first boot.
+fn flake_expr(hostname: &str, system: &str, nixpkgs: &Path) -> Expr {
+    let settings = AttrSet::new()
+        .with("nix.registry.nixpkgs.flake", Expr::var("nixpkgs"))
+        .with("nix.extraOptions", "experimental-features = nix-command flakes\n");
+
+    let nixos_system = AttrSet::new()
+        .with("system", system)
+        .with("modules", Expr::List(vec![
+            PathBuf::from("configuration.nix").into(),
+            settings.into(),
+        ]));
+
+    let mut outputs = AttrSet::new();
+    outputs.set_path(
+        &["nixosConfigurations", hostname],
+        Expr::apply("nixpkgs.lib.nixosSystem", nixos_system)
+    );
+
+    AttrSet::new()
+        .with("description", ["NixOS configuration of ", hostname].concat())
+        .with("inputs.nixpkgs.url", ["path:", &nixpkgs.to_string_lossy()].concat())
+        .with("outputs", Expr::module(&["self", "nixpkgs"], outputs))
+        .into()
+}
+
+/// The derivations which `nix-build --dry-run` reports that it would build, and the paths
+/// which it would fetch.
+fn dry_run_paths(output: &str) -> (Vec<&str>, Vec<&str>) {
+    let mut builds = Vec::new();
+    let mut fetches = Vec::new();
+    let mut section = None;
+
+    for line in output.lines() {
+        let path = line.trim();
+        if line.contains("will be built") {
+            section = Some(&mut builds);
+        } else if line.contains("will be fetched") {
+            section = Some(&mut fetches);
+        } else if line.starts_with(' ') && path.starts_with("/nix/store/") {
+            if let Some(ref mut paths) = section {
+                paths.push(path);
+            }
+        } else {
+            section = None;
+        }
+    }
 
-    return json::stringify(j);
+    (builds, fetches)
 }
 
-macro_rules! ap {
-    ($target:expr, [ $($str:expr),+ ]) => {
-        $($target += $str;)+
+/// Merges `overlay` into `base`, recursing into the objects that both of them define.
+fn merge_json(base: &mut JsonValue, overlay: JsonValue) {
+    match overlay {
+        JsonValue::Object(ref overlay) if base.is_object() => {
+            for (key, value) in overlay.iter() {
+                merge_json(&mut base[key], value.clone());
+            }
+        }
+        overlay => *base = overlay,
     }
 }
 
-macro_rules! ap_nix {
-    ($target:expr, $key:expr, $val:expr) => {
-        ap!($target, [ "  ", $key, " = ", $val, ";\n" ]);
+/// systemd-boot keeps the kernel and initrd of every generation on the ESP, so it must have
+/// room for several of them. 512 MiB, in 512-byte sectors.
+const SYSTEMD_BOOT_ESP_SECTORS: u64 = 1_048_576;
+
+/// Finds the EFI system partition, which is mounted at `/boot/efi`, or at `/boot` when that
+/// partition has the ESP flag set, as NixOS may mount it at either.
+fn find_esp(disks: &Disks) -> Option<(&Path, &PartitionInfo)> {
+    disks.find_partition(Path::new("/boot/efi")).or_else(|| {
+        disks.find_partition(Path::new("/boot"))
+            .filter(|(_, boot)| boot.flags.contains(&PartitionFlag::PED_PARTITION_ESP))
+    })
+}
+
+/// Ensures that an offline install will be checked by its preflight, which can only tell what
+/// `nixos-install` would fetch. Other installers may fetch anything.
+pub fn validate_offline(config: &Config) -> io::Result<()> {
+    if config.flags & OFFLINE == 0 {
+        return Ok(());
+    }
+
+    let installer = Path::new(&config.nixos.installer);
+    let name = installer.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    if OFFLINE_INSTALLERS.contains(&name) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "cannot install offline with {}, which may not be checked by the preflight; only \
+                 {} are supported",
+                config.nixos.installer,
+                OFFLINE_INSTALLERS.join(" and ")
+            ),
+        ))
     }
 }
 
-macro_rules! quote {
-    ($str:expr) => {
-        &format!("\"{}\"", $str)
+/// Validates the partitions that have been configured, wherever the ESP is mounted, and
+/// ensures that the boot loader can be installed to them.
+pub fn validate_boot(
+    disks: &Disks,
+    bootloader: Bootloader,
+    manager: BootManager,
+) -> io::Result<()> {
+    let esp = find_esp(disks)
+        .and_then(|(_, esp)| esp.target.as_deref())
+        .unwrap_or_else(|| Path::new("/boot/efi"));
+
+    let diagnostics = disks.validate_with_esp(bootloader, esp);
+    if let Some(diagnostic) = diagnostics.into_iter().find(Diagnostic::is_error) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, diagnostic.message));
+    }
+
+    if bootloader != Bootloader::Efi || manager != BootManager::SystemdBoot {
+        return Ok(());
+    }
+
+    match find_esp(disks) {
+        Some((_, esp)) if esp.get_sectors() < SYSTEMD_BOOT_ESP_SECTORS => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "systemd-boot stores every generation on the ESP, which must be at least 512 MiB",
+        )),
+        Some(_) => Ok(()),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "EFI partition was not defined")),
     }
 }
 
 fn generate_boot_config(
     disks: &Disks,
     bootloader: Bootloader,
+    manager: BootManager,
 ) -> String {
-    let mut conf: String = "{ config, pkgs, lib, ... }:".to_string();
+    // The ESP may be mounted at /boot, which `Disks::get_base_partitions` does not accept.
+    let (root_dev, _root_part) = disks
+        .find_partition(Path::new("/"))
+        .expect("verify_partitions() should have ensured that a root partition was created");
+
+    let boot_opt = match bootloader {
+        Bootloader::Bios => disks.find_partition(Path::new("/boot")),
+        Bootloader::Efi => find_esp(disks),
+    };
+
+    let bootloader_dev = boot_opt.map_or(root_dev, |(dev, _dev_part)| dev);
+
+    let esp = boot_opt
+        .and_then(|(_, esp)| esp.target.as_deref())
+        .unwrap_or_else(|| Path::new("/boot/efi"));
+
+    let efi_variables = !NO_EFI_VARIABLES.load(Ordering::Relaxed);
+
+    nix::file(
+        "Boot settings, be careful",
+        &boot_module(bootloader, manager, bootloader_dev, esp, efi_variables)
+    )
+}
+
+/// Describes the file systems, swap, and encrypted volumes of the committed layout, by stable
+/// identifiers, so that they need not be detected again from the running system.
+fn hardware_module<D: InstallerDiskOps>(disks: &D, hardware_scan: bool) -> Expr {
+    let mut conf = AttrSet::new();
+    let mut swaps: Vec<Expr> = Vec::new();
+    let mut resume = None;
 
-    let ((root_dev, _root_part), boot_opt) = disks.get_base_partitions(bootloader);
+    if hardware_scan {
+        conf.set("imports", vec![Path::new("hardware-scan.nix")]);
+    }
+
+    for entry in disks.mount_entries() {
+        let device = entry.stable_device().to_string_lossy().into_owned();
+        let target = match entry.target {
+            Some(ref target) => target.to_string_lossy(),
+            None => {
+                resume.get_or_insert_with(|| device.clone());
+                swaps.push(AttrSet::new().with("device", device).into());
+                continue;
+            }
+        };
+
+        let fs_type = match entry.fs {
+            FileSystem::Fat16 | FileSystem::Fat32 => "vfat",
+            fs => fs.into(),
+        };
+
+        let mut file_system = AttrSet::new().with("device", device).with("fsType", fs_type);
+        let options = entry.options.split(',').filter(|&option| option != "defaults").collect::<Vec<_>>();
+        if !options.is_empty() {
+            file_system.set("options", options);
+        }
+
+        conf.set_path(&["fileSystems", target.as_ref()], file_system);
+    }
+
+    for volume in disks.crypt_volumes() {
+        if volume.swap {
+            // Random encryption overwrites the swap header, so the partition is found by its
+            // PARTUUID rather than the UUID of the file system.
+            let device = PartitionID::get_partuuid(&volume.device).map_or_else(
+                || ["/dev/disk/by-uuid/", &volume.uuid].concat(),
+                |id| ["/dev/disk/by-partuuid/", &id.id].concat()
+            );
 
-    let mut efi_part_num = 0;
+            let encryption = AttrSet::new()
+                .with("enable", true)
+                .with("cipher", "aes-xts-plain64")
+                .with("allowDiscards", true);
 
-    let bootloader_dev = boot_opt.map_or(root_dev, |(dev, dev_part)| {
-        efi_part_num = dev_part.number;
-        dev
-    });
+            swaps.push(AttrSet::new().with("device", device).with("randomEncryption", encryption).into());
+            continue;
+        }
+
+        let mut luks = AttrSet::new()
+            .with("device", ["/dev/disk/by-uuid/", &volume.uuid].concat())
+            .with("allowDiscards", true);
 
-    ap!(conf, [
-        "# Boot settings, be careful\n\n",
-        "{"
-    ]);
+        if let CryptKey::File(ref path) = volume.key {
+            // The key is read before any file system is mounted, so it is embedded within
+            // the initrd from the installed system.
+            let path = path.to_string_lossy().into_owned();
+            luks.set("keyFile", path.clone());
+            conf.set_path(&["boot", "initrd", "secrets", path.as_str()], path.clone());
+        }
+
+        conf.set_path(&["boot", "initrd", "luks", "devices", volume.name.as_str()], luks);
+    }
+
+    if !swaps.is_empty() {
+        conf.set("swapDevices", Expr::List(swaps));
+    }
+
+    if let Some(resume) = resume {
+        conf.set("boot.resumeDevice", resume);
+    }
+
+    Expr::module(&["config", "lib", "pkgs"], conf)
+}
+
+fn boot_module(
+    bootloader: Bootloader,
+    manager: BootManager,
+    bootloader_dev: &Path,
+    esp: &Path,
+    efi_variables: bool,
+) -> Expr {
+    let mut conf = AttrSet::new().with("imports", vec![Path::new("hardware.nix")]);
 
     match bootloader {
         Bootloader::Bios => {
-            ap_nix!(conf, "boot.loader.grub.enable", "true");
-            ap_nix!(conf, "boot.loader.grub.device", quote!(bootloader_dev.to_str().unwrap().to_owned()));
+            conf.set("boot.loader.grub.enable", true)
+                .set("boot.loader.grub.device", bootloader_dev.to_string_lossy().into_owned());
         }
         Bootloader::Efi => {
-            // ap_nix!(conf, "boot.loader.canTouchEfiVariables", if NO_EFI_VARIABLES.load(Ordering::Relaxed) { "false" } else { "true" }); // if not --no-efi-vars
-            ap_nix!(conf, "boot.loader.efi.efiSysMountPoint", quote!("/boot/efi")); // maybe get from disk ops?
-            ap_nix!(conf, "boot.loader.grub.enable", "true");
-            ap_nix!(conf, "boot.loader.grub.efiSupport", "true");
-            ap_nix!(conf, "boot.loader.grub.devices", "[ \"nodev\" ]");
+            conf.set("boot.loader.efi.canTouchEfiVariables", efi_variables)
+                .set("boot.loader.efi.efiSysMountPoint", esp.to_string_lossy().into_owned());
+
+            match manager {
+                BootManager::Grub => {
+                    conf.set("boot.loader.grub.enable", true)
+                        .set("boot.loader.grub.efiSupport", true)
+                        .set("boot.loader.grub.devices", vec!["nodev"]);
+                }
+                BootManager::SystemdBoot => {
+                    conf.set("boot.loader.systemd-boot.enable", true);
+                }
+            }
         }
     }
 
-    conf += "}\n";
-
-    return conf
+    Expr::module(&["config", "pkgs", "lib"], conf)
 }
 
-fn progress(data: String) -> Option<f64> {
-    struct Status {
-        done: u64,
-        expected: u64,
-        weight: f64,
-    }
-
-    fn to_status(data: &JsonValue) -> Result<Vec<Status>, io::Error> {
-        let mut status: Vec<Status> = Vec::new();
-        for &(name, weight) in USE_STATUS.iter() {
-            let item = &data[name];
-            status.push(Status {
-                done: match item["done"].as_u64() {
-                    Some(x) => x,
-                    _ => return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput, "Cannot find name"))
-                },
-                expected: match item["expected"].as_u64() {
-                    Some(x) => x,
-                    _ => return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput, "Cannot find name"))
-                },
-                weight: weight,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::installer::{bitflags::FileSystemSupport, traits::{CryptVolume, MountEntry}};
+    use fstab_generate::BlockInfo;
+    use json::array;
+    use std::{cell::RefCell, ffi::OsString, sync::Once};
+    use tempdir::TempDir;
+
+    #[test]
+    fn offline_dry_run() {
+        let output = [
+            "these 2 derivations will be built:\n",
+            "  /nix/store/aaaa-etc.drv\n",
+            "  /nix/store/bbbb-nixos-system.drv\n",
+            "these paths will be fetched (1.20 MiB download, 4.00 MiB unpacked):\n",
+            "  /nix/store/cccc-hello-2.10\n",
+            "warning: ignoring substituter\n",
+            "  /nix/store/dddd-unrelated\n",
+        ].concat();
+
+        assert_eq!(
+            dry_run_paths(&output),
+            (
+                vec!["/nix/store/aaaa-etc.drv", "/nix/store/bbbb-nixos-system.drv"],
+                vec!["/nix/store/cccc-hello-2.10"]
+            )
+        );
+        assert_eq!(dry_run_paths(""), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn offline_installers() {
+        let mut config = Config { flags: OFFLINE, ..crate::installer::tests::config() };
+        for installer in &["nixos-install-wrapped", "/run/current-system/sw/bin/nixos-install"] {
+            config.nixos.installer = installer.to_string();
+            assert!(validate_offline(&config).is_ok());
+        }
+
+        config.nixos.installer = "my-installer".into();
+        let why = validate_offline(&config).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidInput);
+
+        config.flags = 0;
+        assert!(validate_offline(&config).is_ok());
+    }
+
+    #[test]
+    fn offline_fixed_outputs() {
+        let derivations = json::parse(r#"{
+            "/nix/store/aaaa-etc.drv": {
+                "outputs": { "out": { "path": "/nix/store/eeee-etc" } }
+            },
+            "/nix/store/ffff-hello-2.10.tar.gz.drv": {
+                "outputs": {
+                    "out": {
+                        "path": "/nix/store/gggg-hello-2.10.tar.gz",
+                        "hashAlgo": "sha256",
+                        "hash": "31e066137a962676e89f69d1b65382de95a7ef7d914b8cb956f41ea72e0f516b"
+                    }
+                }
+            }
+        }"#).unwrap();
+
+        let builds = ["/nix/store/aaaa-etc.drv", "/nix/store/ffff-hello-2.10.tar.gz.drv"];
+        assert_eq!(
+            fixed_outputs(&derivations, &builds),
+            vec!["/nix/store/ffff-hello-2.10.tar.gz.drv"]
+        );
+        assert!(fixed_outputs(&derivations, &builds[..1]).is_empty());
+    }
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<Vec<String>>> = RefCell::new(None);
+    }
+
+    /// Records the messages that are logged by threads which are capturing them, so that
+    /// tests running on other threads neither see nor disturb them.
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool { true }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED.with(|captured| {
+                if let Some(ref mut messages) = *captured.borrow_mut() {
+                    messages.push(record.args().to_string());
+                }
             });
         }
-        Ok(status)
+
+        fn flush(&self) {}
     }
 
-    fn percentage(data: Vec<Status>) -> Option<f64> {
-        let mut done = 0f64;
-        let mut expected = 0f64;
+    /// Returns every message that was logged on this thread while `func` was running.
+    fn capture_logs<F: FnOnce()>(func: F) -> Vec<String> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Capture).expect("another logger was already set");
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+
+        CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+        func();
+        CAPTURED.with(|captured| captured.borrow_mut().take().unwrap_or_default())
+    }
+
+    #[test]
+    fn hashed_passwords() {
+        const PASSWORD: &str = "correct horse battery staple";
+
+        let user = UserAccountCreate {
+            username: "pop".into(),
+            password: Some(PASSWORD.into()),
+            ..UserAccountCreate::default()
+        };
+
+        let mut conf = object!{};
+        let logs = capture_logs(|| user_accounts(&mut conf, &[user]).unwrap());
+
+        // A configuration left behind by a previous attempt must not keep its permissions.
+        let dir = TempDir::new("distinst").unwrap();
+        let path = dir.path().join("conf-tool.json");
+        fs::write(&path, "{}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, &json::stringify(conf)).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(!written.contains(PASSWORD));
+
+        assert!(logs.iter().any(|message| message.contains("pop")));
+        assert!(!logs.iter().any(|message| message.contains(PASSWORD)));
 
-        for i in data {
-            done += i.done as f64 * i.weight;
-            expected += i.expected as f64 * i.weight;
+        let conf = json::parse(&written).unwrap();
+        let account = &conf["keys"]["users"]["users"]["pop"];
+        assert!(account["initialPassword"].is_null());
+
+        let hash = account["initialHashedPassword"].as_str().unwrap();
+        assert!(hash.starts_with("$6$"));
+        assert!(pwhash::sha512_crypt::verify(PASSWORD, hash));
+    }
+
+    #[test]
+    fn wipe_partial_target() {
+        let target = TempDir::new("distinst").unwrap();
+        let root = target.path();
+        fs::create_dir_all(root.join("nix/store/aaaa-hello/bin")).unwrap();
+        fs::create_dir_all(root.join("lost+found")).unwrap();
+        fs::write(root.join("nix/store/aaaa-hello/bin/hello"), "").unwrap();
+        fs::write(root.join("install.log"), "").unwrap();
+        std::os::unix::fs::symlink("/nix/store/aaaa-hello", root.join("result")).unwrap();
+
+        wipe_target(root, &[PathBuf::from("/elsewhere")]).unwrap();
+        assert!(root.join("install.log").exists());
+
+        wipe_target(root, &[root.to_path_buf()]).unwrap();
+
+        let remaining = fs::read_dir(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, vec![OsString::from("lost+found")]);
+    }
+
+    #[test]
+    fn merge_extra_json() {
+        let mut conf = object! {
+            "keys" => object! {
+                "networking" => object! { "hostName" => "pop-os", "enableIPv6" => true },
+                "services" => array!["sshd"]
+            }
+        };
+
+        merge_json(&mut conf, json::parse(r#"{
+            "keys": {
+                "networking": { "hostName": "edition" },
+                "services": ["printing"],
+                "time": { "timeZone": "UTC" }
+            }
+        }"#).unwrap());
+
+        assert_eq!(conf, object! {
+            "keys" => object! {
+                "networking" => object! { "hostName" => "edition", "enableIPv6" => true },
+                "services" => array!["printing"],
+                "time" => object! { "timeZone" => "UTC" }
+            }
+        });
+    }
+
+    struct Layout;
+
+    impl InstallerDiskOps for Layout {
+        fn generate_fstabs(&self) -> (OsString, OsString) { (OsString::new(), OsString::new()) }
+
+        fn crypt_volumes(&self) -> Vec<CryptVolume> {
+            let volume = |name: &str, key, swap| CryptVolume {
+                name: name.into(),
+                device: PathBuf::from("/dev/null"),
+                uuid: [name, "-uuid"].concat(),
+                key,
+                swap,
+            };
+
+            vec![
+                volume("cryptdata", CryptKey::File("/etc/cryptkeys/cryptdata".into()), false),
+                volume("cryptswap", CryptKey::Random, true),
+            ]
         }
 
-        if expected == 0.0 || done == expected {
-            None
-        } else {
-            Some(done / expected)
+        fn mount_entries(&self) -> Vec<MountEntry> {
+            let entry = |target: Option<&str>, uuid: &str, fs, options| MountEntry {
+                target: target.map(PathBuf::from),
+                device: PathBuf::from("/dev/null"),
+                uuid: Some(uuid.into()),
+                fs,
+                options,
+            };
+
+            vec![
+                entry(Some("/"), "root-uuid", FileSystem::Ext4, "noatime,errors=remount-ro"),
+                entry(Some("/boot/efi"), "efi-uuid", FileSystem::Fat32, "umask=0077"),
+                entry(Some("/mnt/my data"), "data-uuid", FileSystem::Xfs, "defaults"),
+                entry(None, "swap-uuid", FileSystem::Swap, "sw"),
+            ]
         }
+
+        fn get_block_info_of(&self, path: &str) -> io::Result<BlockInfo> {
+            if path != "/" {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "partition not found"));
+            }
+
+            Ok(BlockInfo::new(
+                PartitionID::new_uuid("root-uuid".into()),
+                FileSystem::Ext4,
+                Some(Path::new("/")),
+                "noatime,errors=remount-ro",
+            ))
+        }
+
+        fn get_support_flags(&self) -> FileSystemSupport { FileSystemSupport::empty() }
+    }
+
+    #[test]
+    fn hardware_config() {
+        let hardware = nix::file("Generated", &hardware_module(&Layout, true));
+        assert_eq!(hardware, [
+            "# Generated\n",
+            "{ config, lib, pkgs, ... }:\n",
+            "\n",
+            "{\n",
+            "  imports = [ ./hardware-scan.nix ];\n",
+            "  fileSystems.\"/\" = {\n",
+            "    device = \"/dev/disk/by-uuid/root-uuid\";\n",
+            "    fsType = \"ext4\";\n",
+            "    options = [ \"noatime\" \"errors=remount-ro\" ];\n",
+            "  };\n",
+            "  fileSystems.\"/boot/efi\" = {\n",
+            "    device = \"/dev/disk/by-uuid/efi-uuid\";\n",
+            "    fsType = \"vfat\";\n",
+            "    options = [ \"umask=0077\" ];\n",
+            "  };\n",
+            "  fileSystems.\"/mnt/my data\" = {\n",
+            "    device = \"/dev/disk/by-uuid/data-uuid\";\n",
+            "    fsType = \"xfs\";\n",
+            "  };\n",
+            "  boot.initrd.secrets.\"/etc/cryptkeys/cryptdata\" = \"/etc/cryptkeys/cryptdata\";\n",
+            "  boot.initrd.luks.devices.cryptdata = {\n",
+            "    device = \"/dev/disk/by-uuid/cryptdata-uuid\";\n",
+            "    allowDiscards = true;\n",
+            "    keyFile = \"/etc/cryptkeys/cryptdata\";\n",
+            "  };\n",
+            "  swapDevices = [\n",
+            "    {\n",
+            "      device = \"/dev/disk/by-uuid/swap-uuid\";\n",
+            "    }\n",
+            "    {\n",
+            "      device = \"/dev/disk/by-uuid/cryptswap-uuid\";\n",
+            "      randomEncryption = {\n",
+            "        enable = true;\n",
+            "        cipher = \"aes-xts-plain64\";\n",
+            "        allowDiscards = true;\n",
+            "      };\n",
+            "    }\n",
+            "  ];\n",
+            "  boot.resumeDevice = \"/dev/disk/by-uuid/swap-uuid\";\n",
+            "}\n",
+        ].concat());
     }
 
-    let parsed = parse(&data).unwrap();
-    let status = to_status(&parsed["status"]).unwrap();
-    percentage(status)
+    #[test]
+    fn flake_config() {
+        let flake = flake_expr("pop-os", "x86_64-linux", Path::new("/nix/store/aaaa-source"));
+        assert_eq!(nix::file("Generated", &flake), [
+            "# Generated\n",
+            "{\n",
+            "  description = \"NixOS configuration of pop-os\";\n",
+            "  inputs.nixpkgs.url = \"path:/nix/store/aaaa-source\";\n",
+            "  outputs = { self, nixpkgs, ... }: {\n",
+            "    nixosConfigurations.pop-os = nixpkgs.lib.nixosSystem {\n",
+            "      system = \"x86_64-linux\";\n",
+            "      modules = [\n",
+            "        ./configuration.nix\n",
+            "        {\n",
+            "          nix.registry.nixpkgs.flake = nixpkgs;\n",
+            "          nix.extraOptions = \"experimental-features = nix-command flakes\\n\";\n",
+            "        }\n",
+            "      ];\n",
+            "    };\n",
+            "  };\n",
+            "}\n",
+        ].concat());
+    }
+
+    #[test]
+    fn boot_config() {
+        let boot = |bootloader, manager, efi_variables| nix::file(
+            "Boot settings, be careful",
+            &boot_module(bootloader, manager, Path::new("/dev/sda"), Path::new("/boot"), efi_variables)
+        );
+
+        let header = [
+            "# Boot settings, be careful\n",
+            "{ config, pkgs, lib, ... }:\n",
+            "\n",
+            "{\n",
+            "  imports = [ ./hardware.nix ];\n",
+        ].concat();
+
+        assert_eq!(boot(Bootloader::Bios, BootManager::SystemdBoot, true), [
+            header.as_str(),
+            "  boot.loader.grub.enable = true;\n",
+            "  boot.loader.grub.device = \"/dev/sda\";\n",
+            "}\n",
+        ].concat());
+
+        assert_eq!(boot(Bootloader::Efi, BootManager::Grub, false), [
+            header.as_str(),
+            "  boot.loader.efi.canTouchEfiVariables = false;\n",
+            "  boot.loader.efi.efiSysMountPoint = \"/boot\";\n",
+            "  boot.loader.grub.enable = true;\n",
+            "  boot.loader.grub.efiSupport = true;\n",
+            "  boot.loader.grub.devices = [ \"nodev\" ];\n",
+            "}\n",
+        ].concat());
+
+        assert_eq!(boot(Bootloader::Efi, BootManager::SystemdBoot, true), [
+            header.as_str(),
+            "  boot.loader.efi.canTouchEfiVariables = true;\n",
+            "  boot.loader.efi.efiSysMountPoint = \"/boot\";\n",
+            "  boot.loader.systemd-boot.enable = true;\n",
+            "}\n",
+        ].concat());
+    }
 }
@@ -0,0 +1,360 @@
+//! Tracks the progress of `nixos-install` from its standard error, which interleaves plain
+//! log lines with the status summaries of `nixos-install-wrapped`, and with the messages of
+//! Nix's `internal-json` log format.
+
+use crate::installer::{Counter, CounterUnit, Progress};
+use json::JsonValue;
+use std::{collections::HashMap, path::Path};
+
+// Activity types, as they are numbered by Nix.
+const ACT_COPY_PATH: u64 = 100;
+const ACT_FILE_TRANSFER: u64 = 101;
+const ACT_COPY_PATHS: u64 = 103;
+const ACT_BUILDS: u64 = 104;
+const ACT_BUILD: u64 = 105;
+const ACT_SUBSTITUTE: u64 = 108;
+
+// Result types, as they are numbered by Nix.
+const RES_PROGRESS: u64 = 105;
+const RES_SET_EXPECTED: u64 = 106;
+
+/// How much each kind of work contributes to the overall progress, with the key that the
+/// wrapper's status summaries report it under. Copied paths are counted, but it is the bytes
+/// within them that are weighed.
+const WEIGHTS: [(&str, u64, f64); 4] = [
+    ("builds", ACT_BUILDS, 1.0),
+    ("copyPath", ACT_COPY_PATH, 0.1),
+    ("copyPaths", ACT_COPY_PATHS, 0.0),
+    ("fileTransfer", ACT_FILE_TRANSFER, 0.3),
+];
+
+/// The stages which `nixos-install` announces, with the description of each.
+const STAGES: [(&str, &str); 6] = [
+    ("copying channel", "copying the channel"),
+    ("building the configuration", "building the system"),
+    ("building the flake", "building the system"),
+    ("installing the boot loader", "installing the boot loader"),
+    ("setting up /etc", "activating the system"),
+    ("setting root password", "setting the root password"),
+];
+
+/// The work which is reported as a `Counter`.
+const COUNTERS: [(u64, CounterUnit); 2] =
+    [(ACT_COPY_PATHS, CounterUnit::Items), (ACT_COPY_PATH, CounterUnit::Bytes)];
+
+#[derive(Default)]
+struct Activity {
+    kind:     u64,
+    done:     u64,
+    expected: u64,
+}
+
+/// Converts each line that `nixos-install` writes into installer progress.
+#[derive(Default)]
+pub struct InstallProgress {
+    activities: HashMap<u64, Activity>,
+    /// The amount of work of each kind which activities expect to perform.
+    expected:   HashMap<(u64, u64), u64>,
+    /// The totals of each kind of work, from the latest status summary.
+    summary:    HashMap<u64, (u64, u64)>,
+    /// The totals which were last reported by each counter.
+    counters:   HashMap<u64, (u64, u64)>,
+    percent:    Option<i32>,
+    stage:      Option<&'static str>,
+}
+
+impl InstallProgress {
+    /// Parses a line of output, reporting the activity which is now in progress, the
+    /// weighted percentage of work that has been completed, the store paths and bytes that
+    /// have been copied, and any lines to log.
+    ///
+    /// Lines which are not understood are logged as they are.
+    pub fn parse(&mut self, line: &str, callback: &mut dyn FnMut(Progress)) {
+        let (message, internal) = if line.starts_with("@nix ") {
+            (&line[5..], true)
+        } else if line.starts_with('{') {
+            (line, false)
+        } else {
+            if let Some(&(_, stage)) = STAGES.iter().find(|(prefix, _)| line.starts_with(prefix)) {
+                self.stage = Some(stage);
+            }
+
+            callback(Progress::Log(line.into()));
+            return;
+        };
+
+        let value = match json::parse(message) {
+            Ok(value) => value,
+            Err(_) => {
+                callback(Progress::Log(line.into()));
+                return;
+            }
+        };
+
+        if internal {
+            self.parse_internal(&value, callback);
+        } else if value["status"].is_object() {
+            self.parse_summary(&value["status"]);
+        } else {
+            callback(Progress::Log(line.into()));
+            return;
+        }
+
+        for &(kind, unit) in &COUNTERS {
+            let totals = self.totals(kind);
+            if totals != (0, 0) && self.counters.get(&kind) != Some(&totals) {
+                self.counters.insert(kind, totals);
+                callback(Progress::Counter(Counter {
+                    unit,
+                    done: totals.0,
+                    expected: if totals.1 == 0 { None } else { Some(totals.1) },
+                }));
+            }
+        }
+
+        if let Some(percent) = self.percentage().map(|fraction| (fraction * 100.0) as i32) {
+            if self.percent != Some(percent) {
+                self.percent = Some(percent);
+                callback(Progress::Percent(percent));
+            }
+        }
+    }
+
+    /// The stage of the install which `nixos-install` last announced.
+    pub fn stage(&self) -> Option<&'static str> { self.stage }
+
+    fn parse_internal(&mut self, value: &JsonValue, callback: &mut dyn FnMut(Progress)) {
+        let id = value["id"].as_u64().unwrap_or(0);
+        let fields = &value["fields"];
+
+        match value["action"].as_str() {
+            Some("start") => {
+                let kind = value["type"].as_u64().unwrap_or(0);
+                self.activities.insert(id, Activity { kind, ..Activity::default() });
+
+                if let Some(name) = activity_name(kind, fields, value["text"].as_str()) {
+                    callback(Progress::SubStep(name));
+                }
+            }
+            Some("result") => match value["type"].as_u64() {
+                Some(RES_PROGRESS) => {
+                    if let Some(activity) = self.activities.get_mut(&id) {
+                        activity.done = fields[0].as_u64().unwrap_or(activity.done);
+                        activity.expected = fields[1].as_u64().unwrap_or(activity.expected);
+                    }
+                }
+                Some(RES_SET_EXPECTED) => {
+                    if let (Some(kind), Some(expected)) = (fields[0].as_u64(), fields[1].as_u64()) {
+                        self.expected.insert((id, kind), expected);
+                    }
+                }
+                _ => (),
+            },
+            Some("msg") => {
+                if let Some(message) = value["msg"].as_str() {
+                    callback(Progress::Log(message.into()));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn parse_summary(&mut self, status: &JsonValue) {
+        for &(key, kind, _) in &WEIGHTS {
+            let item = &status[key];
+            if let (Some(done), Some(expected)) = (item["done"].as_u64(), item["expected"].as_u64())
+            {
+                self.summary.insert(kind, (done, expected));
+            }
+        }
+    }
+
+    /// The fraction of the work that has been completed, weighted by its kind.
+    fn percentage(&self) -> Option<f64> {
+        let mut done = 0f64;
+        let mut expected = 0f64;
+
+        for &(_, kind, weight) in &WEIGHTS {
+            let (kind_done, kind_expected) = self.totals(kind);
+
+            done += kind_done as f64 * weight;
+            expected += kind_expected as f64 * weight;
+        }
+
+        if expected == 0.0 {
+            None
+        } else {
+            Some((done / expected).min(1.0))
+        }
+    }
+
+    /// The work of the given kind which has been done, and which is expected.
+    fn totals(&self, kind: u64) -> (u64, u64) {
+        match self.summary.get(&kind) {
+            Some(&totals) => totals,
+            None => self.activity_totals(kind),
+        }
+    }
+
+    fn activity_totals(&self, kind: u64) -> (u64, u64) {
+        let (done, expected) = self
+            .activities
+            .values()
+            .filter(|activity| activity.kind == kind)
+            .fold((0, 0), |(done, expected), activity| {
+                (done + activity.done, expected + activity.expected)
+            });
+
+        let announced = self
+            .expected
+            .iter()
+            .filter(|&(&(_, expected_kind), _)| expected_kind == kind)
+            .map(|(_, &expected)| expected)
+            .sum::<u64>();
+
+        (done, expected.max(announced))
+    }
+}
+
+/// Describes an activity that is worth reporting to the user.
+fn activity_name(kind: u64, fields: &JsonValue, text: Option<&str>) -> Option<String> {
+    let verb = match kind {
+        ACT_BUILD => "building",
+        ACT_SUBSTITUTE => "fetching",
+        ACT_FILE_TRANSFER => "downloading",
+        _ => return None,
+    };
+
+    match fields[0].as_str() {
+        Some(url) if kind == ACT_FILE_TRANSFER => Some([verb, " ", url].concat()),
+        Some(path) => Some([verb, " ", &store_name(path)].concat()),
+        None => text.filter(|text| !text.is_empty()).map(String::from),
+    }
+}
+
+/// The name of a store path, without its hash, or the `.drv` extension of a derivation.
+fn store_name(path: &str) -> String {
+    let name = Path::new(path).file_name().map_or(path.into(), |name| name.to_string_lossy());
+    let name = match name.find('-') {
+        Some(pos) if pos == 32 => &name[33..],
+        _ => &name[..],
+    };
+
+    name.trim_end_matches(".drv").to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Vec<Progress> {
+        let mut events = Vec::new();
+        let mut progress = InstallProgress::default();
+        for line in lines {
+            progress.parse(line, &mut |event| events.push(event));
+        }
+
+        events
+    }
+
+    #[test]
+    fn plain_lines() {
+        assert_eq!(
+            parse(&["copying channel...", "{ not json", r#"{"unrelated": true}"#]),
+            vec![
+                Progress::Log("copying channel...".into()),
+                Progress::Log("{ not json".into()),
+                Progress::Log(r#"{"unrelated": true}"#.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn stages() {
+        let mut progress = InstallProgress::default();
+        assert_eq!(progress.stage(), None);
+
+        for line in &["copying channel...", "building the flake in path:/mnt/etc/nixos#pop-os..."] {
+            progress.parse(line, &mut |_| ());
+        }
+
+        assert_eq!(progress.stage(), Some("building the system"));
+    }
+
+    #[test]
+    fn status_summary() {
+        let status = |builds: u64| {
+            format!(
+                r#"{{"status": {{"builds": {{"done": {}, "expected": 4}}, "copyPath": {{"done": 0, "expected": 10}}}}}}"#,
+                builds
+            )
+        };
+
+        assert_eq!(
+            parse(&[&status(1), &status(1), r#"{"status": {}}"#, &status(4)]),
+            vec![
+                Progress::Counter(Counter {
+                    unit:     CounterUnit::Bytes,
+                    done:     0,
+                    expected: Some(10),
+                }),
+                Progress::Percent(20),
+                Progress::Percent(80),
+            ]
+        );
+    }
+
+    #[test]
+    fn copy_counters() {
+        let lines = [
+            r#"@nix {"action":"start","id":1,"type":103,"text":"copying 2 paths","fields":[]}"#,
+            r#"@nix {"action":"start","id":2,"type":100,"fields":["/nix/store/a","local","target"]}"#,
+            r#"@nix {"action":"result","id":2,"type":105,"fields":[512,1024,0,0]}"#,
+            r#"@nix {"action":"result","id":1,"type":105,"fields":[1,2,1,0]}"#,
+        ];
+
+        assert_eq!(
+            parse(&lines),
+            vec![
+                Progress::Counter(Counter {
+                    unit:     CounterUnit::Bytes,
+                    done:     512,
+                    expected: Some(1024),
+                }),
+                Progress::Percent(50),
+                Progress::Counter(Counter {
+                    unit:     CounterUnit::Items,
+                    done:     1,
+                    expected: Some(2),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn nix_activities() {
+        let lines = [
+            r#"@nix {"action":"start","id":1,"level":0,"type":104,"text":"","fields":[]}"#,
+            r#"@nix {"action":"result","id":1,"type":106,"fields":[104,2]}"#,
+            concat!(
+                r#"@nix {"action":"start","id":2,"level":3,"type":105,"text":"building","#,
+                r#""fields":["/nix/store/00000000000000000000000000000000-hello-2.10.drv"]}"#
+            ),
+            r#"@nix {"action":"msg","level":1,"msg":"unpacking sources"}"#,
+            r#"@nix {"action":"result","id":1,"type":105,"fields":[1,2,1,0]}"#,
+            r#"@nix {"action":"start","id":3,"type":101,"fields":["https://cache.nixos.org/x"]}"#,
+            r#"@nix {"action":"stop","id":2}"#,
+        ];
+
+        assert_eq!(
+            parse(&lines),
+            vec![
+                Progress::Percent(0),
+                Progress::SubStep("building hello-2.10".into()),
+                Progress::Log("unpacking sources".into()),
+                Progress::Percent(50),
+                Progress::SubStep("downloading https://cache.nixos.org/x".into()),
+            ]
+        );
+    }
+}
@@ -1,11 +1,43 @@
 use crate::disks::{operations::FormatPartitions, Disks};
 use crate::errors::IoContext;
+use crate::installer::{snapshot, Progress};
 use crate::external::{blockdev, pvs, vgactivate, vgdeactivate};
 use itertools::Itertools;
 use rayon::{self, prelude::*};
-use std::{collections::BTreeMap, io, path::PathBuf, thread::sleep, time::Duration};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::Duration,
+};
 
-pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Result<()> {
+pub fn partition<F: FnMut(Progress)>(
+    disks: &mut Disks,
+    snapshot_dir: &Path,
+    mut callback: F,
+) -> io::Result<()> {
+    callback(Progress::SubStep("saving partition tables".into()));
+    let snapshots = snapshot::snapshot_tables(disks, snapshot_dir)
+        .with_context(|why| format!("failed to save partition tables: {}", why))?;
+
+    let result = commit(disks, &mut callback);
+
+    // Revert every disk to its original partition table if anything failed after the tables
+    // were saved, rather than leaving them in an intermediate state.
+    if result.is_err() {
+        callback(Progress::SubStep("restoring partition tables".into()));
+        if let Err(why) = snapshot::restore_tables(&snapshots) {
+            error!("failed to restore partition tables: {}", why);
+        }
+    }
+
+    result
+}
+
+/// Commits the partition tables, formats the partitions, and then commits the logical volumes.
+fn commit<F: FnMut(Progress)>(disks: &mut Disks, callback: &mut F) -> io::Result<()> {
+    callback(Progress::SubStep("committing partition tables and formatting partitions".into()));
     let (pvs_result, commit_result): (
         io::Result<BTreeMap<PathBuf, Option<String>>>,
         io::Result<()>,
@@ -36,9 +68,11 @@ pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Resul
         },
     );
 
-    let pvs = commit_result.and(pvs_result)?;
+    commit_result?;
+    let pvs = pvs_result?;
 
-    callback(25);
+    callback(Progress::Percent(25));
+    callback(Progress::SubStep("deactivating volume groups".into()));
 
     // Utilizes the physical volume collection to generate a vector of volume
     // groups which we will need to deactivate pre-`blockdev`, and will be
@@ -57,7 +91,8 @@ pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Resul
 
     // Ensure that the logical volumes have had time to deactivate.
     sleep(Duration::from_secs(1));
-    callback(50);
+    callback(Progress::Percent(50));
+    callback(Progress::SubStep("rereading partition tables".into()));
 
     // This is to ensure that everything's been written and the OS is ready to
     // proceed.
@@ -67,15 +102,16 @@ pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Resul
 
     // Give a bit of time to ensure that logical volumes can be re-activated.
     sleep(Duration::from_secs(1));
-    callback(75);
+    callback(Progress::Percent(75));
+    callback(Progress::SubStep("committing logical volumes".into()));
 
     // Reactivate the logical volumes.
     vgs.iter().map(|vg| vgactivate(vg)).collect::<io::Result<()>>()?;
 
-    let res = disks
+    disks
         .commit_logical_partitions()
-        .with_context(|why| format!("failed to commit logical partitions: {}", why));
+        .with_context(|why| format!("failed to commit logical partitions: {}", why))?;
 
-    callback(100);
-    res
+    callback(Progress::Percent(100));
+    Ok(())
 }
@@ -1,22 +1,123 @@
 use self::FileSystem::*;
 use super::bitflags::FileSystemSupport;
 use disk_types::{BlockDeviceExt, FileSystem, PartitionExt};
-use crate::disks::{Disks};
+use crate::disks::{get_preferred_options, Disks, LvmEncryption, PartitionInfo};
 use crate::errors::IntoIoResult;
 use crate::external::generate_unique_id;
 use fstab_generate::BlockInfo;
 use crate::misc::hasher;
 use partition_identity::PartitionID;
 use std::{
-    borrow::Cow,
-    ffi::{OsStr, OsString},
+    ffi::OsString,
     io,
+    path::{Path, PathBuf},
 };
 
+/// Options for swap which is encrypted with a random key on each boot. The offset
+/// preserves the swap header, so that the partition may still be found by its UUID.
+const CRYPTSWAP_OPTIONS: &str = "swap,plain,offset=1024,cipher=aes-xts-plain64,size=512";
+
+/// How the key of an encrypted volume is obtained when it is unlocked at boot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CryptKey {
+    /// The user is prompted for the passphrase.
+    Passphrase,
+    /// The key is read from a file.
+    File(PathBuf),
+    /// A random key is generated on each boot.
+    Random,
+}
+
+/// An encrypted volume which is unlocked at boot, as described by a line of `/etc/crypttab`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CryptVolume {
+    /// The name of the device map which the volume is unlocked to.
+    pub name:   String,
+    /// The encrypted partition.
+    pub device: PathBuf,
+    /// The UUID of the encrypted partition.
+    pub uuid:   String,
+    pub key:    CryptKey,
+    /// Whether the volume is swap, which is encrypted with a random key on each boot.
+    pub swap:   bool,
+}
+
+impl CryptVolume {
+    /// Appends the volume's line of `/etc/crypttab` to the table.
+    fn write_entry(&self, crypttab: &mut OsString) {
+        crypttab.push(&self.name);
+        crypttab.push(" UUID=");
+        crypttab.push(&self.uuid);
+        crypttab.push(" ");
+        match self.key {
+            CryptKey::Passphrase => crypttab.push("none"),
+            CryptKey::File(ref path) => crypttab.push(path),
+            CryptKey::Random => crypttab.push("/dev/urandom"),
+        }
+        crypttab.push(" ");
+        crypttab.push(if self.swap { CRYPTSWAP_OPTIONS } else { "luks" });
+        crypttab.push("\n");
+    }
+}
+
+/// A file system, or swap, which is enabled at boot, as described by a line of `/etc/fstab`.
+///
+/// Swap which is encrypted with a random key is described by its `CryptVolume` instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MountEntry {
+    /// Where the file system is mounted, or `None` for swap.
+    pub target:  Option<PathBuf>,
+    /// The device which contains the file system.
+    pub device:  PathBuf,
+    /// The UUID of the file system, if it was found.
+    pub uuid:    Option<String>,
+    pub fs:      FileSystem,
+    pub options: &'static str,
+}
+
+impl MountEntry {
+    fn new(partition: &PartitionInfo) -> Option<Self> {
+        let fs = partition.get_file_system()?;
+        if fs != Swap && partition.target.is_none() {
+            return None;
+        }
+
+        let device = partition.get_device_path();
+        Some(MountEntry {
+            target: partition.target.clone(),
+            device: device.to_path_buf(),
+            uuid: PartitionID::get_uuid(device).map(|uuid| uuid.id),
+            fs,
+            options: get_preferred_options(fs),
+        })
+    }
+
+    /// A path to the device which does not depend on the order that devices are probed in.
+    pub fn stable_device(&self) -> PathBuf {
+        match self.uuid {
+            Some(ref uuid) => Path::new("/dev/disk/by-uuid").join(uuid),
+            None => self.device.clone(),
+        }
+    }
+}
+
+/// The file systems and encrypted volumes that the fstab and crypttab are generated from.
+struct Tables {
+    volumes: Vec<CryptVolume>,
+    mounts:  Vec<MountEntry>,
+    fstab:   OsString,
+}
+
 pub trait InstallerDiskOps: Sync {
     /// Generates the crypttab and fstab files in memory.
     fn generate_fstabs(&self) -> (OsString, OsString);
 
+    /// The encrypted volumes which are written to the crypttab by `generate_fstabs`.
+    fn crypt_volumes(&self) -> Vec<CryptVolume>;
+
+    /// The file systems and swap which are written to the fstab by `generate_fstabs`.
+    fn mount_entries(&self) -> Vec<MountEntry>;
+
     /// Find the root partition's block info from this disks object.
     fn get_block_info_of(&self, mount: &str) -> io::Result<BlockInfo>;
 
@@ -27,118 +128,12 @@ pub trait InstallerDiskOps: Sync {
 impl InstallerDiskOps for Disks {
     /// Generates the crypttab and fstab files in memory.
     fn generate_fstabs(&self) -> (OsString, OsString) {
-        let &Disks { ref logical, ref physical, .. } = self;
-
         info!("generating /etc/crypttab & /etc/fstab in memory");
+        let Tables { volumes, mut fstab, .. } = generate_tables(self);
+
         let mut crypttab = OsString::with_capacity(1024);
-        let mut fstab = OsString::with_capacity(1024);
-
-        let partitions = physical
-            .iter()
-            .flat_map(|x| {
-                x.file_system
-                    .as_ref()
-                    .into_iter()
-                    .chain(x.partitions.iter())
-                    .map(|p| (true, &None, p))
-            })
-            .chain(logical.iter().flat_map(|x| {
-                let luks_parent = &x.luks_parent;
-                let is_unencrypted: bool = x.encryption.is_none();
-                x.file_system
-                    .as_ref()
-                    .into_iter()
-                    .chain(x.partitions.iter())
-                    .map(move |p| (is_unencrypted, luks_parent, p))
-            }));
-
-        let mut swap_uuids: Vec<u64> = Vec::new();
-        let mut crypt_ids: Vec<u64> = Vec::new();
-
-        for (is_unencrypted, luks_parent, partition) in partitions {
-            if let Some(&(_, Some(ref enc))) = partition.volume_group.as_ref() {
-                let password: Cow<'static, OsStr> =
-                    match (enc.password.is_some(), enc.keydata.as_ref()) {
-                        (true, None) => Cow::Borrowed(OsStr::new("none")),
-                        (false, None) => Cow::Borrowed(OsStr::new("/dev/urandom")),
-                        (true, Some(_key)) => unimplemented!(),
-                        (false, Some(&(_, ref key))) => {
-                            let path = key
-                                .clone()
-                                .expect("should have been populated")
-                                .1
-                                .join(&enc.physical_volume);
-                            Cow::Owned(path.into_os_string())
-                        }
-                    };
-
-                let ppath = partition.get_device_path();
-                let luks_path = luks_parent.as_ref().map_or(ppath, |x| &x);
-
-                for logical in logical {
-                    if let Some(ref parent) = logical.luks_parent {
-                        if parent == ppath {
-                            if logical.partitions.iter().any(|p| p.target.is_some()) {
-                                match PartitionID::get_uuid(luks_path) {
-                                    Some(uuid) => {
-                                        let id = hasher(&enc.physical_volume);
-                                        if !crypt_ids.contains(&id) {
-                                            crypt_ids.push(id);
-
-                                            crypttab.push(&enc.physical_volume);
-                                            crypttab.push(" UUID=");
-                                            crypttab.push(&uuid.id);
-                                            crypttab.push(" ");
-                                            crypttab.push(&password);
-                                            crypttab.push(" luks\n");
-                                        }
-                                    }
-                                    None => warn!(
-                                        "unable to find UUID for {} -- skipping",
-                                        ppath.display()
-                                    ),
-                                }
-                            }
-                            break;
-                        }
-                    }
-                }
-                if let Some(blockinfo) = partition.get_block_info() {
-                    blockinfo.write_entry(&mut fstab);
-                }
-            } else if partition.is_swap() {
-                if is_unencrypted {
-                    match PartitionID::get_uuid(&partition.get_device_path()) {
-                        Some(uuid) => {
-                            let unique_id = generate_unique_id("cryptswap", &swap_uuids)
-                                .unwrap_or_else(|_| "cryptswap".into());
-
-                            swap_uuids.push(hasher(&unique_id));
-
-                            crypttab.push(&unique_id);
-                            crypttab.push(" UUID=");
-                            crypttab.push(&uuid.id);
-                            crypttab.push(
-                                " /dev/urandom swap,plain,offset=1024,cipher=aes-xts-plain64,size=512\n",
-                            );
-
-                            fstab.push(
-                                &["/dev/mapper/", &unique_id, "  none  swap  defaults  0  0\n"]
-                                    .concat(),
-                            );
-                        }
-                        None => warn!(
-                            "unable to find UUID for {} -- skipping",
-                            partition.get_device_path().display()
-                        ),
-                    }
-                } else {
-                    fstab.push(partition.get_device_path());
-                    fstab.push("  none  swap  defaults  0  0\n");
-                }
-            } else if let Some(blockinfo) = partition.get_block_info() {
-                blockinfo.write_entry(&mut fstab);
-            }
+        for volume in &volumes {
+            volume.write_entry(&mut crypttab);
         }
 
         info!("generated the following crypttab data:\n{}", crypttab.to_string_lossy(),);
@@ -150,6 +145,10 @@ impl InstallerDiskOps for Disks {
         (crypttab, fstab)
     }
 
+    fn crypt_volumes(&self) -> Vec<CryptVolume> { generate_tables(self).volumes }
+
+    fn mount_entries(&self) -> Vec<MountEntry> { generate_tables(self).mounts }
+
     fn get_block_info_of(&self, path: &str) -> io::Result<BlockInfo> {
         self.get_partitions()
             .filter_map(|part| part.get_block_info())
@@ -177,3 +176,164 @@ impl InstallerDiskOps for Disks {
         flags
     }
 }
+
+/// How the volume is unlocked at boot. A keyfile is preferred to prompting for the password,
+/// when a volume has both.
+fn crypt_key(enc: &LvmEncryption) -> CryptKey {
+    match (enc.password.is_some(), enc.keydata.as_ref()) {
+        (true, None) => CryptKey::Passphrase,
+        (false, None) => CryptKey::Random,
+        (_, Some(&(_, ref key))) => CryptKey::File(
+            key.clone().expect("should have been populated").1.join(&enc.physical_volume),
+        ),
+    }
+}
+
+/// Generates the encrypted volumes for the crypttab, and the fstab.
+fn generate_tables(disks: &Disks) -> Tables {
+    let &Disks { ref logical, ref physical, .. } = disks;
+
+    let mut volumes = Vec::new();
+    let mut mounts = Vec::new();
+    let mut fstab = OsString::with_capacity(1024);
+
+    let partitions = physical
+        .iter()
+        .flat_map(|x| {
+            x.file_system.as_ref().into_iter().chain(x.partitions.iter()).map(|p| (true, &None, p))
+        })
+        .chain(logical.iter().flat_map(|x| {
+            let luks_parent = &x.luks_parent;
+            let is_unencrypted: bool = x.encryption.is_none();
+            x.file_system
+                .as_ref()
+                .into_iter()
+                .chain(x.partitions.iter())
+                .map(move |p| (is_unencrypted, luks_parent, p))
+        }));
+
+    let mut swap_uuids: Vec<u64> = Vec::new();
+    let mut crypt_ids: Vec<u64> = Vec::new();
+
+    for (is_unencrypted, luks_parent, partition) in partitions {
+        if let Some(&(_, Some(ref enc))) = partition.volume_group.as_ref() {
+            let key = crypt_key(enc);
+            let ppath = partition.get_device_path();
+            let luks_path = luks_parent.as_ref().map_or(ppath, |x| &x);
+
+            for logical in logical {
+                if let Some(ref parent) = logical.luks_parent {
+                    if parent == ppath {
+                        if logical.partitions.iter().any(|p| p.target.is_some()) {
+                            match PartitionID::get_uuid(luks_path) {
+                                Some(uuid) => {
+                                    let id = hasher(&enc.physical_volume);
+                                    if !crypt_ids.contains(&id) {
+                                        crypt_ids.push(id);
+
+                                        volumes.push(CryptVolume {
+                                            name:   enc.physical_volume.clone(),
+                                            device: luks_path.to_path_buf(),
+                                            uuid:   uuid.id,
+                                            key:    key.clone(),
+                                            swap:   false,
+                                        });
+                                    }
+                                }
+                                None => {
+                                    warn!("unable to find UUID for {} -- skipping", ppath.display())
+                                }
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+            if let Some(blockinfo) = partition.get_block_info() {
+                blockinfo.write_entry(&mut fstab);
+                mounts.extend(MountEntry::new(partition));
+            }
+        } else if partition.is_swap() {
+            if is_unencrypted {
+                match PartitionID::get_uuid(&partition.get_device_path()) {
+                    Some(uuid) => {
+                        let unique_id = generate_unique_id("cryptswap", &swap_uuids)
+                            .unwrap_or_else(|_| "cryptswap".into());
+
+                        swap_uuids.push(hasher(&unique_id));
+
+                        fstab.push(
+                            &["/dev/mapper/", &unique_id, "  none  swap  defaults  0  0\n"]
+                                .concat(),
+                        );
+
+                        volumes.push(CryptVolume {
+                            name:   unique_id,
+                            device: partition.get_device_path().to_path_buf(),
+                            uuid:   uuid.id,
+                            key:    CryptKey::Random,
+                            swap:   true,
+                        });
+                    }
+                    None => warn!(
+                        "unable to find UUID for {} -- skipping",
+                        partition.get_device_path().display()
+                    ),
+                }
+            } else {
+                fstab.push(partition.get_device_path());
+                fstab.push("  none  swap  defaults  0  0\n");
+                mounts.extend(MountEntry::new(partition));
+            }
+        } else if let Some(blockinfo) = partition.get_block_info() {
+            blockinfo.write_entry(&mut fstab);
+            mounts.extend(MountEntry::new(partition));
+        }
+    }
+
+    Tables { volumes, mounts, fstab }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crypttab_entries() {
+        let mut crypttab = OsString::new();
+        let volume = |name: &str, key, swap| CryptVolume {
+            name: name.into(),
+            device: PathBuf::from("/dev/sda3"),
+            uuid: "1234".into(),
+            key,
+            swap,
+        };
+
+        volume("cryptdata", CryptKey::Passphrase, false).write_entry(&mut crypttab);
+        volume("cryptkey", CryptKey::File("/etc/cryptkeys/cryptkey".into()), false)
+            .write_entry(&mut crypttab);
+        volume("cryptswap", CryptKey::Random, true).write_entry(&mut crypttab);
+
+        let expected = [
+            "cryptdata UUID=1234 none luks\n",
+            "cryptkey UUID=1234 /etc/cryptkeys/cryptkey luks\n",
+            "cryptswap UUID=1234 /dev/urandom ",
+            CRYPTSWAP_OPTIONS,
+            "\n",
+        ];
+
+        assert_eq!(crypttab.to_string_lossy(), expected.concat());
+    }
+
+    #[test]
+    fn crypt_keys() {
+        let mut enc = LvmEncryption::new("cryptdata".into(), Some("password".into()), None);
+        assert_eq!(crypt_key(&enc), CryptKey::Passphrase);
+
+        enc.keydata = Some(("key".into(), Some(("/dev/sda4".into(), "/etc/cryptkeys".into()))));
+        assert_eq!(crypt_key(&enc), CryptKey::File("/etc/cryptkeys/cryptdata".into()));
+
+        enc.password = None;
+        assert_eq!(crypt_key(&enc), CryptKey::File("/etc/cryptkeys/cryptdata".into()));
+    }
+}
@@ -43,6 +43,7 @@ extern crate libparted;
 #[macro_use]
 extern crate log;
 extern crate logind_dbus;
+extern crate pwhash;
 extern crate rayon;
 extern crate systemd_boot_conf;
 extern crate tempdir;
@@ -61,6 +62,7 @@ mod logging;
 mod upgrade;
 
 pub mod auto;
+pub mod plan;
 pub(crate) mod errors;
 
 /// Useful DBus interfaces for installers to implement.
@@ -0,0 +1,225 @@
+//! Applies the disks section of a plan to freshly-probed devices.
+
+use super::*;
+use crate::disks::{Disk, DiskExt, Disks, LogicalDevice, PartitionBuilder, PartitionInfo};
+use disk_types::{BlockDeviceExt, PartitionExt, SectorExt};
+
+impl DisksPlan {
+    /// Probes each disk referenced by the plan, and applies the plan's changes to them.
+    ///
+    /// Operations are applied in the same order as the command line interface: new
+    /// partition tables, then deleted, moved, reused, and new partitions, followed by the
+    /// initialization of volume groups, the decryption of LUKS partitions, and finally
+    /// changes to logical volumes.
+    pub fn build(&self) -> Result<Disks, PlanError> {
+        let mut disks = Disks::default();
+
+        for plan in &self.disks {
+            info!("adding {} to disks configuration", plan.path.display());
+            if plan.path.is_file() {
+                disks.add_image(&plan.path)?;
+            } else {
+                disks.add(Disk::from_name(&plan.path)?);
+            }
+        }
+
+        for plan in &self.disks {
+            let disk = disks
+                .find_disk_mut(&plan.path)
+                .ok_or_else(|| PlanError::DiskNotFound { path: plan.path.clone() })?;
+            plan.apply(disk)?;
+        }
+
+        disks.initialize_volume_groups()?;
+
+        for decrypt in &self.decrypt {
+            disks
+                .decrypt_partition(&decrypt.device, &decrypt.encryption)
+                .map_err(|why| PlanError::Decrypt { device: decrypt.device.clone(), why })?;
+        }
+
+        for plan in &self.logical {
+            let device = disks
+                .get_logical_device_mut(&plan.group)
+                .ok_or_else(|| PlanError::LogicalDeviceNotFound { group: plan.group.clone() })?;
+            plan.apply(device)?;
+        }
+
+        Ok(disks)
+    }
+}
+
+impl PlanDisk {
+    fn apply(&self, disk: &mut Disk) -> Result<(), PlanError> {
+        if let Some(table) = self.table {
+            disk.mklabel(table)?;
+        }
+
+        for &partition in &self.delete {
+            disk.remove_partition(partition)?;
+        }
+
+        // Positions which already match the partition are skipped, so that exported plans,
+        // which record the position of every retained partition, can be applied as-is.
+        for moved in &self.moves {
+            if let Some(start) = moved.start {
+                let start = disk.get_sector(start);
+                if start != find_partition(disk, moved.partition)?.start_sector {
+                    disk.move_partition(moved.partition, start)?;
+                }
+            }
+
+            if let Some(end) = moved.end {
+                let end = disk.get_sector(end);
+                if end != find_partition(disk, moved.partition)?.end_sector {
+                    disk.resize_partition(moved.partition, end)?;
+                }
+            }
+        }
+
+        for reuse in &self.reuse {
+            let partition = disk.get_partition_mut(reuse.partition).ok_or_else(|| {
+                PlanError::PartitionNotFound {
+                    disk:      self.path.clone(),
+                    partition: reuse.partition,
+                }
+            })?;
+
+            if let Some(ref mount) = reuse.mount {
+                partition.set_mount(mount.clone());
+            }
+
+            if let Some(ref key_id) = reuse.key_id {
+                partition.associate_keyfile(key_id.clone());
+            }
+
+            let fs = match reuse.format {
+                Some(PlanFileSystem::Fs(fs)) => Some(fs),
+                Some(PlanFileSystem::Lvm { ref group, ref encryption }) => {
+                    partition.set_volume_group(group.clone(), encryption.clone());
+                    Some(FileSystem::Lvm)
+                }
+                None => None,
+            };
+
+            if let Some(fs) = fs {
+                let path = partition.device_path.clone();
+                partition
+                    .format_with_options(fs, reuse.format_options.clone())
+                    .map_err(|why| DiskError::new_partition_error(path, why))?;
+            }
+
+            if let Some(ref flags) = reuse.flags {
+                partition.flags = flags.clone();
+            }
+        }
+
+        for new in &self.new {
+            let (start, end) = (disk.get_sector(new.start), disk.get_sector(new.end));
+            let mut builder = match new.format {
+                Some(PlanFileSystem::Fs(fs)) => PartitionBuilder::new(start, end, fs),
+                Some(PlanFileSystem::Lvm { ref group, ref encryption }) => {
+                    PartitionBuilder::new(start, end, FileSystem::Lvm)
+                        .logical_volume(group.clone(), encryption.clone())
+                }
+                None => PartitionBuilder::new(start, end, None),
+            };
+
+            builder = builder
+                .partition_type(new.kind)
+                .flags(new.flags.clone())
+                .format_options(new.format_options.clone());
+
+            if let Some(ref name) = new.name {
+                builder = builder.name(name.clone());
+            }
+
+            if let Some(ref mount) = new.mount {
+                builder = builder.mount(mount.clone());
+            }
+
+            if let Some(ref key_id) = new.key_id {
+                builder = builder.associate_keyfile(key_id.clone());
+            }
+
+            disk.add_partition(builder)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PlanLogical {
+    pub(super) fn apply(&self, device: &mut LogicalDevice) -> Result<(), PlanError> {
+        if self.remove_all {
+            device.clear_partitions();
+        }
+
+        for volume in &self.remove {
+            device.remove_partition(volume)?;
+        }
+
+        for modify in &self.modify {
+            let group = &self.group;
+            let partition = device.get_partition_mut(&modify.volume).ok_or_else(|| {
+                PlanError::LogicalPartitionNotFound {
+                    group:  group.clone(),
+                    volume: modify.volume.clone(),
+                }
+            })?;
+
+            if let Some(fs) = modify.fs {
+                partition.format_and_keep_name(fs);
+            }
+
+            if let Some(ref mount) = modify.mount {
+                partition.set_mount(mount.clone());
+            }
+
+            // Sizes which already match the volume are skipped, as with moved partitions.
+            if let Some(size) = modify.size {
+                let sectors = device.get_sector(size);
+                let current = device.get_partition(&modify.volume).map(|p| p.get_sectors());
+                if current != Some(sectors) {
+                    device.resize_volume(&modify.volume, sectors)?;
+                }
+            }
+        }
+
+        device.set_remaining_space(self.remaining);
+
+        for pool in &self.thin_pools {
+            let sectors = device.get_sector(pool.size);
+            device.add_thin_pool(&pool.name, sectors)?;
+        }
+
+        // Thin volumes do not take space from the volume group, so they are not placed
+        // after the other volumes.
+        for volume in &self.new {
+            let sectors = device.get_sector(volume.size);
+            let start = if volume.pool.is_some() { 0 } else { device.get_last_sector() };
+            let mut builder = PartitionBuilder::new(start, start + sectors, volume.fs)
+                .name(volume.name.clone())
+                .flags(volume.flags.clone())
+                .format_options(volume.format_options.clone());
+
+            if let Some(ref mount) = volume.mount {
+                builder = builder.mount(mount.clone());
+            }
+
+            match volume.pool {
+                Some(ref pool) => device.add_thin_volume(pool, builder)?,
+                None => device.add_partition(builder)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn find_partition(disk: &Disk, partition: i32) -> Result<&PartitionInfo, PlanError> {
+    disk.get_partition(partition).ok_or_else(|| PlanError::PartitionNotFound {
+        disk: disk.get_device_path().to_path_buf(),
+        partition,
+    })
+}
@@ -0,0 +1,540 @@
+//! Reports the changes that installing a disks configuration will make to each device,
+//! without applying them.
+
+use super::format;
+use crate::disks::{
+    Disk, DiskError, Disks, FormatOptions, LogicalDevice, PartitionFlag, PartitionTable, FORMAT,
+    REMOVE, SOURCE,
+};
+use disk_types::{FileSystem, PartitionExt, PartitionType};
+use std::{fmt, path::PathBuf, ptr};
+
+/// An operation that will be performed on a device when the disks configuration is installed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// A new partition table will be written, wiping every partition on the disk.
+    Mklabel { table: PartitionTable },
+    /// An existing partition will be deleted.
+    Remove { partition: PathBuf, number: i32, file_system: Option<FileSystem> },
+    /// An existing partition will be wiped and formatted with a new file system.
+    Format {
+        partition: PathBuf,
+        number:    i32,
+        old:       Option<FileSystem>,
+        new:       Option<FileSystem>,
+        options:   FormatOptions,
+    },
+    /// An existing partition will be moved and / or resized, retaining its data.
+    Resize { partition: PathBuf, number: i32, old: (u64, u64), new: (u64, u64) },
+    /// New flags or a new label will be set on an existing partition.
+    Modify {
+        partition: PathBuf,
+        number:    i32,
+        flags:     Vec<PartitionFlag>,
+        label:     Option<String>,
+    },
+    /// A new partition will be created, and formatted if it has a file system.
+    Create {
+        start:       u64,
+        end:         u64,
+        kind:        PartitionType,
+        file_system: Option<FileSystem>,
+        options:     FormatOptions,
+        label:       Option<String>,
+        flags:       Vec<PartitionFlag>,
+    },
+    /// A partition will be encrypted with LUKS. The partition will be `None` if it has yet
+    /// to be created.
+    Encrypt { partition: Option<PathBuf>, physical_volume: String },
+    /// A new volume group will be created.
+    CreateVolumeGroup { group: String },
+    /// A new logical volume will be created, and formatted if it has a file system.
+    CreateVolume {
+        group:       String,
+        volume:      String,
+        sectors:     u64,
+        file_system: Option<FileSystem>,
+        options:     FormatOptions,
+    },
+    /// An existing logical volume will be resized, along with its file system.
+    ResizeVolume { group: String, volume: String, old: u64, new: u64 },
+    /// A new thin pool will be created, which thin volumes are provisioned from.
+    CreateThinPool { group: String, pool: String, sectors: u64 },
+    /// An existing logical volume will be deleted.
+    RemoveVolume { group: String, volume: String },
+    /// An existing logical volume will be wiped and formatted with a new file system.
+    FormatVolume { group: String, volume: String, file_system: Option<FileSystem> },
+}
+
+impl Change {
+    /// Whether the change will destroy data which currently exists on the device.
+    pub fn is_destructive(&self) -> bool {
+        match *self {
+            Change::Mklabel { .. }
+            | Change::Remove { .. }
+            | Change::Format { .. }
+            | Change::Encrypt { .. }
+            | Change::RemoveVolume { .. }
+            | Change::FormatVolume { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Mklabel { table } => write!(
+                f,
+                "write a new {} partition table",
+                match table {
+                    PartitionTable::Gpt => "GPT",
+                    PartitionTable::Msdos => "MSDOS",
+                }
+            ),
+            Change::Remove { ref partition, file_system, .. } => {
+                write!(f, "delete {} ({})", partition.display(), fs_name(file_system))
+            }
+            Change::Format { ref partition, old, new, ref options, .. } => {
+                write!(
+                    f,
+                    "format {} as {} (was {})",
+                    partition.display(),
+                    fs_name(new),
+                    fs_name(old)
+                )?;
+                write_options(f, options)
+            }
+            Change::Resize { ref partition, old, new, .. } => write!(
+                f,
+                "move {} from sectors {}-{} to {}-{}",
+                partition.display(),
+                old.0,
+                old.1,
+                new.0,
+                new.1
+            ),
+            Change::Modify { ref partition, ref flags, ref label, .. } => {
+                write!(f, "modify {}", partition.display())?;
+                if !flags.is_empty() {
+                    write!(f, ", setting flags {:?}", flags)?;
+                }
+                if let Some(ref label) = *label {
+                    write!(f, ", setting label '{}'", label)?;
+                }
+                Ok(())
+            }
+            Change::Create { start, end, file_system, ref options, ref label, .. } => {
+                write!(
+                    f,
+                    "create {} partition at sectors {}-{}",
+                    fs_name(file_system),
+                    start,
+                    end
+                )?;
+                if let Some(ref label) = *label {
+                    write!(f, " labeled '{}'", label)?;
+                }
+                write_options(f, options)
+            }
+            Change::Encrypt { ref partition, ref physical_volume } => match *partition {
+                Some(ref partition) => {
+                    write!(f, "encrypt {} with LUKS as {}", partition.display(), physical_volume)
+                }
+                None => write!(f, "encrypt new partition with LUKS as {}", physical_volume),
+            },
+            Change::CreateVolumeGroup { ref group } => write!(f, "create volume group {}", group),
+            Change::CreateVolume { ref group, ref volume, sectors, file_system, ref options } => {
+                write!(
+                    f,
+                    "create {} logical volume {}/{} with {} sectors",
+                    fs_name(file_system),
+                    group,
+                    volume,
+                    sectors
+                )?;
+                write_options(f, options)
+            }
+            Change::ResizeVolume { ref group, ref volume, old, new } => write!(
+                f,
+                "resize logical volume {}/{} from {} to {} sectors",
+                group, volume, old, new
+            ),
+            Change::CreateThinPool { ref group, ref pool, sectors } => {
+                write!(f, "create thin pool {}/{} with {} sectors", group, pool, sectors)
+            }
+            Change::RemoveVolume { ref group, ref volume } => {
+                write!(f, "delete logical volume {}/{}", group, volume)
+            }
+            Change::FormatVolume { ref group, ref volume, file_system } => {
+                write!(f, "format logical volume {}/{} as {}", group, volume, fs_name(file_system))
+            }
+        }
+    }
+}
+
+/// All of the changes that will be made to a single physical or logical device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceChanges {
+    pub device:  PathBuf,
+    pub changes: Vec<Change>,
+}
+
+impl DeviceChanges {
+    /// Compares a disk against the source disk that it was derived from, in the same manner
+    /// as when the disk is committed.
+    pub fn from_disk(source: &Disk, new: &Disk) -> Result<DeviceChanges, DiskError> {
+        let ops = source.diff(new)?;
+        let mut changes = Vec::new();
+
+        if let Some(table) = ops.mklabel {
+            changes.push(Change::Mklabel { table });
+            for part in &source.partitions {
+                changes.push(Change::Remove {
+                    partition:   part.device_path.clone(),
+                    number:      part.number,
+                    file_system: part.filesystem,
+                });
+            }
+        }
+
+        // Source partitions which are to be formatted are removed and then re-created by
+        // the diff, so they are paired back up with their replacements here.
+        let mut formatted = Vec::new();
+        for &start in &ops.remove_partitions {
+            let part = match source.partitions.iter().find(|part| part.start_sector == start) {
+                Some(part) => part,
+                None => continue,
+            };
+
+            let replacement = new.partitions.iter().find(|new| {
+                new.number == part.number
+                    && new.flag_is_enabled(SOURCE)
+                    && !new.flag_is_enabled(REMOVE)
+            });
+
+            changes.push(match replacement {
+                Some(replacement) => {
+                    formatted.push(replacement.start_sector);
+                    Change::Format {
+                        partition: part.device_path.clone(),
+                        number:    part.number,
+                        old:       part.filesystem,
+                        new:       replacement.filesystem,
+                        options:   replacement.fs_options.clone(),
+                    }
+                }
+                None => Change::Remove {
+                    partition:   part.device_path.clone(),
+                    number:      part.number,
+                    file_system: part.filesystem,
+                },
+            });
+        }
+
+        for change in &ops.change_partitions {
+            let old = match source.get_partition(change.num) {
+                Some(old) => old,
+                None => continue,
+            };
+
+            if old.start_sector != change.start || old.end_sector != change.end {
+                changes.push(Change::Resize {
+                    partition: change.path.clone(),
+                    number:    change.num,
+                    old:       (old.start_sector, old.end_sector),
+                    new:       (change.start, change.end),
+                });
+            }
+
+            let label = change.label.clone().filter(|label| old.name.as_ref() != Some(label));
+            if !change.flags.is_empty() || label.is_some() {
+                changes.push(Change::Modify {
+                    partition: change.path.clone(),
+                    number: change.num,
+                    flags: change.flags.clone(),
+                    label,
+                });
+            }
+        }
+
+        for create in &ops.create_partitions {
+            if formatted.contains(&create.start_sector) {
+                continue;
+            }
+
+            changes.push(Change::Create {
+                start:       create.start_sector,
+                end:         create.end_sector,
+                kind:        create.kind,
+                file_system: create.file_system,
+                options:     create.fs_options.clone(),
+                label:       create.label.clone(),
+                flags:       create.flags.clone(),
+            });
+        }
+
+        Ok(DeviceChanges { device: new.device_path.clone(), changes })
+    }
+
+    /// Collects the changes that will be made to a logical device, in the same manner as
+    /// `Disks::commit_logical_partitions`.
+    pub fn from_logical(device: &LogicalDevice, disks: &Disks) -> DeviceChanges {
+        let mut changes = Vec::new();
+        let group = &device.volume_group;
+
+        if !device.is_source {
+            if let Some(ref encryption) = device.encryption {
+                let partition = disks
+                    .find_volume_paths(group)
+                    .first()
+                    .map(|&(_, partition)| partition.to_path_buf())
+                    .filter(|partition| partition.as_os_str() != "");
+
+                changes.push(Change::Encrypt {
+                    partition,
+                    physical_volume: encryption.physical_volume.clone(),
+                });
+            }
+
+            changes.push(Change::CreateVolumeGroup { group: group.clone() });
+        }
+
+        for pool in &device.thin_pools {
+            changes.push(Change::CreateThinPool {
+                group:   group.clone(),
+                pool:    pool.name.clone(),
+                sectors: pool.sectors,
+            });
+        }
+
+        let resized = device.get_resized_volumes();
+
+        for part in device.file_system.iter().chain(device.partitions.iter()) {
+            let volume = match part.name {
+                Some(ref name) => name.clone(),
+                None => continue,
+            };
+
+            if !part.flag_is_enabled(SOURCE) {
+                if !part.flag_is_enabled(REMOVE) {
+                    changes.push(Change::CreateVolume {
+                        group: group.clone(),
+                        volume,
+                        sectors: part.get_sectors(),
+                        file_system: part.filesystem,
+                        options: part.fs_options.clone(),
+                    });
+                }
+            } else if part.flag_is_enabled(REMOVE) {
+                changes.push(Change::RemoveVolume { group: group.clone(), volume });
+            } else {
+                if let Some(&(_, old)) = resized.iter().find(|&&(p, _)| ptr::eq(p, part)) {
+                    changes.push(Change::ResizeVolume {
+                        group: group.clone(),
+                        volume: volume.clone(),
+                        old,
+                        new: part.get_sectors(),
+                    });
+                }
+
+                if part.flag_is_enabled(FORMAT) {
+                    changes.push(Change::FormatVolume {
+                        group: group.clone(),
+                        volume,
+                        file_system: part.filesystem,
+                    });
+                }
+            }
+        }
+
+        DeviceChanges { device: device.device_path.clone(), changes }
+    }
+}
+
+/// Describes every change that `Installer::install` will make to the devices in a disks
+/// configuration, so that they may be confirmed before being applied.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChangeReport {
+    pub devices: Vec<DeviceChanges>,
+}
+
+impl ChangeReport {
+    /// Probes the source of each physical device in the configuration, and compares it
+    /// against the configuration. Devices without changes are omitted.
+    pub fn new(disks: &Disks) -> Result<ChangeReport, DiskError> {
+        let mut devices = Vec::new();
+
+        for disk in disks.get_physical_devices() {
+            let source = Disk::from_name_with_serial(&disk.device_path, &disk.serial)?;
+            devices.push(DeviceChanges::from_disk(&source, disk)?);
+        }
+
+        for device in disks.get_logical_devices() {
+            devices.push(DeviceChanges::from_logical(device, disks));
+        }
+
+        devices.retain(|device| !device.changes.is_empty());
+        Ok(ChangeReport { devices })
+    }
+
+    /// Whether no changes will be made.
+    pub fn is_empty(&self) -> bool { self.devices.is_empty() }
+
+    /// Whether any change will destroy existing data.
+    pub fn is_destructive(&self) -> bool {
+        self.devices.iter().flat_map(|device| device.changes.iter()).any(Change::is_destructive)
+    }
+
+    /// Serializes the report into its JSON representation.
+    pub fn to_json(&self) -> String { format::report_to_json(self).pretty(4) }
+}
+
+impl fmt::Display for ChangeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for device in &self.devices {
+            writeln!(f, "{}:", device.device.display())?;
+            for change in &device.changes {
+                let marker = if change.is_destructive() { '!' } else { '*' };
+                writeln!(f, "    {} {}", marker, change)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Describes the options that a file system will be created with, if any were given.
+fn write_options(f: &mut fmt::Formatter, options: &FormatOptions) -> fmt::Result {
+    if let Some(ref label) = options.label {
+        write!(f, ", with file system label '{}'", label)?;
+    }
+    if let Some(ref uuid) = options.uuid {
+        write!(f, ", with UUID {}", uuid)?;
+    }
+    if !options.ext_features.is_empty() {
+        write!(f, ", with features {}", options.ext_features.join(","))?;
+    }
+    if let Some(profile) = options.btrfs_data {
+        write!(f, ", with {} data", profile)?;
+    }
+    if let Some(profile) = options.btrfs_metadata {
+        write!(f, ", with {} metadata", profile)?;
+    }
+    if let Some(reflink) = options.xfs_reflink {
+        write!(f, ", with reflinks {}", if reflink { "enabled" } else { "disabled" })?;
+    }
+    Ok(())
+}
+
+fn fs_name(fs: Option<FileSystem>) -> &'static str {
+    match fs {
+        Some(fs) => fs.into(),
+        None => "unformatted",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disks::{PartitionBuilder, PartitionInfo};
+    use partition_identity::PartitionIdentifiers;
+    use std::path::Path;
+
+    fn partition(number: i32, start: u64, end: u64, fs: FileSystem) -> PartitionInfo {
+        PartitionInfo {
+            bitflags: SOURCE,
+            device_path: PathBuf::from(format!("/dev/sdz{}", number)),
+            flags: vec![],
+            mount_point: None,
+            target: None,
+            start_sector: start,
+            end_sector: end,
+            filesystem: Some(fs),
+            name: None,
+            number,
+            ordering: number,
+            part_type: PartitionType::Primary,
+            key_id: None,
+            original_vg: None,
+            volume_group: None,
+            identifiers: PartitionIdentifiers::default(),
+            fs_options: FormatOptions::default(),
+        }
+    }
+
+    fn source() -> Disk {
+        Disk {
+            mklabel:     false,
+            model_name:  "Test Disk".into(),
+            serial:      "Test Disk 123".into(),
+            device_path: "/dev/sdz".into(),
+            file_system: None,
+            mount_point: None,
+            size:        1953525168,
+            device_type: "TEST".into(),
+            table_type:  Some(PartitionTable::Gpt),
+            read_only:   false,
+            partitions:  vec![
+                partition(1, 2048, 1026047, FileSystem::Fat16),
+                partition(2, 1026048, 420456447, FileSystem::Ext4),
+            ],
+        }
+    }
+
+    #[test]
+    fn report_changes() {
+        let source = source();
+        let mut new = source.clone();
+        new.remove_partition(1).unwrap();
+        new.format_partition(2, FileSystem::Xfs).unwrap();
+        new.add_partition(
+            PartitionBuilder::new(2048, 1026048, FileSystem::Fat32)
+                .format_options(FormatOptions::new().label("EFI")),
+        )
+        .unwrap();
+
+        let report = DeviceChanges::from_disk(&source, &new).unwrap();
+        assert_eq!(report.device, Path::new("/dev/sdz"));
+        assert_eq!(
+            report.changes,
+            vec![
+                Change::Remove {
+                    partition:   "/dev/sdz1".into(),
+                    number:      1,
+                    file_system: Some(FileSystem::Fat16),
+                },
+                Change::Format {
+                    partition: "/dev/sdz2".into(),
+                    number:    2,
+                    old:       Some(FileSystem::Ext4),
+                    new:       Some(FileSystem::Xfs),
+                    options:   FormatOptions::default(),
+                },
+                Change::Create {
+                    start:       2048,
+                    end:         1026047,
+                    kind:        PartitionType::Primary,
+                    file_system: Some(FileSystem::Fat32),
+                    options:     FormatOptions::new().label("EFI"),
+                    label:       None,
+                    flags:       vec![],
+                },
+            ]
+        );
+        assert!(report.changes.iter().any(Change::is_destructive));
+        assert_eq!(
+            report.changes[2].to_string(),
+            "create fat32 partition at sectors 2048-1026047, with file system label 'EFI'"
+        );
+
+        let mut new = source.clone();
+        new.mklabel(PartitionTable::Msdos).unwrap();
+        let report = DeviceChanges::from_disk(&source, &new).unwrap();
+        assert_eq!(report.changes[0], Change::Mklabel { table: PartitionTable::Msdos });
+        assert_eq!(report.changes.len(), 3);
+
+        let report = DeviceChanges::from_disk(&source, &source.clone()).unwrap();
+        assert!(report.changes.is_empty());
+    }
+}
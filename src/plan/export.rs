@@ -0,0 +1,215 @@
+//! Describes an in-memory disks configuration as the disks section of a plan.
+
+use super::*;
+use crate::disks::{Disk, Disks, LogicalDevice, PartitionInfo, FORMAT, REMOVE, SOURCE};
+use disk_types::PartitionExt;
+
+impl DisksPlan {
+    /// Describes the changes that have been made to the given disks configuration.
+    ///
+    /// Every retained source partition is recorded with its absolute position, and new
+    /// partitions are recorded with absolute start and end sectors.
+    pub fn from_disks(disks: &Disks) -> DisksPlan {
+        let mut plan = DisksPlan::default();
+
+        for disk in disks.get_physical_devices() {
+            plan.disks.push(export_disk(disk));
+        }
+
+        for device in disks.get_logical_devices() {
+            if let (Some(parent), Some(encryption)) = (&device.luks_parent, &device.encryption) {
+                if device.is_source {
+                    plan.decrypt.push(PlanDecrypt {
+                        device:     parent.clone(),
+                        encryption: encryption.clone(),
+                    });
+                }
+            }
+
+            if let Some(logical) = export_logical(device) {
+                plan.logical.push(logical);
+            }
+        }
+
+        plan
+    }
+}
+
+fn export_disk(disk: &Disk) -> PlanDisk {
+    let mut plan = PlanDisk {
+        path:   disk.device_path.clone(),
+        table:  if disk.mklabel { disk.table_type } else { None },
+        delete: Vec::new(),
+        moves:  Vec::new(),
+        reuse:  Vec::new(),
+        new:    Vec::new(),
+    };
+
+    for part in &disk.partitions {
+        if !part.flag_is_enabled(SOURCE) {
+            plan.new.push(PlanPartition {
+                kind:           part.part_type,
+                start:          Sector::Unit(part.start_sector),
+                end:            Sector::Unit(part.end_sector),
+                format:         export_format(part),
+                format_options: part.fs_options.clone(),
+                name:           part.name.clone(),
+                mount:          part.target.clone(),
+                flags:          part.flags.clone(),
+                key_id:         part.key_id.clone(),
+            });
+        } else if part.flag_is_enabled(REMOVE) {
+            plan.delete.push(part.number);
+        } else {
+            plan.moves.push(PlanMove {
+                partition: part.number,
+                start:     Some(Sector::Unit(part.start_sector)),
+                end:       Some(Sector::Unit(part.end_sector)),
+            });
+
+            let formatted = part.flag_is_enabled(FORMAT);
+            plan.reuse.push(PlanReuse {
+                partition:      part.number,
+                format:         if formatted { export_format(part) } else { None },
+                format_options: if formatted {
+                    part.fs_options.clone()
+                } else {
+                    FormatOptions::default()
+                },
+                mount:          part.target.clone(),
+                flags:          Some(part.flags.clone()),
+                key_id:         part.key_id.clone(),
+            });
+        }
+    }
+
+    plan
+}
+
+/// Every retained volume is recorded with its size, so that resized volumes are kept.
+fn export_logical(device: &LogicalDevice) -> Option<PlanLogical> {
+    let mut plan = PlanLogical {
+        group:      device.volume_group.clone(),
+        remove_all: false,
+        remove:     Vec::new(),
+        modify:     Vec::new(),
+        thin_pools: device
+            .thin_pools
+            .iter()
+            .map(|pool| PlanThinPool { name: pool.name.clone(), size: Sector::Unit(pool.sectors) })
+            .collect(),
+        new:        Vec::new(),
+        remaining:  device.remaining,
+    };
+
+    for part in &device.partitions {
+        let name = match part.name {
+            Some(ref name) => name.clone(),
+            None => continue,
+        };
+
+        if !part.flag_is_enabled(SOURCE) {
+            plan.new.push(PlanVolume {
+                name,
+                size: Sector::Unit(part.get_sectors()),
+                pool: device.get_thin_pool_of(part).map(|pool| pool.name.clone()),
+                fs: part.filesystem,
+                format_options: part.fs_options.clone(),
+                mount: part.target.clone(),
+                flags: part.flags.clone(),
+            });
+        } else if part.flag_is_enabled(REMOVE) {
+            plan.remove.push(name);
+        } else {
+            plan.modify.push(PlanVolumeModify {
+                volume: name,
+                fs:     if part.flag_is_enabled(FORMAT) { part.filesystem } else { None },
+                mount:  part.target.clone(),
+                size:   Some(Sector::Unit(part.get_sectors())),
+            });
+        }
+    }
+
+    if plan.remove.is_empty()
+        && plan.modify.is_empty()
+        && plan.thin_pools.is_empty()
+        && plan.new.is_empty()
+        && plan.remaining == RemainingSpace::default()
+    {
+        None
+    } else {
+        Some(plan)
+    }
+}
+
+fn export_format(part: &PartitionInfo) -> Option<PlanFileSystem> {
+    match part.volume_group {
+        Some((ref group, ref encryption)) => {
+            Some(PlanFileSystem::Lvm { group: group.clone(), encryption: encryption.clone() })
+        }
+        None => part.filesystem.map(PlanFileSystem::Fs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disks::{DiskExt, PartitionBuilder};
+
+    const MIB: u64 = 2048;
+
+    fn volume(name: &str, sectors: u64) -> PartitionBuilder {
+        PartitionBuilder::new(0, sectors, FileSystem::Ext4).name(name.into())
+    }
+
+    #[test]
+    fn logical_round_trip() {
+        let mut root = volume("root", 400 * MIB).build();
+        root.bitflags = SOURCE;
+        root.device_path = "/dev/mapper/data-root".into();
+
+        let source = LogicalDevice {
+            volume_group: "data".into(),
+            sectors: 1000 * MIB,
+            sector_size: 512,
+            is_source: true,
+            partitions: vec![root],
+            ..LogicalDevice::default()
+        };
+
+        let mut device = source.clone();
+        device.resize_volume("root", 300 * MIB).unwrap();
+        device.add_thin_pool("pool", 500 * MIB).unwrap();
+        device.add_thin_volume("pool", volume("home", 2000 * MIB)).unwrap();
+        let start = device.get_last_sector();
+        let var = PartitionBuilder::new(start, start + 100 * MIB, FileSystem::Ext4);
+        device.add_partition(var.name("var".into())).unwrap();
+        device.set_remaining_space(RemainingSpace::Unallocated);
+
+        let plan = export_logical(&device).unwrap();
+        assert_eq!(plan.modify[0].size, Some(Sector::Unit(300 * MIB)));
+        assert_eq!(plan.new[0].pool, Some("pool".into()));
+        assert_eq!(plan.new[1].pool, None);
+
+        let mut applied = source.clone();
+        plan.apply(&mut applied).unwrap();
+
+        let sizes = |device: &LogicalDevice| {
+            device
+                .partitions
+                .iter()
+                .map(|p| (p.name.clone(), p.get_sectors(), device.get_thin_pool_of(p).cloned()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sizes(&applied), sizes(&device));
+        assert_eq!(applied.thin_pools, device.thin_pools);
+        assert_eq!(applied.remaining, device.remaining);
+
+        let disks = Disks::default();
+        assert_eq!(
+            DeviceChanges::from_logical(&applied, &disks),
+            DeviceChanges::from_logical(&device, &disks)
+        );
+    }
+}
@@ -0,0 +1,790 @@
+//! Conversions between install plans and their JSON representation.
+
+use super::*;
+use crate::disks::BtrfsProfile;
+use json::{object, JsonValue};
+
+const FLAGS: &[(&str, PartitionFlag)] = &[
+    ("esp", PartitionFlag::PED_PARTITION_ESP),
+    ("boot", PartitionFlag::PED_PARTITION_BOOT),
+    ("root", PartitionFlag::PED_PARTITION_ROOT),
+    ("swap", PartitionFlag::PED_PARTITION_SWAP),
+    ("hidden", PartitionFlag::PED_PARTITION_HIDDEN),
+    ("raid", PartitionFlag::PED_PARTITION_RAID),
+    ("lvm", PartitionFlag::PED_PARTITION_LVM),
+    ("lba", PartitionFlag::PED_PARTITION_LBA),
+    ("hpservice", PartitionFlag::PED_PARTITION_HPSERVICE),
+    ("palo", PartitionFlag::PED_PARTITION_PALO),
+    ("prep", PartitionFlag::PED_PARTITION_PREP),
+    ("msft_reserved", PartitionFlag::PED_PARTITION_MSFT_RESERVED),
+    ("apple_tv_recovery", PartitionFlag::PED_PARTITION_APPLE_TV_RECOVERY),
+    ("diag", PartitionFlag::PED_PARTITION_DIAG),
+    ("legacy_boot", PartitionFlag::PED_PARTITION_LEGACY_BOOT),
+    ("msft_data", PartitionFlag::PED_PARTITION_MSFT_DATA),
+    ("irst", PartitionFlag::PED_PARTITION_IRST),
+];
+
+pub(crate) fn parse_plan(value: &JsonValue) -> Result<InstallPlan, PlanError> {
+    let version = value["version"].as_u64().ok_or_else(|| invalid("version", &value["version"]))?;
+    if version != PLAN_VERSION {
+        return Err(PlanError::UnsupportedVersion { version });
+    }
+
+    Ok(InstallPlan {
+        disks:  parse_disks_plan(value)?,
+        config: parse_config(&value["config"])?,
+        users:  parse_users(value)?,
+    })
+}
+
+pub(crate) fn plan_to_json(plan: &InstallPlan) -> JsonValue {
+    let mut value = disks_plan_to_json(&plan.disks);
+    value["config"] = config_to_json(&plan.config);
+    value["users"] = plan.users.iter().map(user_to_json).collect::<Vec<_>>().into();
+
+    value
+}
+
+fn parse_config(value: &JsonValue) -> Result<Config, PlanError> {
+    if !value.is_object() {
+        return Err(invalid("config", value));
+    }
+
+    let string_or = |key: &str, default: &str| -> Result<String, PlanError> {
+        optional(&value[key], &["config.", key].concat(), string)
+            .map(|field| field.unwrap_or_else(|| default.into()))
+    };
+
+    Ok(Config {
+        hostname:         string(&value["hostname"], "config.hostname")?,
+        keyboard_layout:  string_or("keyboard_layout", "us")?,
+        keyboard_model:   optional(&value["keyboard_model"], "config.keyboard_model", string)?,
+        keyboard_variant: optional(&value["keyboard_variant"], "config.keyboard_variant", string)?,
+        old_root:         optional(&value["old_root"], "config.old_root", string)?,
+        lang:             string_or("lang", "en_US.UTF-8")?,
+        remove:           string_or("remove", "")?,
+        squashfs:         string_or("squashfs", "")?,
+        flags:            optional(&value["flags"], "config.flags", |value, field| {
+            value.as_u8().ok_or_else(|| invalid(field, value))
+        })?
+        .unwrap_or(0),
+        distribution:     optional(&value["distribution"], "config.distribution", distribution)?,
+        hooks:            list(&value["hooks"], "config.hooks", path)?,
+        boot_manager:     optional(&value["boot_manager"], "config.boot_manager", boot_manager)?
+            .unwrap_or_default(),
+        nixos:            optional(&value["nixos"], "config.nixos", parse_nixos)?
+            .unwrap_or_default(),
+    })
+}
+
+fn config_to_json(config: &Config) -> JsonValue {
+    object! {
+        "hostname" => config.hostname.as_str(),
+        "keyboard_layout" => config.keyboard_layout.as_str(),
+        "keyboard_model" => config.keyboard_model.clone(),
+        "keyboard_variant" => config.keyboard_variant.clone(),
+        "old_root" => config.old_root.clone(),
+        "lang" => config.lang.as_str(),
+        "remove" => config.remove.as_str(),
+        "squashfs" => config.squashfs.as_str(),
+        "flags" => config.flags,
+        "distribution" => config.distribution.map(|distribution| distribution.to_string()),
+        "hooks" => config.hooks.iter().map(|hook| path_to_json(hook)).collect::<Vec<_>>(),
+        "boot_manager" => config.boot_manager.to_string(),
+        "nixos" => nixos_to_json(&config.nixos)
+    }
+}
+
+/// Fields which are not defined keep their default values. The `extra` object is merged
+/// into the generated conf-tool configuration.
+fn parse_nixos(value: &JsonValue, field: &str) -> Result<NixosConfig, PlanError> {
+    if !value.is_object() {
+        return Err(invalid(field, value));
+    }
+
+    let key = |key: &str| [field, ".", key].concat();
+    let defaults = NixosConfig::default();
+
+    Ok(NixosConfig {
+        template:   optional(&value["template"], &key("template"), string)?
+            .unwrap_or(defaults.template),
+        seed:       optional(&value["seed"], &key("seed"), path)?.unwrap_or(defaults.seed),
+        installer:  optional(&value["installer"], &key("installer"), string)?
+            .unwrap_or(defaults.installer),
+        extra_json: optional(&value["extra"], &key("extra"), |value, field| {
+            if value.is_object() {
+                Ok(value.dump())
+            } else {
+                Err(invalid(field, value))
+            }
+        })?,
+        closure:    optional(&value["closure"], &key("closure"), path)?,
+        flake:      optional(&value["flake"], &key("flake"), |value, field| {
+            value.as_bool().ok_or_else(|| invalid(field, value))
+        })?
+        .unwrap_or(defaults.flake),
+        wipe:       optional(&value["wipe"], &key("wipe"), |value, field| {
+            value.as_bool().ok_or_else(|| invalid(field, value))
+        })?
+        .unwrap_or(defaults.wipe),
+    })
+}
+
+fn nixos_to_json(nixos: &NixosConfig) -> JsonValue {
+    object! {
+        "template" => nixos.template.as_str(),
+        "seed" => path_to_json(&nixos.seed),
+        "installer" => nixos.installer.as_str(),
+        "extra" => nixos.extra_json.as_ref().and_then(|extra| json::parse(extra).ok()),
+        "closure" => nixos.closure.as_ref().map(|closure| path_to_json(closure)),
+        "flake" => nixos.flake,
+        "wipe" => nixos.wipe
+    }
+}
+
+/// Accounts are listed in the `users` array, which follow the single account of the
+/// original `user` field, if it is defined.
+fn parse_users(value: &JsonValue) -> Result<Vec<UserAccountCreate>, PlanError> {
+    let mut users = optional(&value["user"], "user", parse_user)?.into_iter().collect::<Vec<_>>();
+    users.extend(list(&value["users"], "users", parse_user)?);
+    Ok(users)
+}
+
+fn parse_user(value: &JsonValue, field: &str) -> Result<UserAccountCreate, PlanError> {
+    let key = |key: &str| [field, ".", key].concat();
+
+    let username = string(&value["username"], &key("username"))?;
+    if username.is_empty() {
+        return Err(invalid(&key("username"), &value["username"]));
+    }
+
+    let id = |value: &JsonValue, field: &str| value.as_u32().ok_or_else(|| invalid(field, value));
+
+    Ok(UserAccountCreate {
+        username,
+        realname: optional(&value["realname"], &key("realname"), string)?,
+        password: optional(&value["password"], &key("password"), string)?,
+        groups: list(&value["groups"], &key("groups"), string)?,
+        shell: optional(&value["shell"], &key("shell"), string)?,
+        uid: optional(&value["uid"], &key("uid"), id)?,
+        gid: optional(&value["gid"], &key("gid"), id)?,
+        ssh_keys: list(&value["ssh_keys"], &key("ssh_keys"), string)?,
+    })
+}
+
+fn user_to_json(user: &UserAccountCreate) -> JsonValue {
+    object! {
+        "username" => user.username.as_str(),
+        "realname" => user.realname.clone(),
+        "password" => user.password.clone(),
+        "groups" => user.groups.clone(),
+        "shell" => user.shell.clone(),
+        "uid" => user.uid,
+        "gid" => user.gid,
+        "ssh_keys" => user.ssh_keys.clone()
+    }
+}
+
+fn parse_disks_plan(value: &JsonValue) -> Result<DisksPlan, PlanError> {
+    Ok(DisksPlan {
+        disks:   list(&value["disks"], "disks", parse_disk)?,
+        decrypt: list(&value["decrypt"], "decrypt", |value, field| {
+            Ok(PlanDecrypt {
+                device:     path(&value["device"], &[field, ".device"].concat())?,
+                encryption: parse_encryption(value, field)?,
+            })
+        })?,
+        logical: list(&value["logical"], "logical", parse_logical)?,
+    })
+}
+
+/// Serializes the disks section of a plan, which is also a valid plan without a config.
+pub(crate) fn disks_plan_to_json(plan: &DisksPlan) -> JsonValue {
+    let mut value = object! { "version" => PLAN_VERSION };
+    value["disks"] = plan.disks.iter().map(disk_to_json).collect::<Vec<_>>().into();
+    value["decrypt"] = plan
+        .decrypt
+        .iter()
+        .map(|decrypt| {
+            let mut value = encryption_to_json(&decrypt.encryption);
+            value["device"] = path_to_json(&decrypt.device);
+            value
+        })
+        .collect::<Vec<_>>()
+        .into();
+    value["logical"] = plan.logical.iter().map(logical_to_json).collect::<Vec<_>>().into();
+    value
+}
+
+fn parse_disk(value: &JsonValue, field: &str) -> Result<PlanDisk, PlanError> {
+    let key = |key: &str| [field, ".", key].concat();
+
+    Ok(PlanDisk {
+        path:   path(&value["path"], &key("path"))?,
+        table:  optional(&value["table"], &key("table"), |value, field| match value.as_str() {
+            Some("gpt") => Ok(PartitionTable::Gpt),
+            Some("msdos") => Ok(PartitionTable::Msdos),
+            _ => Err(invalid(field, value)),
+        })?,
+        delete: list(&value["delete"], &key("delete"), partition_number)?,
+        moves:  list(&value["move"], &key("move"), |value, field| {
+            Ok(PlanMove {
+                partition: partition_number(&value["partition"], &[field, ".partition"].concat())?,
+                start:     optional(&value["start"], &[field, ".start"].concat(), sector)?,
+                end:       optional(&value["end"], &[field, ".end"].concat(), sector)?,
+            })
+        })?,
+        reuse:  list(&value["reuse"], &key("reuse"), |value, field| {
+            let key = |key: &str| [field, ".", key].concat();
+            Ok(PlanReuse {
+                partition:      partition_number(&value["partition"], &key("partition"))?,
+                format:         parse_format(value, field)?,
+                format_options: parse_format_options(value, field)?,
+                mount:          optional(&value["mount"], &key("mount"), mount)?,
+                flags:          optional(&value["flags"], &key("flags"), flags)?,
+                key_id:         optional(&value["keyid"], &key("keyid"), string)?,
+            })
+        })?,
+        new:    list(&value["new"], &key("new"), |value, field| {
+            let key = |key: &str| [field, ".", key].concat();
+            Ok(PlanPartition {
+                kind:           optional(&value["kind"], &key("kind"), kind)?
+                    .unwrap_or(PartitionType::Primary),
+                start:          sector(&value["start"], &key("start"))?,
+                end:            sector(&value["end"], &key("end"))?,
+                format:         parse_format(value, field)?,
+                format_options: parse_format_options(value, field)?,
+                name:           optional(&value["name"], &key("name"), string)?,
+                mount:          optional(&value["mount"], &key("mount"), mount)?,
+                flags:          optional(&value["flags"], &key("flags"), flags)?
+                    .unwrap_or_default(),
+                key_id:         optional(&value["keyid"], &key("keyid"), string)?,
+            })
+        })?,
+    })
+}
+
+fn disk_to_json(disk: &PlanDisk) -> JsonValue {
+    let mut value = object! { "path" => path_to_json(&disk.path) };
+
+    if let Some(table) = disk.table {
+        value["table"] = table_to_json(table);
+    }
+
+    value["delete"] = disk.delete.clone().into();
+    value["move"] = disk
+        .moves
+        .iter()
+        .map(|moved| {
+            object! {
+                "partition" => moved.partition,
+                "start" => moved.start.map(sector_to_json),
+                "end" => moved.end.map(sector_to_json)
+            }
+        })
+        .collect::<Vec<_>>()
+        .into();
+
+    value["reuse"] = disk
+        .reuse
+        .iter()
+        .map(|reuse| {
+            let mut value = object! {
+                "partition" => reuse.partition,
+                "mount" => reuse.mount.as_ref().map(|path| path_to_json(path)),
+                "flags" => reuse.flags.as_ref().map(|flags| flags_to_json(flags)),
+                "keyid" => reuse.key_id.clone(),
+                "format_options" => format_options_to_json(&reuse.format_options)
+            };
+            format_to_json(&mut value, reuse.format.as_ref());
+            value
+        })
+        .collect::<Vec<_>>()
+        .into();
+
+    value["new"] = disk
+        .new
+        .iter()
+        .map(|part| {
+            let mut value = object! {
+                "kind" => kind_to_json(part.kind),
+                "start" => sector_to_json(part.start),
+                "end" => sector_to_json(part.end),
+                "name" => part.name.clone(),
+                "mount" => part.mount.as_ref().map(|path| path_to_json(path)),
+                "flags" => flags_to_json(&part.flags),
+                "keyid" => part.key_id.clone(),
+                "format_options" => format_options_to_json(&part.format_options)
+            };
+            format_to_json(&mut value, part.format.as_ref());
+            value
+        })
+        .collect::<Vec<_>>()
+        .into();
+
+    value
+}
+
+fn parse_logical(value: &JsonValue, field: &str) -> Result<PlanLogical, PlanError> {
+    let key = |key: &str| [field, ".", key].concat();
+
+    Ok(PlanLogical {
+        group:      string(&value["group"], &key("group"))?,
+        remove_all: optional(&value["remove_all"], &key("remove_all"), |value, field| {
+            value.as_bool().ok_or_else(|| invalid(field, value))
+        })?
+        .unwrap_or(false),
+        remove:     list(&value["remove"], &key("remove"), string)?,
+        modify:     list(&value["modify"], &key("modify"), |value, field| {
+            let key = |key: &str| [field, ".", key].concat();
+            Ok(PlanVolumeModify {
+                volume: string(&value["volume"], &key("volume"))?,
+                fs:     optional(&value["fs"], &key("fs"), file_system)?,
+                mount:  optional(&value["mount"], &key("mount"), mount)?,
+                size:   optional(&value["size"], &key("size"), sector)?,
+            })
+        })?,
+        thin_pools: list(&value["thin_pools"], &key("thin_pools"), |value, field| {
+            Ok(PlanThinPool {
+                name: string(&value["name"], &[field, ".name"].concat())?,
+                size: sector(&value["size"], &[field, ".size"].concat())?,
+            })
+        })?,
+        new:        list(&value["new"], &key("new"), |value, field| {
+            let key = |key: &str| [field, ".", key].concat();
+            Ok(PlanVolume {
+                name:           string(&value["name"], &key("name"))?,
+                size:           sector(&value["size"], &key("size"))?,
+                pool:           optional(&value["pool"], &key("pool"), string)?,
+                fs:             optional(&value["fs"], &key("fs"), file_system)?,
+                format_options: parse_format_options(value, field)?,
+                mount:          optional(&value["mount"], &key("mount"), mount)?,
+                flags:          optional(&value["flags"], &key("flags"), flags)?
+                    .unwrap_or_default(),
+            })
+        })?,
+        remaining:  optional(&value["remaining"], &key("remaining"), |value, field| {
+            match value.as_str() {
+                Some("fill_last") => Ok(RemainingSpace::FillLast),
+                Some("unallocated") => Ok(RemainingSpace::Unallocated),
+                _ => Err(invalid(field, value)),
+            }
+        })?
+        .unwrap_or_default(),
+    })
+}
+
+fn logical_to_json(device: &PlanLogical) -> JsonValue {
+    let mut value = object! {
+        "group" => device.group.as_str(),
+        "remove_all" => device.remove_all,
+        "remaining" => match device.remaining {
+            RemainingSpace::FillLast => "fill_last",
+            RemainingSpace::Unallocated => "unallocated",
+        }
+    };
+
+    value["remove"] = device.remove.clone().into();
+    value["modify"] = device
+        .modify
+        .iter()
+        .map(|modify| {
+            object! {
+                "volume" => modify.volume.as_str(),
+                "fs" => modify.fs.map(file_system_to_json),
+                "mount" => modify.mount.as_ref().map(|path| path_to_json(path)),
+                "size" => modify.size.map(sector_to_json)
+            }
+        })
+        .collect::<Vec<_>>()
+        .into();
+
+    value["thin_pools"] = device
+        .thin_pools
+        .iter()
+        .map(|pool| {
+            object! {
+                "name" => pool.name.as_str(),
+                "size" => sector_to_json(pool.size)
+            }
+        })
+        .collect::<Vec<_>>()
+        .into();
+
+    value["new"] = device
+        .new
+        .iter()
+        .map(|volume| {
+            object! {
+                "name" => volume.name.as_str(),
+                "size" => sector_to_json(volume.size),
+                "pool" => volume.pool.clone(),
+                "fs" => volume.fs.map(file_system_to_json),
+                "format_options" => format_options_to_json(&volume.format_options),
+                "mount" => volume.mount.as_ref().map(|path| path_to_json(path)),
+                "flags" => flags_to_json(&volume.flags)
+            }
+        })
+        .collect::<Vec<_>>()
+        .into();
+
+    value
+}
+
+/// A partition may either be formatted with the `fs` field, or made into a LVM physical
+/// volume with the `lvm` field.
+fn parse_format(value: &JsonValue, field: &str) -> Result<Option<PlanFileSystem>, PlanError> {
+    let lvm = &value["lvm"];
+    if lvm.is_null() {
+        let fs = [field, ".fs"].concat();
+        return optional(&value["fs"], &fs, file_system).map(|fs| fs.map(PlanFileSystem::Fs));
+    }
+
+    let lvm_field = [field, ".lvm"].concat();
+    Ok(Some(PlanFileSystem::Lvm {
+        group:      string(&lvm["group"], &[&lvm_field, ".group"].concat())?,
+        encryption: optional(
+            &lvm["encryption"],
+            &[&lvm_field, ".encryption"].concat(),
+            parse_encryption,
+        )?,
+    }))
+}
+
+fn format_to_json(value: &mut JsonValue, format: Option<&PlanFileSystem>) {
+    match format {
+        Some(PlanFileSystem::Fs(fs)) => value["fs"] = file_system_to_json(*fs),
+        Some(PlanFileSystem::Lvm { group, encryption }) => {
+            value["lvm"] = object! {
+                "group" => group.as_str(),
+                "encryption" => encryption.as_ref().map(encryption_to_json)
+            };
+        }
+        None => (),
+    }
+}
+
+/// The options that a file system is created with, which are validated against the file
+/// system when the plan is applied.
+fn parse_format_options(value: &JsonValue, field: &str) -> Result<FormatOptions, PlanError> {
+    let field = [field, ".format_options"].concat();
+    let value = &value["format_options"];
+    if value.is_null() {
+        return Ok(FormatOptions::default());
+    } else if !value.is_object() {
+        return Err(invalid(&field, value));
+    }
+
+    let key = |key: &str| [&field, ".", key].concat();
+
+    Ok(FormatOptions {
+        label:          optional(&value["label"], &key("label"), string)?,
+        uuid:           optional(&value["uuid"], &key("uuid"), string)?,
+        ext_features:   list(&value["ext_features"], &key("ext_features"), string)?,
+        btrfs_data:     optional(&value["btrfs_data"], &key("btrfs_data"), btrfs_profile)?,
+        btrfs_metadata: optional(&value["btrfs_metadata"], &key("btrfs_metadata"), btrfs_profile)?,
+        xfs_reflink:    optional(&value["xfs_reflink"], &key("xfs_reflink"), |value, field| {
+            value.as_bool().ok_or_else(|| invalid(field, value))
+        })?,
+    })
+}
+
+/// Default options are omitted.
+fn format_options_to_json(options: &FormatOptions) -> JsonValue {
+    if *options == FormatOptions::default() {
+        return JsonValue::Null;
+    }
+
+    object! {
+        "label" => options.label.clone(),
+        "uuid" => options.uuid.clone(),
+        "ext_features" => options.ext_features.clone(),
+        "btrfs_data" => options.btrfs_data.map(|profile| profile.to_string()),
+        "btrfs_metadata" => options.btrfs_metadata.map(|profile| profile.to_string()),
+        "xfs_reflink" => options.xfs_reflink
+    }
+}
+
+fn btrfs_profile(value: &JsonValue, field: &str) -> Result<BtrfsProfile, PlanError> {
+    value.as_str().and_then(|name| name.parse().ok()).ok_or_else(|| invalid(field, value))
+}
+
+fn parse_encryption(value: &JsonValue, field: &str) -> Result<LvmEncryption, PlanError> {
+    let key = |key: &str| [field, ".", key].concat();
+
+    let physical_volume = string(&value["physical_volume"], &key("physical_volume"))?;
+    let password = optional(&value["password"], &key("password"), string)?;
+    let keydata = optional(&value["keyfile"], &key("keyfile"), string)?;
+
+    if password.is_none() && keydata.is_none() {
+        return Err(PlanError::MissingField { field: key("password") });
+    }
+
+    Ok(LvmEncryption::new(physical_volume, password, keydata))
+}
+
+fn encryption_to_json(encryption: &LvmEncryption) -> JsonValue {
+    object! {
+        "physical_volume" => encryption.physical_volume.as_str(),
+        "password" => encryption.password.clone(),
+        "keyfile" => encryption.keydata.as_ref().map(|&(ref id, _)| id.clone())
+    }
+}
+
+/// Serializes a report of the changes that will be made to each device.
+pub(crate) fn report_to_json(report: &ChangeReport) -> JsonValue {
+    report
+        .devices
+        .iter()
+        .map(|device| {
+            object! {
+                "device" => path_to_json(&device.device),
+                "changes" => device.changes.iter().map(change_to_json).collect::<Vec<_>>()
+            }
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn change_to_json(change: &Change) -> JsonValue {
+    let mut value = match *change {
+        Change::Mklabel { table } => object! {
+            "action" => "mklabel",
+            "table" => table_to_json(table)
+        },
+        Change::Remove { ref partition, number, file_system } => object! {
+            "action" => "remove",
+            "partition" => path_to_json(partition),
+            "number" => number,
+            "fs" => file_system.map(file_system_to_json)
+        },
+        Change::Format { ref partition, number, old, new, ref options } => object! {
+            "action" => "format",
+            "partition" => path_to_json(partition),
+            "number" => number,
+            "old_fs" => old.map(file_system_to_json),
+            "fs" => new.map(file_system_to_json),
+            "format_options" => format_options_to_json(options)
+        },
+        Change::Resize { ref partition, number, old, new } => object! {
+            "action" => "resize",
+            "partition" => path_to_json(partition),
+            "number" => number,
+            "old_start" => old.0,
+            "old_end" => old.1,
+            "start" => new.0,
+            "end" => new.1
+        },
+        Change::Modify { ref partition, number, ref flags, ref label } => object! {
+            "action" => "modify",
+            "partition" => path_to_json(partition),
+            "number" => number,
+            "flags" => flags_to_json(flags),
+            "name" => label.clone()
+        },
+        Change::Create { start, end, kind, file_system, ref options, ref label, ref flags } => {
+            object! {
+                "action" => "create",
+                "kind" => kind_to_json(kind),
+                "start" => start,
+                "end" => end,
+                "fs" => file_system.map(file_system_to_json),
+                "format_options" => format_options_to_json(options),
+                "name" => label.clone(),
+                "flags" => flags_to_json(flags)
+            }
+        }
+        Change::Encrypt { ref partition, ref physical_volume } => object! {
+            "action" => "encrypt",
+            "partition" => partition.as_ref().map(|path| path_to_json(path)),
+            "physical_volume" => physical_volume.as_str()
+        },
+        Change::CreateVolumeGroup { ref group } => object! {
+            "action" => "create_group",
+            "group" => group.as_str()
+        },
+        Change::CreateVolume { ref group, ref volume, sectors, file_system, ref options } => {
+            object! {
+                "action" => "create_volume",
+                "group" => group.as_str(),
+                "volume" => volume.as_str(),
+                "sectors" => sectors,
+                "fs" => file_system.map(file_system_to_json),
+                "format_options" => format_options_to_json(options)
+            }
+        }
+        Change::ResizeVolume { ref group, ref volume, old, new } => object! {
+            "action" => "resize_volume",
+            "group" => group.as_str(),
+            "volume" => volume.as_str(),
+            "old_sectors" => old,
+            "sectors" => new
+        },
+        Change::CreateThinPool { ref group, ref pool, sectors } => object! {
+            "action" => "create_thin_pool",
+            "group" => group.as_str(),
+            "pool" => pool.as_str(),
+            "sectors" => sectors
+        },
+        Change::RemoveVolume { ref group, ref volume } => object! {
+            "action" => "remove_volume",
+            "group" => group.as_str(),
+            "volume" => volume.as_str()
+        },
+        Change::FormatVolume { ref group, ref volume, file_system } => object! {
+            "action" => "format_volume",
+            "group" => group.as_str(),
+            "volume" => volume.as_str(),
+            "fs" => file_system.map(file_system_to_json)
+        },
+    };
+
+    value["destructive"] = change.is_destructive().into();
+    value
+}
+
+fn invalid(field: &str, value: &JsonValue) -> PlanError {
+    if value.is_null() {
+        PlanError::MissingField { field: field.into() }
+    } else {
+        PlanError::InvalidField { field: field.into(), value: value.dump() }
+    }
+}
+
+/// Parses a field which may be omitted or null.
+fn optional<T, F>(value: &JsonValue, field: &str, parse: F) -> Result<Option<T>, PlanError>
+where
+    F: FnOnce(&JsonValue, &str) -> Result<T, PlanError>,
+{
+    if value.is_null() {
+        Ok(None)
+    } else {
+        parse(value, field).map(Some)
+    }
+}
+
+/// Parses each member of an array field, which is empty when omitted.
+fn list<T, F>(value: &JsonValue, field: &str, mut parse: F) -> Result<Vec<T>, PlanError>
+where
+    F: FnMut(&JsonValue, &str) -> Result<T, PlanError>,
+{
+    if value.is_null() {
+        return Ok(Vec::new());
+    } else if !value.is_array() {
+        return Err(invalid(field, value));
+    }
+
+    value
+        .members()
+        .enumerate()
+        .map(|(id, member)| parse(member, &format!("{}[{}]", field, id)))
+        .collect()
+}
+
+fn string(value: &JsonValue, field: &str) -> Result<String, PlanError> {
+    value.as_str().map(String::from).ok_or_else(|| invalid(field, value))
+}
+
+fn path(value: &JsonValue, field: &str) -> Result<PathBuf, PlanError> {
+    string(value, field).map(PathBuf::from)
+}
+
+fn distribution(value: &JsonValue, field: &str) -> Result<Distribution, PlanError> {
+    value.as_str().and_then(|name| name.parse().ok()).ok_or_else(|| invalid(field, value))
+}
+
+fn boot_manager(value: &JsonValue, field: &str) -> Result<BootManager, PlanError> {
+    value.as_str().and_then(|name| name.parse().ok()).ok_or_else(|| invalid(field, value))
+}
+
+fn table_to_json(table: PartitionTable) -> JsonValue {
+    match table {
+        PartitionTable::Gpt => "gpt",
+        PartitionTable::Msdos => "msdos",
+    }
+    .into()
+}
+
+fn kind(value: &JsonValue, field: &str) -> Result<PartitionType, PlanError> {
+    match value.as_str() {
+        Some("primary") => Ok(PartitionType::Primary),
+        Some("logical") => Ok(PartitionType::Logical),
+        Some("extended") => Ok(PartitionType::Extended),
+        _ => Err(invalid(field, value)),
+    }
+}
+
+fn kind_to_json(kind: PartitionType) -> JsonValue {
+    match kind {
+        PartitionType::Primary => "primary",
+        PartitionType::Logical => "logical",
+        PartitionType::Extended => "extended",
+    }
+    .into()
+}
+
+fn path_to_json(path: &Path) -> JsonValue { path.to_string_lossy().into_owned().into() }
+
+fn mount(value: &JsonValue, field: &str) -> Result<PathBuf, PlanError> {
+    match value.as_str() {
+        Some(target) if target.starts_with('/') => Ok(PathBuf::from(target)),
+        _ => Err(invalid(field, value)),
+    }
+}
+
+fn partition_number(value: &JsonValue, field: &str) -> Result<i32, PlanError> {
+    value.as_i32().filter(|&number| number > 0).ok_or_else(|| invalid(field, value))
+}
+
+fn sector(value: &JsonValue, field: &str) -> Result<Sector, PlanError> {
+    match value.as_u64() {
+        Some(sector) => Ok(Sector::Unit(sector)),
+        None => value
+            .as_str()
+            .and_then(|sector| sector.parse::<Sector>().ok())
+            .ok_or_else(|| invalid(field, value)),
+    }
+}
+
+fn sector_to_json(sector: Sector) -> JsonValue {
+    match sector {
+        Sector::Unit(sector) => sector.into(),
+        Sector::Start => "start".into(),
+        Sector::End => "end".into(),
+        Sector::UnitFromEnd(sector) => format!("-{}", sector).into(),
+        Sector::Megabyte(mb) => format!("{}M", mb).into(),
+        Sector::MegabyteFromEnd(mb) => format!("-{}M", mb).into(),
+        Sector::Percent(percent) => format!("{}%", percent).into(),
+    }
+}
+
+fn file_system(value: &JsonValue, field: &str) -> Result<FileSystem, PlanError> {
+    value
+        .as_str()
+        .and_then(|fs| fs.parse::<FileSystem>().ok())
+        .filter(|&fs| fs != FileSystem::Lvm && fs != FileSystem::Luks)
+        .ok_or_else(|| invalid(field, value))
+}
+
+fn file_system_to_json(fs: FileSystem) -> JsonValue {
+    let fs: &str = fs.into();
+    fs.into()
+}
+
+fn flags(value: &JsonValue, field: &str) -> Result<Vec<PartitionFlag>, PlanError> {
+    list(value, field, |value, field| {
+        value
+            .as_str()
+            .and_then(|flag| FLAGS.iter().find(|&&(name, _)| name == flag))
+            .map(|&(_, flag)| flag)
+            .ok_or_else(|| invalid(field, value))
+    })
+}
+
+fn flags_to_json(flags: &[PartitionFlag]) -> JsonValue {
+    flags
+        .iter()
+        .filter_map(|flag| FLAGS.iter().find(|&&(_, f)| f == *flag).map(|&(name, _)| name))
+        .collect::<Vec<_>>()
+        .into()
+}
@@ -0,0 +1,427 @@
+//! Declarative install plans, which describe a disks configuration together with the
+//! installer's `Config` and the user accounts to create.
+//!
+//! Plans are stored as versioned JSON documents:
+//!
+//! ```json
+//! {
+//!     "version": 1,
+//!     "config": {
+//!         "hostname": "pop-os",
+//!         "keyboard_layout": "us",
+//!         "lang": "en_US.UTF-8",
+//!         "flags": 0
+//!     },
+//!     "users": [
+//!         { "username": "pop", "realname": "Pop", "password": "secret",
+//!           "groups": ["adm", "sudo"], "shell": "/bin/bash", "uid": 1000,
+//!           "ssh_keys": ["ssh-ed25519 AAAA... pop@example"] }
+//!     ],
+//!     "disks": [
+//!         {
+//!             "path": "/dev/sda",
+//!             "table": "gpt",
+//!             "new": [
+//!                 { "start": "start", "end": "512M", "fs": "fat32",
+//!                   "mount": "/boot/efi", "flags": ["esp"] },
+//!                 { "start": "512M", "end": "end",
+//!                   "lvm": { "group": "data", "encryption": {
+//!                       "physical_volume": "cryptdata", "password": "secret" } } }
+//!             ]
+//!         }
+//!     ],
+//!     "logical": [
+//!         { "group": "data", "new": [
+//!             { "name": "root", "size": "100%", "fs": "ext4", "mount": "/" } ] }
+//!     ]
+//! }
+//! ```
+//!
+//! Each disk may also list partitions to `delete` (by number), to `move` (with a new
+//! `start` and / or `end`), and to `reuse` (with an optional new `fs`, `lvm`, `mount`,
+//! `flags`, and `keyid`). Formatted partitions and new logical volumes may also be given
+//! `format_options`, such as `{ "label": "root", "ext_features": ["^has_journal"] }`, which
+//! accepts a `label`, `uuid`, `ext_features`, `btrfs_data`, `btrfs_metadata`, and
+//! `xfs_reflink`. Existing LUKS partitions are opened with `decrypt` entries, and
+//! logical devices support `remove`, `remove_all`, and `modify` (which may also resize a
+//! volume to a new `size`) alongside `new`. A logical device may also create `thin_pools`,
+//! each with a `name` and `size`, which new volumes are provisioned from by naming their
+//! `pool`, and its `remaining` space may be left `"unallocated"` rather than given to the
+//! last volume with `"fill_last"`.
+//!
+//! Sectors may be written as an absolute sector number, or as any string which parses
+//! as a `Sector`, such as `"start"`, `"end"`, `"512M"`, `"-4096M"`, or `"50%"`.
+
+mod apply;
+mod changes;
+mod export;
+mod format;
+
+pub use self::changes::{Change, ChangeReport, DeviceChanges};
+
+use crate::{
+    disks::{
+        DecryptionError, DiskError, FormatOptions, LvmEncryption, PartitionFlag, PartitionTable,
+        RemainingSpace, Sector,
+    },
+    hostname, BootManager, Config, Distribution, NixosConfig, UserAccountCreate,
+};
+use disk_types::{FileSystem, PartitionType};
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// The version of the plan format that is read and written by this library.
+pub const PLAN_VERSION: u64 = 1;
+
+#[derive(Debug, Fail)]
+pub enum PlanError {
+    #[fail(display = "failed to read install plan at {:?}: {}", path, why)]
+    Read { path: PathBuf, why: io::Error },
+    #[fail(display = "failed to write install plan to {:?}: {}", path, why)]
+    Write { path: PathBuf, why: io::Error },
+    #[fail(display = "install plan is not valid JSON: {}", why)]
+    Parse { why: String },
+    #[fail(display = "install plan version {} is not supported", version)]
+    UnsupportedVersion { version: u64 },
+    #[fail(display = "install plan is missing the '{}' field", field)]
+    MissingField { field: String },
+    #[fail(display = "install plan field '{}' has an invalid value: {}", field, value)]
+    InvalidField { field: String, value: String },
+    #[fail(display = "hostname '{}' is not valid", hostname)]
+    InvalidHostname { hostname: String },
+    #[fail(display = "mount target {:?} is assigned to more than one partition", target)]
+    DuplicateTarget { target: PathBuf },
+    #[fail(display = "disk at {:?} was not found in the plan's disks", path)]
+    DiskNotFound { path: PathBuf },
+    #[fail(display = "partition {} was not found on {:?}", partition, disk)]
+    PartitionNotFound { disk: PathBuf, partition: i32 },
+    #[fail(display = "no logical device named '{}' found", group)]
+    LogicalDeviceNotFound { group: String },
+    #[fail(display = "'{}' was not found on '{}'", volume, group)]
+    LogicalPartitionNotFound { group: String, volume: String },
+    #[fail(display = "disk error: {}", why)]
+    Disk { why: DiskError },
+    #[fail(display = "failed to decrypt {:?}: {}", device, why)]
+    Decrypt { device: PathBuf, why: DecryptionError },
+}
+
+impl From<DiskError> for PlanError {
+    fn from(why: DiskError) -> PlanError { PlanError::Disk { why } }
+}
+
+impl From<PlanError> for io::Error {
+    fn from(why: PlanError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{}", why))
+    }
+}
+
+/// How a new or reused partition should be formatted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlanFileSystem {
+    /// Format the partition with a standard file system.
+    Fs(FileSystem),
+    /// Make the partition a physical volume of a LVM volume group, optionally within LUKS.
+    Lvm { group: String, encryption: Option<LvmEncryption> },
+}
+
+/// Moves and / or resizes an existing partition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanMove {
+    pub partition: i32,
+    pub start:     Option<Sector>,
+    pub end:       Option<Sector>,
+}
+
+/// Reuses an existing partition, optionally reformatting it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanReuse {
+    pub partition:      i32,
+    pub format:         Option<PlanFileSystem>,
+    pub format_options: FormatOptions,
+    pub mount:          Option<PathBuf>,
+    pub flags:          Option<Vec<PartitionFlag>>,
+    pub key_id:         Option<String>,
+}
+
+/// Creates a new partition on the disk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanPartition {
+    pub kind:           PartitionType,
+    pub start:          Sector,
+    pub end:            Sector,
+    pub format:         Option<PlanFileSystem>,
+    pub format_options: FormatOptions,
+    pub name:           Option<String>,
+    pub mount:          Option<PathBuf>,
+    pub flags:          Vec<PartitionFlag>,
+    pub key_id:         Option<String>,
+}
+
+/// All of the changes to apply to a physical disk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanDisk {
+    pub path:   PathBuf,
+    pub table:  Option<PartitionTable>,
+    pub delete: Vec<i32>,
+    pub moves:  Vec<PlanMove>,
+    pub reuse:  Vec<PlanReuse>,
+    pub new:    Vec<PlanPartition>,
+}
+
+/// Opens an existing LUKS partition, so that its logical volumes may be configured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanDecrypt {
+    pub device:     PathBuf,
+    pub encryption: LvmEncryption,
+}
+
+/// Modifies and / or resizes an existing logical volume.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanVolumeModify {
+    pub volume: String,
+    pub fs:     Option<FileSystem>,
+    pub mount:  Option<PathBuf>,
+    pub size:   Option<Sector>,
+}
+
+/// Creates a thin pool, which thin volumes are provisioned from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanThinPool {
+    pub name: String,
+    pub size: Sector,
+}
+
+/// Creates a new logical volume, which is a thin volume if it names the pool to be
+/// provisioned from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanVolume {
+    pub name:           String,
+    pub size:           Sector,
+    pub pool:           Option<String>,
+    pub fs:             Option<FileSystem>,
+    pub format_options: FormatOptions,
+    pub mount:          Option<PathBuf>,
+    pub flags:          Vec<PartitionFlag>,
+}
+
+/// All of the changes to apply to a logical device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanLogical {
+    pub group:      String,
+    pub remove_all: bool,
+    pub remove:     Vec<String>,
+    pub modify:     Vec<PlanVolumeModify>,
+    pub thin_pools: Vec<PlanThinPool>,
+    pub new:        Vec<PlanVolume>,
+    pub remaining:  RemainingSpace,
+}
+
+/// The disks configuration section of an install plan.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DisksPlan {
+    pub disks:   Vec<PlanDisk>,
+    pub decrypt: Vec<PlanDecrypt>,
+    pub logical: Vec<PlanLogical>,
+}
+
+/// A complete description of an installation, which may be handed to `Installer::install_plan`.
+pub struct InstallPlan {
+    pub disks:  DisksPlan,
+    pub config: Config,
+    pub users:  Vec<UserAccountCreate>,
+}
+
+impl InstallPlan {
+    /// Reads and validates the plan stored at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<InstallPlan, PlanError> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|why| PlanError::Read { path: path.to_path_buf(), why })?;
+        InstallPlan::parse(&data)
+    }
+
+    /// Parses and validates a plan from its JSON representation.
+    pub fn parse(data: &str) -> Result<InstallPlan, PlanError> {
+        let value = json::parse(data).map_err(|why| PlanError::Parse { why: why.to_string() })?;
+        let plan = format::parse_plan(&value)?;
+        plan.validate()?;
+        Ok(plan)
+    }
+
+    /// Checks the plan for errors which can be detected without probing any devices.
+    pub fn validate(&self) -> Result<(), PlanError> {
+        if !hostname::is_valid(&self.config.hostname) {
+            return Err(PlanError::InvalidHostname { hostname: self.config.hostname.clone() });
+        }
+
+        let mut targets = HashSet::new();
+        for target in self.disks.targets() {
+            if !targets.insert(target) {
+                return Err(PlanError::DuplicateTarget { target: target.to_path_buf() });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the plan into its JSON representation.
+    pub fn to_json(&self) -> String { format::plan_to_json(self).pretty(4) }
+
+    /// Writes the plan to the given path. Plans contain passwords, so the file may only be
+    /// read by its owner.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), PlanError> {
+        let path = path.as_ref();
+        write_private(path, &self.to_json())
+            .map_err(|why| PlanError::Write { path: path.to_path_buf(), why })
+    }
+}
+
+/// Writes a file which only its owner may read or write.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut file =
+        OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+
+    // The mode is only applied to files that did not already exist.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+impl DisksPlan {
+    /// Every mount target that the plan assigns.
+    fn targets<'a>(&'a self) -> impl Iterator<Item = &'a Path> + 'a {
+        let physical = self.disks.iter().flat_map(|disk| {
+            disk.reuse
+                .iter()
+                .filter_map(|part| part.mount.as_ref())
+                .chain(disk.new.iter().filter_map(|part| part.mount.as_ref()))
+        });
+
+        let logical = self.logical.iter().flat_map(|device| {
+            device
+                .modify
+                .iter()
+                .filter_map(|part| part.mount.as_ref())
+                .chain(device.new.iter().filter_map(|part| part.mount.as_ref()))
+        });
+
+        physical.chain(logical).map(PathBuf::as_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disks::BtrfsProfile;
+
+    const PLAN: &str = r#"{
+        "version": 1,
+        "config": { "hostname": "pop-os", "lang": "en_US.UTF-8" },
+        "user": { "username": "pop", "password": "secret" },
+        "users": [
+            { "username": "admin", "groups": ["wheel", "networkmanager"], "shell": "/bin/zsh",
+              "uid": 1001, "gid": 1001, "ssh_keys": ["ssh-ed25519 AAAA admin@example"] }
+        ],
+        "disks": [
+            {
+                "path": "/dev/sda",
+                "table": "gpt",
+                "new": [
+                    { "start": 2048, "end": "512M", "fs": "fat32", "mount": "/boot/efi",
+                      "flags": ["esp"], "format_options": { "label": "EFI" } },
+                    { "start": "512M", "end": "end", "lvm": { "group": "data",
+                      "encryption": { "physical_volume": "cryptdata", "password": "pass" } } }
+                ]
+            }
+        ],
+        "logical": [
+            { "group": "data", "thin_pools": [ { "name": "pool", "size": "40%" } ], "new": [
+                { "name": "root", "size": "50%", "fs": "btrfs", "mount": "/",
+                  "format_options": { "btrfs_data": "single", "btrfs_metadata": "dup" } },
+                { "name": "home", "size": "25%", "pool": "pool", "fs": "ext4",
+                  "mount": "/home" } ],
+              "remaining": "unallocated" }
+        ]
+    }"#;
+
+    #[test]
+    fn plan_round_trip() {
+        let plan = InstallPlan::parse(PLAN).unwrap();
+        assert_eq!(plan.config.keyboard_layout, "us");
+        assert_eq!(plan.users[0].username, "pop");
+        assert_eq!(plan.users[1].groups, vec!["wheel".to_owned(), "networkmanager".to_owned()]);
+        assert_eq!(plan.users[1].uid, Some(1001));
+
+        let disk = &plan.disks.disks[0];
+        assert_eq!(disk.table, Some(PartitionTable::Gpt));
+        assert_eq!(disk.new[0].start, Sector::Unit(2048));
+        assert_eq!(disk.new[0].flags, vec![PartitionFlag::PED_PARTITION_ESP]);
+        assert_eq!(disk.new[0].format_options, FormatOptions::new().label("EFI"));
+        assert_eq!(disk.new[1].format_options, FormatOptions::default());
+
+        let logical = &plan.disks.logical[0];
+        assert_eq!(
+            logical.new[0].format_options,
+            FormatOptions::new().btrfs_profiles(BtrfsProfile::Single, BtrfsProfile::Dup)
+        );
+        assert_eq!(
+            logical.thin_pools,
+            vec![PlanThinPool { name: "pool".into(), size: Sector::Percent(40) }]
+        );
+        assert_eq!(logical.new[1].pool, Some("pool".into()));
+        assert_eq!(logical.remaining, RemainingSpace::Unallocated);
+        assert_eq!(
+            disk.new[1].format,
+            Some(PlanFileSystem::Lvm {
+                group:      "data".into(),
+                encryption: Some(LvmEncryption::new("cryptdata".into(), Some("pass".into()), None)),
+            })
+        );
+
+        let reparsed = InstallPlan::parse(&plan.to_json()).unwrap();
+        assert_eq!(plan.disks, reparsed.disks);
+        assert_eq!(plan.config.hostname, reparsed.config.hostname);
+        assert_eq!(plan.users, reparsed.users);
+    }
+
+    #[test]
+    fn plan_errors() {
+        match InstallPlan::parse(&PLAN.replace("\"version\": 1", "\"version\": 2")) {
+            Err(PlanError::UnsupportedVersion { version: 2 }) => (),
+            _ => panic!("expected unsupported version"),
+        }
+
+        match InstallPlan::parse(&PLAN.replace("\"end\": \"end\"", "\"end\": \"nowhere\"")) {
+            Err(PlanError::InvalidField { ref field, .. }) if field == "disks[0].new[1].end" => (),
+            _ => panic!("expected invalid sector"),
+        }
+
+        match InstallPlan::parse(&PLAN.replace("\"dup\"", "\"raid9\"")) {
+            Err(PlanError::InvalidField { ref field, .. })
+                if field == "logical[0].new[0].format_options.btrfs_metadata" => {}
+            _ => panic!("expected invalid btrfs profile"),
+        }
+
+        match InstallPlan::parse(&PLAN.replace("\"/boot/efi\"", "\"/\"")) {
+            Err(PlanError::DuplicateTarget { .. }) => (),
+            _ => panic!("expected duplicate target"),
+        }
+    }
+
+    #[test]
+    fn plan_file_is_private() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempdir::TempDir::new("distinst").unwrap();
+        let path = dir.path().join("plan.json");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        InstallPlan::parse(PLAN).unwrap().write(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+    }
+}